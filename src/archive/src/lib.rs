@@ -0,0 +1,513 @@
+// The crate is named after its dfx canister
+#![allow(non_snake_case)]
+
+use candid::{CandidType, Decode, Deserialize, Encode, Nat, Principal};
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    storable::{Bound, Storable},
+    DefaultMemoryImpl, StableBTreeMap, StableCell,
+};
+use std::cell::RefCell;
+
+// Archive canister for the HarvestX backend. Holds ICRC-3 blocks that the
+// backend has moved out of its own stable memory and serves them back to
+// explorers through `icrc3_get_blocks`, as well as finalized transactions
+// and investment requests that the backend looks up on behalf of its users.
+
+// Memory management
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(0);
+const BLOCKS_MEMORY_ID: MemoryId = MemoryId::new(1);
+const RECORDS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const PARTY_INDEX_MEMORY_ID: MemoryId = MemoryId::new(3);
+
+const MAX_BLOCKS_PER_RESPONSE: u64 = 1_000;
+
+// Types shared with the backend over Candid
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum Icrc3Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(candid::Int),
+    Array(Vec<Icrc3Value>),
+    Map(Vec<(String, Icrc3Value)>),
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Icrc3Value,
+}
+
+candid::define_function!(pub GetBlocksFn : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksFn,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum RecordKind {
+    Transaction,
+    Request,
+}
+
+/// A backend record moved to the archive. The archive does not interpret
+/// `payload` (the Candid-encoded record); it only indexes it by `parties`.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ArchivedRecord {
+    pub kind: RecordKind,
+    pub id: String,
+    pub parties: Vec<Principal>,
+    pub created_at: u64,
+    pub archived_at: u64,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ArchiveInitArgs {
+    /// The backend canister allowed to append to this archive.
+    pub parent: Principal,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct ArchiveConfig {
+    parent: Option<Principal>,
+    /// Index of the first block held here; blocks are stored contiguously.
+    first_index: u64,
+}
+
+impl Storable for ArchiveConfig {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 128,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for ArchivedRecord {
+    const BOUND: Bound = Bound::Unbounded;
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Icrc3Value {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 4096,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+
+    static CONFIG: RefCell<StableCell<ArchiveConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG_MEMORY_ID)),
+            ArchiveConfig { parent: None, first_index: 0 },
+        )
+        .expect("failed to initialize archive config")
+    );
+
+    static BLOCKS: RefCell<StableBTreeMap<u64, Icrc3Value, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(BLOCKS_MEMORY_ID)))
+    );
+
+    // Keyed by `record_key`
+    static RECORDS: RefCell<StableBTreeMap<String, ArchivedRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(RECORDS_MEMORY_ID)))
+    );
+
+    // Keyed by `<party>|<record_key>`
+    static PARTY_INDEX: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(PARTY_INDEX_MEMORY_ID)))
+    );
+}
+
+fn get_config() -> ArchiveConfig {
+    CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn next_index() -> u64 {
+    let config = get_config();
+    config.first_index + BLOCKS.with(|blocks| blocks.borrow().len())
+}
+
+fn to_u64(value: &Nat) -> u64 {
+    u64::try_from(&value.0).unwrap_or(u64::MAX)
+}
+
+fn is_parent() -> bool {
+    get_config().parent == Some(ic_cdk::caller())
+}
+
+fn record_key(kind: RecordKind, id: &str) -> String {
+    match kind {
+        RecordKind::Transaction => format!("txn:{}", id),
+        RecordKind::Request => format!("req:{}", id),
+    }
+}
+
+fn party_prefix(party: &Principal) -> String {
+    format!("{}|", party.to_text())
+}
+
+#[ic_cdk::init]
+fn init(args: ArchiveInitArgs) {
+    CONFIG.with(|config| {
+        config
+            .borrow_mut()
+            .set(ArchiveConfig {
+                parent: Some(args.parent),
+                first_index: 0,
+            })
+            .expect("failed to write archive config");
+    });
+}
+
+/// Appends blocks starting at index `start`. Only the parent canister may
+/// call this, and `start` must continue exactly where the archive ends.
+#[ic_cdk::update]
+fn append_blocks(start: u64, blocks: Vec<Icrc3Value>) -> Result<(), String> {
+    if !is_parent() {
+        return Err("Only the parent canister can append blocks".to_string());
+    }
+    store_blocks(start, blocks)
+}
+
+fn store_blocks(start: u64, blocks: Vec<Icrc3Value>) -> Result<(), String> {
+    let mut config = get_config();
+    let is_empty = BLOCKS.with(|blocks| blocks.borrow().is_empty());
+    if is_empty && config.first_index == 0 {
+        // The first batch fixes where this archive's range begins
+        config.first_index = start;
+        CONFIG.with(|cell| {
+            cell.borrow_mut()
+                .set(config)
+                .expect("failed to write archive config");
+        });
+    }
+
+    let expected = next_index();
+    if start != expected {
+        return Err(format!(
+            "Expected blocks starting at {}, got {}",
+            expected, start
+        ));
+    }
+
+    BLOCKS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for (offset, block) in blocks.into_iter().enumerate() {
+            stored.insert(start + offset as u64, block);
+        }
+    });
+
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    get_blocks(args)
+}
+
+fn get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let first_index = get_config().first_index;
+    let log_length = next_index();
+    let mut blocks = Vec::new();
+
+    BLOCKS.with(|stored| {
+        let stored = stored.borrow();
+        for arg in args {
+            let start = to_u64(&arg.start).max(first_index);
+            let remaining = MAX_BLOCKS_PER_RESPONSE - blocks.len() as u64;
+            let length = to_u64(&arg.length).min(remaining);
+            let end = start.saturating_add(length).min(log_length);
+
+            for id in start..end {
+                if let Some(block) = stored.get(&id) {
+                    blocks.push(BlockWithId {
+                        id: Nat::from(id),
+                        block,
+                    });
+                }
+            }
+        }
+    });
+
+    GetBlocksResult {
+        log_length: Nat::from(log_length),
+        blocks,
+        archived_blocks: Vec::new(),
+    }
+}
+
+/// Stores records moved out of the backend. Re-appending a record that is
+/// already archived overwrites it, so the backend can safely retry a batch.
+#[ic_cdk::update]
+fn append_records(records: Vec<ArchivedRecord>) -> Result<(), String> {
+    if !is_parent() {
+        return Err("Only the parent canister can append records".to_string());
+    }
+    store_records(records);
+    Ok(())
+}
+
+fn store_records(records: Vec<ArchivedRecord>) {
+    for record in records {
+        let key = record_key(record.kind, &record.id);
+        PARTY_INDEX.with(|index| {
+            let mut index = index.borrow_mut();
+            for party in &record.parties {
+                index.insert(format!("{}{}", party_prefix(party), key), ());
+            }
+        });
+        RECORDS.with(|stored| stored.borrow_mut().insert(key, record));
+    }
+}
+
+/// Records of the given kind involving `party`. Only the parent canister may
+/// read them, since it is the one enforcing per-user access control.
+#[ic_cdk::query]
+fn get_records_by_party(kind: RecordKind, party: Principal) -> Result<Vec<ArchivedRecord>, String> {
+    if !is_parent() {
+        return Err("Only the parent canister can read records".to_string());
+    }
+    Ok(records_by_party(kind, &party))
+}
+
+fn records_by_party(kind: RecordKind, party: &Principal) -> Vec<ArchivedRecord> {
+    let prefix = party_prefix(party);
+    let keys = PARTY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, _)| key[prefix.len()..].to_string())
+            .collect::<Vec<_>>()
+    });
+
+    RECORDS.with(|stored| {
+        let stored = stored.borrow();
+        keys.iter()
+            .filter_map(|key| stored.get(key))
+            .filter(|record| record.kind == kind)
+            .collect()
+    })
+}
+
+/// Erases `party` from the archive: the given records replace the stored
+/// ones (the parent redacts their payloads, which the archive cannot read)
+/// and `party` is dropped from the lookup index.
+#[ic_cdk::update]
+fn erase_party(party: Principal, redacted: Vec<ArchivedRecord>) -> Result<(), String> {
+    if !is_parent() {
+        return Err("Only the parent canister can erase records".to_string());
+    }
+    erase(&party, redacted);
+    Ok(())
+}
+
+fn erase(party: &Principal, redacted: Vec<ArchivedRecord>) {
+    RECORDS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for record in redacted {
+            let key = record_key(record.kind, &record.id);
+            if stored.contains_key(&key) {
+                stored.insert(key, record);
+            }
+        }
+    });
+
+    let prefix = party_prefix(party);
+    PARTY_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        let keys = index
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in keys {
+            index.remove(&key);
+        }
+    });
+}
+
+#[ic_cdk::query]
+fn get_parent() -> Option<Principal> {
+    get_config().parent
+}
+
+// Export Candid interface
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(value: u64) -> Icrc3Value {
+        Icrc3Value::Nat(Nat::from(value))
+    }
+
+    fn range(start: u64, length: u64) -> GetBlocksArgs {
+        GetBlocksArgs {
+            start: Nat::from(start),
+            length: Nat::from(length),
+        }
+    }
+
+    fn record(kind: RecordKind, id: &str, parties: &[Principal], payload: &[u8]) -> ArchivedRecord {
+        ArchivedRecord {
+            kind,
+            id: id.to_string(),
+            parties: parties.to_vec(),
+            created_at: 0,
+            archived_at: 0,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn blocks_must_continue_where_the_archive_ends() {
+        store_blocks(100, vec![block(100), block(101)]).unwrap();
+        assert_eq!(next_index(), 102);
+
+        assert!(store_blocks(101, vec![block(101)]).is_err());
+        assert!(store_blocks(103, vec![block(103)]).is_err());
+        store_blocks(102, vec![block(102)]).unwrap();
+
+        let result = get_blocks(vec![range(0, 10)]);
+        assert_eq!(result.log_length, Nat::from(103u64));
+        let ids = result
+            .blocks
+            .iter()
+            .map(|block| block.id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![Nat::from(100u64), Nat::from(101u64), Nat::from(102u64)]
+        );
+    }
+
+    #[test]
+    fn get_blocks_caps_the_response_over_all_ranges() {
+        let blocks = (0..1_500).map(block).collect::<Vec<_>>();
+        store_blocks(0, blocks).unwrap();
+
+        let result = get_blocks(vec![range(0, 800), range(800, 700)]);
+        assert_eq!(result.blocks.len() as u64, MAX_BLOCKS_PER_RESPONSE);
+        assert_eq!(result.blocks.last().unwrap().id, Nat::from(999u64));
+    }
+
+    #[test]
+    fn records_are_found_by_party_and_kind() {
+        let farmer = Principal::from_slice(&[1]);
+        let investor = Principal::from_slice(&[2]);
+        store_records(vec![
+            record(
+                RecordKind::Transaction,
+                "txn_1",
+                &[farmer, investor],
+                b"trade",
+            ),
+            record(RecordKind::Request, "req_1", &[investor], b"request"),
+        ]);
+
+        assert_eq!(records_by_party(RecordKind::Transaction, &farmer).len(), 1);
+        assert!(records_by_party(RecordKind::Request, &farmer).is_empty());
+        assert_eq!(
+            records_by_party(RecordKind::Transaction, &investor).len(),
+            1
+        );
+        assert_eq!(records_by_party(RecordKind::Request, &investor).len(), 1);
+    }
+
+    #[test]
+    fn erasing_a_party_redacts_and_unindexes_only_its_records() {
+        let farmer = Principal::from_slice(&[1]);
+        let investor = Principal::from_slice(&[2]);
+        store_records(vec![
+            record(
+                RecordKind::Transaction,
+                "txn_1",
+                &[farmer, investor],
+                b"trade",
+            ),
+            record(RecordKind::Request, "req_1", &[investor], b"message"),
+        ]);
+
+        erase(
+            &investor,
+            vec![
+                record(RecordKind::Request, "req_1", &[investor], b"redacted"),
+                // Records that were never archived are not created
+                record(RecordKind::Request, "req_2", &[investor], b"redacted"),
+            ],
+        );
+
+        assert!(records_by_party(RecordKind::Transaction, &investor).is_empty());
+        assert!(records_by_party(RecordKind::Request, &investor).is_empty());
+        assert_eq!(records_by_party(RecordKind::Transaction, &farmer).len(), 1);
+
+        let stored = RECORDS.with(|stored| {
+            stored
+                .borrow()
+                .get(&record_key(RecordKind::Request, "req_1"))
+        });
+        assert_eq!(stored.unwrap().payload, b"redacted".to_vec());
+        assert!(RECORDS.with(|stored| !stored
+            .borrow()
+            .contains_key(&record_key(RecordKind::Request, "req_2"))));
+    }
+
+    #[test]
+    fn config_fits_its_bound() {
+        let config = ArchiveConfig {
+            parent: Some(Principal::from_slice(&[0xff; 29])),
+            first_index: u64::MAX,
+        };
+        let Bound::Bounded { max_size, .. } = ArchiveConfig::BOUND else {
+            panic!("ArchiveConfig must be bounded");
+        };
+        assert!(config.to_bytes().len() <= max_size as usize);
+    }
+}
@@ -0,0 +1,177 @@
+use candid::Principal;
+use std::collections::HashMap;
+
+use crate::types::{
+    FarmerAnalytics, InvestmentOffer, OfferAnalytics, RequestStatus, Transaction, TransactionStatus,
+};
+use crate::{OFFERS, REQUESTS, TRANSACTIONS};
+
+#[derive(Default)]
+struct OfferActivity {
+    /// Trades on the offer, refunds excluded.
+    trades: Vec<Transaction>,
+    requests_received: u64,
+    requests_accepted: u64,
+    requests_rejected: u64,
+    requests_pending: u64,
+}
+
+fn ratio(part: f64, whole: f64) -> Option<f64> {
+    (whole > 0.0).then_some(part / whole)
+}
+
+fn is_unsettled(txn: &Transaction) -> bool {
+    matches!(
+        txn.status,
+        TransactionStatus::Confirmed | TransactionStatus::Tokenized
+    )
+}
+
+fn offer_analytics(offer: InvestmentOffer, activity: &OfferActivity) -> OfferAnalytics {
+    let traded_quantity = activity.trades.iter().map(|txn| txn.quantity).sum::<u64>();
+    let funding_raised = activity
+        .trades
+        .iter()
+        .map(|txn| txn.total_amount)
+        .sum::<f64>();
+    let average_accepted_price_per_kg = ratio(funding_raised, traded_quantity as f64);
+    let sold_quantity = offer.total_quantity - offer.available_quantity;
+    let unsettled = activity
+        .trades
+        .iter()
+        .filter(|txn| is_unsettled(txn))
+        .collect::<Vec<_>>();
+
+    let time_to_fill_ns = if offer.available_quantity == 0 {
+        activity
+            .trades
+            .iter()
+            .map(|txn| txn.created_at)
+            .max()
+            .map(|filled_at| filled_at.saturating_sub(offer.created_at))
+    } else {
+        None
+    };
+
+    OfferAnalytics {
+        offer_id: offer.id,
+        product_name: offer.product_name,
+        status: offer.status,
+        asking_price_per_kg: offer.price_per_kg,
+        total_quantity: offer.total_quantity,
+        sold_quantity,
+        fill_rate: ratio(sold_quantity as f64, offer.total_quantity as f64).unwrap_or(0.0),
+        funding_raised,
+        average_accepted_price_per_kg,
+        price_realization: average_accepted_price_per_kg
+            .and_then(|price| ratio(price, offer.price_per_kg)),
+        requests_received: activity.requests_received,
+        requests_accepted: activity.requests_accepted,
+        requests_rejected: activity.requests_rejected,
+        requests_pending: activity.requests_pending,
+        acceptance_rate: ratio(
+            activity.requests_accepted as f64,
+            (activity.requests_accepted + activity.requests_rejected) as f64,
+        ),
+        time_to_fill_ns,
+        pending_delivery_quantity: unsettled.iter().map(|txn| txn.quantity).sum(),
+        pending_delivery_value: unsettled.iter().map(|txn| txn.total_amount).sum(),
+    }
+}
+
+/// Dashboard figures for the offers `farmer` listed, computed from the
+/// offers, requests and transactions held by the backend.
+pub fn farmer(farmer: Principal) -> FarmerAnalytics {
+    let offers = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .iter()
+            .filter(|(_, offer)| offer.farmer == farmer)
+            .map(|(_, offer)| offer)
+            .collect::<Vec<_>>()
+    });
+    let mut activity = offers
+        .iter()
+        .map(|offer| (offer.id.clone(), OfferActivity::default()))
+        .collect::<HashMap<_, _>>();
+
+    TRANSACTIONS.with(|transactions| {
+        for (_, txn) in transactions.borrow().iter() {
+            if txn.status == TransactionStatus::Refunded {
+                continue;
+            }
+            if let Some(activity) = activity.get_mut(&txn.offer_id) {
+                activity.trades.push(txn);
+            }
+        }
+    });
+
+    let mut pending_request_value = 0.0;
+    REQUESTS.with(|requests| {
+        for (_, req) in requests.borrow().iter() {
+            let activity = match activity.get_mut(&req.offer_id) {
+                Some(activity) => activity,
+                None => continue,
+            };
+            activity.requests_received += 1;
+            match req.status {
+                RequestStatus::Accepted => activity.requests_accepted += 1,
+                RequestStatus::Rejected => activity.requests_rejected += 1,
+                RequestStatus::Pending => {
+                    activity.requests_pending += 1;
+                    pending_request_value += req.total_offered;
+                }
+                RequestStatus::Expired | RequestStatus::Cancelled => {}
+            }
+        }
+    });
+
+    let mut summary = FarmerAnalytics {
+        farmer,
+        generated_at: ic_cdk::api::time(),
+        offers: Vec::new(),
+        funding_raised: 0.0,
+        fill_rate: None,
+        price_realization: None,
+        acceptance_rate: None,
+        average_time_to_fill_ns: None,
+        pending_delivery_quantity: 0,
+        pending_delivery_value: 0.0,
+        pending_requests: 0,
+        pending_request_value,
+    };
+    let mut total_quantity = 0;
+    let mut sold_quantity = 0;
+    // What the traded quantities would have fetched at the asking prices
+    let mut asking_value = 0.0;
+    let mut accepted = 0;
+    let mut rejected = 0;
+    let mut fill_times = Vec::new();
+
+    for offer in offers {
+        let activity = &activity[&offer.id];
+        for txn in &activity.trades {
+            asking_value += txn.quantity as f64 * offer.price_per_kg;
+        }
+
+        let analytics = offer_analytics(offer, activity);
+        summary.funding_raised += analytics.funding_raised;
+        summary.pending_delivery_quantity += analytics.pending_delivery_quantity;
+        summary.pending_delivery_value += analytics.pending_delivery_value;
+        summary.pending_requests += analytics.requests_pending;
+        total_quantity += analytics.total_quantity;
+        sold_quantity += analytics.sold_quantity;
+        accepted += analytics.requests_accepted;
+        rejected += analytics.requests_rejected;
+        fill_times.extend(analytics.time_to_fill_ns);
+        summary.offers.push(analytics);
+    }
+
+    summary.fill_rate = ratio(sold_quantity as f64, total_quantity as f64);
+    summary.price_realization = ratio(summary.funding_raised, asking_value);
+    summary.acceptance_rate = ratio(accepted as f64, (accepted + rejected) as f64);
+    summary.average_time_to_fill_ns =
+        (!fill_times.is_empty()).then(|| fill_times.iter().sum::<u64>() / fill_times.len() as u64);
+
+    summary
+}
@@ -0,0 +1,365 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::de::DeserializeOwned;
+use std::cell::Cell;
+use std::thread::LocalKey;
+
+use crate::types::{
+    AccountStatus, ArchiveRunSummary, ArchivedRecord, ConfigureRecordArchiveRequest, EntityType,
+    InvestmentRequest, RecordArchiveState, RecordKind, RequestStatus, Transaction,
+    TransactionStatus,
+};
+use crate::{audit, icrc3, treasury};
+use crate::{ARCHIVED_PARTIES, RECORD_ARCHIVE_STATE, REQUESTS, TRANSACTIONS, USERS};
+
+thread_local! {
+    static ARCHIVING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Holds an in-progress flag for one archiving run and clears it when
+/// dropped. ic-cdk drops a pending future when its callback traps, so a
+/// failed run cannot leave archiving blocked until the next upgrade.
+pub struct RunGuard(&'static LocalKey<Cell<bool>>);
+
+impl RunGuard {
+    /// `None` while another run holding `flag` is in progress.
+    pub fn acquire(flag: &'static LocalKey<Cell<bool>>) -> Option<RunGuard> {
+        if flag.with(|running| running.replace(true)) {
+            return None;
+        }
+        Some(RunGuard(flag))
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        self.0.with(|running| running.set(false));
+    }
+}
+
+pub fn get_state() -> RecordArchiveState {
+    RECORD_ARCHIVE_STATE.with(|state| state.borrow().get().clone())
+}
+
+pub fn set_state(state: RecordArchiveState) {
+    RECORD_ARCHIVE_STATE.with(|cell| {
+        cell.borrow_mut()
+            .set(RecordArchiveState {
+                archiving: None,
+                ..state
+            })
+            .expect("failed to write record archive state");
+    });
+}
+
+/// The stored state with whether a run is in progress.
+pub fn status() -> RecordArchiveState {
+    RecordArchiveState {
+        archiving: Some(ARCHIVING.with(|archiving| archiving.get())),
+        ..get_state()
+    }
+}
+
+pub fn configure(
+    actor: Principal,
+    request: ConfigureRecordArchiveRequest,
+) -> Result<RecordArchiveState, String> {
+    if request.batch_size == 0 {
+        return Err("Batch size must be positive".to_string());
+    }
+
+    let mut state = get_state();
+    let before = state.clone();
+    state.min_age_ns = request.min_age_ns;
+    state.batch_size = request.batch_size;
+    set_state(state.clone());
+    audit::record(
+        actor,
+        "configure_record_archive",
+        EntityType::Config,
+        "record_archive",
+        Some(&before),
+        Some(&state),
+    );
+
+    Ok(status())
+}
+
+// The companion canister is shared with the ICRC-3 block archive.
+fn archive_canister() -> Option<Principal> {
+    icrc3::get_state().archive_canister
+}
+
+fn is_finalized(request: &InvestmentRequest) -> bool {
+    !matches!(request.status, RequestStatus::Pending)
+}
+
+/// Moves completed transactions and finalized requests older than the
+/// configured age to the archive canister, one batch of each per call.
+pub async fn archive_old_records() -> Result<ArchiveRunSummary, String> {
+    let archive = archive_canister().ok_or("No archive canister configured")?;
+
+    let _run = RunGuard::acquire(&ARCHIVING).ok_or("Archiving already in progress")?;
+    archive_batch(archive).await
+}
+
+async fn archive_batch(archive: Principal) -> Result<ArchiveRunSummary, String> {
+    let state = get_state();
+    let now = ic_cdk::api::time();
+    let cutoff = now.saturating_sub(state.min_age_ns);
+
+    let transactions = TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .filter(|(_, txn)| matches!(txn.status, TransactionStatus::Completed))
+            .filter(|(_, txn)| txn.updated_at <= cutoff)
+            // Organization revenue is computed from local transactions
+            .filter(|(_, txn)| txn.organization.is_none())
+            // Payouts and refunds look the transaction up locally
+            .filter(|(_, txn)| treasury::is_paid_out(&txn.id))
+            .take(state.batch_size as usize)
+            .map(|(_, txn)| txn)
+            .collect::<Vec<_>>()
+    });
+
+    let requests = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .filter(|(_, req)| is_finalized(req) && req.updated_at <= cutoff)
+            .take(state.batch_size as usize)
+            .map(|(_, req)| req)
+            .collect::<Vec<_>>()
+    });
+
+    if transactions.is_empty() && requests.is_empty() {
+        return Ok(ArchiveRunSummary {
+            transactions_archived: 0,
+            requests_archived: 0,
+        });
+    }
+
+    let records = transactions
+        .iter()
+        .map(|txn| ArchivedRecord {
+            kind: RecordKind::Transaction,
+            id: txn.id.clone(),
+            parties: vec![txn.farmer, txn.investor],
+            created_at: txn.created_at,
+            archived_at: now,
+            payload: Encode!(txn).unwrap(),
+        })
+        .chain(requests.iter().map(|req| ArchivedRecord {
+            kind: RecordKind::Request,
+            id: req.id.clone(),
+            parties: vec![req.investor],
+            created_at: req.created_at,
+            archived_at: now,
+            payload: Encode!(req).unwrap(),
+        }))
+        .collect::<Vec<_>>();
+
+    let result: ic_cdk::api::call::CallResult<(Result<(), String>,)> =
+        ic_cdk::call(archive, "append_records", (records.clone(),)).await;
+
+    match result {
+        Ok((Ok(()),)) => {}
+        Ok((Err(error),)) => return Err(format!("Archive rejected records: {}", error)),
+        Err((code, message)) => {
+            return Err(format!("Failed to reach archive: {:?} {}", code, message))
+        }
+    }
+
+    let actor = ic_cdk::id();
+    for txn in &transactions {
+        TRANSACTIONS.with(|transactions| transactions.borrow_mut().remove(&txn.id));
+        audit::record(
+            actor,
+            "archive_old_records",
+            EntityType::Transaction,
+            &txn.id,
+            Some(txn),
+            None,
+        );
+    }
+    for req in &requests {
+        REQUESTS.with(|requests| requests.borrow_mut().remove(&req.id));
+        audit::record(
+            actor,
+            "archive_old_records",
+            EntityType::Request,
+            &req.id,
+            Some(req),
+            None,
+        );
+    }
+    for record in &records {
+        for party in &record.parties {
+            ARCHIVED_PARTIES.with(|parties| {
+                let mut parties = parties.borrow_mut();
+                let count = parties.get(party).unwrap_or(0);
+                parties.insert(*party, count + 1);
+            });
+        }
+    }
+
+    let mut state = get_state();
+    state.archived_transactions += transactions.len() as u64;
+    state.archived_requests += requests.len() as u64;
+    state.last_run_at = Some(now);
+    set_state(state);
+
+    Ok(ArchiveRunSummary {
+        transactions_archived: transactions.len() as u64,
+        requests_archived: requests.len() as u64,
+    })
+}
+
+/// Archived transactions involving `party`, fetched from the archive canister.
+pub async fn fetch_transactions(party: Principal) -> Result<Vec<Transaction>, String> {
+    fetch(RecordKind::Transaction, party).await
+}
+
+/// Archived investment requests involving `party`.
+pub async fn fetch_requests(party: Principal) -> Result<Vec<InvestmentRequest>, String> {
+    fetch(RecordKind::Request, party).await
+}
+
+async fn fetch<T: CandidType + DeserializeOwned>(
+    kind: RecordKind,
+    party: Principal,
+) -> Result<Vec<T>, String> {
+    // Avoid a cross-canister call for users with nothing archived
+    if ARCHIVED_PARTIES.with(|parties| !parties.borrow().contains_key(&party)) {
+        return Ok(Vec::new());
+    }
+
+    let archive = match archive_canister() {
+        Some(archive) => archive,
+        None => return Ok(Vec::new()),
+    };
+
+    fetch_records(archive, kind, party)
+        .await?
+        .iter()
+        .map(|record| decode(record))
+        .collect()
+}
+
+async fn fetch_records(
+    archive: Principal,
+    kind: RecordKind,
+    party: Principal,
+) -> Result<Vec<ArchivedRecord>, String> {
+    let result: ic_cdk::api::call::CallResult<(Result<Vec<ArchivedRecord>, String>,)> =
+        ic_cdk::call(archive, "get_records_by_party", (kind, party)).await;
+
+    match result {
+        Ok((Ok(records),)) => Ok(records),
+        Ok((Err(error),)) => Err(format!("Archive lookup failed: {}", error)),
+        Err((code, message)) => Err(format!("Failed to reach archive: {:?} {}", code, message)),
+    }
+}
+
+fn decode<T: CandidType + DeserializeOwned>(record: &ArchivedRecord) -> Result<T, String> {
+    Decode!(&record.payload, T).map_err(|e| format!("Corrupt archived record: {}", e))
+}
+
+/// Erases an account's archived records: request messages written by
+/// `party` are blanked and `party` is dropped from the archive's index.
+/// Transactions carry no free text and stay readable by the counterparty.
+pub async fn erase_party(party: Principal) -> Result<(), String> {
+    if ARCHIVED_PARTIES.with(|parties| !parties.borrow().contains_key(&party)) {
+        return Ok(());
+    }
+    let archive = archive_canister().ok_or("No archive canister configured")?;
+
+    let mut redacted = Vec::new();
+    for record in fetch_records(archive, RecordKind::Request, party).await? {
+        let mut req: InvestmentRequest = decode(&record)?;
+        if req.investor != party || req.message.is_empty() {
+            continue;
+        }
+        req.message = String::new();
+        redacted.push(ArchivedRecord {
+            payload: Encode!(&req).unwrap(),
+            ..record
+        });
+    }
+
+    let result: ic_cdk::api::call::CallResult<(Result<(), String>,)> =
+        ic_cdk::call(archive, "erase_party", (party, redacted)).await;
+
+    match result {
+        Ok((Ok(()),)) => {}
+        Ok((Err(error),)) => return Err(format!("Archive rejected erasure: {}", error)),
+        Err((code, message)) => {
+            return Err(format!("Failed to reach archive: {:?} {}", code, message))
+        }
+    }
+
+    let archived = ARCHIVED_PARTIES.with(|parties| parties.borrow_mut().remove(&party));
+    audit::record(
+        ic_cdk::id(),
+        "erase_archived_records",
+        EntityType::User,
+        &party.to_text(),
+        archived.as_ref(),
+        None,
+    );
+    Ok(())
+}
+
+/// Retries archive erasure for deleted accounts that still have archived
+/// records, e.g. because the archive was unreachable during deletion or a
+/// later archiving run moved more of their records.
+pub async fn erase_pending() {
+    let pending = ARCHIVED_PARTIES.with(|parties| {
+        parties
+            .borrow()
+            .iter()
+            .map(|(party, _)| party)
+            .collect::<Vec<_>>()
+    });
+
+    for party in pending {
+        let erased = USERS
+            .with(|users| users.borrow().get(&party))
+            .is_some_and(|user| matches!(user.account_status(), AccountStatus::Erased));
+        if erased {
+            if let Err(error) = erase_party(party).await {
+                ic_cdk::println!("Archive erasure of {} failed: {}", party, error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    thread_local! {
+        static RUNNING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    #[test]
+    fn run_guard_allows_one_run_at_a_time() {
+        let run = RunGuard::acquire(&RUNNING).unwrap();
+        assert!(RunGuard::acquire(&RUNNING).is_none());
+
+        drop(run);
+        assert!(!RUNNING.with(|running| running.get()));
+        assert!(RunGuard::acquire(&RUNNING).is_some());
+    }
+
+    #[test]
+    fn run_guard_releases_the_flag_on_panic() {
+        // A trap in a callback drops the pending future the same way
+        let result = std::panic::catch_unwind(|| {
+            let _run = RunGuard::acquire(&RUNNING).unwrap();
+            panic!("callback trapped");
+        });
+        assert!(result.is_err());
+        assert!(!RUNNING.with(|running| running.get()));
+    }
+}
@@ -0,0 +1,201 @@
+use candid::{CandidType, Principal};
+
+use crate::certification::hash_candid;
+use crate::types::{AuditChainReport, AuditEntry, AuditLogPage, EntityType};
+use crate::AUDIT_LOG;
+
+/// `prev_hash` of the very first entry in the log.
+const GENESIS_HASH: [u8; 32] = [0; 32];
+
+/// Appends an entry describing a state change to the audit log.
+///
+/// `before` and `after` are the entity as it was stored before and after the
+/// change; only their digests end up in the log. Pass `None` for the side
+/// that does not exist, e.g. `before` when the entity was just created.
+pub fn record<T: CandidType>(
+    actor: Principal,
+    action: &str,
+    entity_type: EntityType,
+    entity_id: &str,
+    before: Option<&T>,
+    after: Option<&T>,
+) {
+    append(AuditEntry {
+        index: 0,
+        timestamp: ic_cdk::api::time(),
+        actor,
+        action: action.to_string(),
+        entity_type,
+        entity_id: entity_id.to_string(),
+        before_digest: before.map(|value| hash_candid(value).to_vec()),
+        after_digest: after.map(|value| hash_candid(value).to_vec()),
+        prev_hash: Vec::new(),
+        hash: Vec::new(),
+    });
+}
+
+// Fills in the position of `entry` in the chain and appends it
+fn append(mut entry: AuditEntry) {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow_mut();
+        entry.index = log.len();
+        entry.prev_hash = match entry.index.checked_sub(1).and_then(|last| log.get(last)) {
+            Some(last) => last.hash,
+            None => GENESIS_HASH.to_vec(),
+        };
+        entry.hash = entry_hash(&entry);
+
+        log.append(&entry).expect("failed to append audit entry");
+    });
+}
+
+pub fn page(start: u64, limit: u64) -> AuditLogPage {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let total = log.len();
+        let end = start.saturating_add(limit).min(total);
+
+        AuditLogPage {
+            entries: (start..end).filter_map(|index| log.get(index)).collect(),
+            total,
+            next_start: (end < total).then_some(end),
+        }
+    })
+}
+
+/// Checks up to `length` entries from `start`: every entry must hash to its
+/// stored hash and link to the hash of its predecessor.
+///
+/// The whole log does not fit in one call once it grows, so callers verify
+/// it in segments, starting the next one at `next_start` and checking that
+/// it continues from the returned `last_hash`.
+pub fn verify_chain(start: u64, length: u64) -> AuditChainReport {
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let end = start.saturating_add(length).min(log.len());
+        let mut prev_hash = match start.checked_sub(1) {
+            Some(previous) => match log.get(previous) {
+                Some(entry) => entry.hash,
+                None => return AuditChainReport::broken(previous, "Entry missing"),
+            },
+            None => GENESIS_HASH.to_vec(),
+        };
+
+        let broken = |index: u64, error: &str| AuditChainReport {
+            entries_checked: index - start,
+            ..AuditChainReport::broken(index, error)
+        };
+        for index in start..end {
+            let entry = match log.get(index) {
+                Some(entry) => entry,
+                None => return broken(index, "Entry missing"),
+            };
+
+            if entry.index != index {
+                return broken(index, "Index mismatch");
+            }
+            if entry.prev_hash != prev_hash {
+                return broken(index, "Previous hash mismatch");
+            }
+            if entry.hash != entry_hash(&entry) {
+                return broken(index, "Entry hash mismatch");
+            }
+
+            prev_hash = entry.hash;
+        }
+
+        AuditChainReport {
+            valid: true,
+            entries_checked: end.saturating_sub(start),
+            first_invalid_index: None,
+            error: None,
+            last_hash: Some(prev_hash),
+            next_start: (end < log.len()).then_some(end),
+        }
+    })
+}
+
+// The hash covers every field of the entry, including `prev_hash`, with the
+// `hash` field itself left empty.
+fn entry_hash(entry: &AuditEntry) -> Vec<u8> {
+    let unhashed = AuditEntry {
+        hash: Vec::new(),
+        ..entry.clone()
+    };
+    hash_candid(&unhashed).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(action: &str) -> AuditEntry {
+        AuditEntry {
+            index: 0,
+            timestamp: 0,
+            actor: Principal::anonymous(),
+            action: action.to_string(),
+            entity_type: EntityType::Config,
+            entity_id: "settings".to_string(),
+            before_digest: None,
+            after_digest: Some(vec![1; 32]),
+            prev_hash: Vec::new(),
+            hash: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn verifies_the_chain_in_segments() {
+        for action in ["first", "second", "third"] {
+            append(entry(action));
+        }
+
+        let head = verify_chain(0, 2);
+        assert!(head.valid);
+        assert_eq!(head.entries_checked, 2);
+        assert_eq!(head.next_start, Some(2));
+
+        // The next segment starts from the hash the previous one ended with
+        let tail = verify_chain(2, 2);
+        assert!(tail.valid);
+        assert_eq!(tail.entries_checked, 1);
+        assert_eq!(tail.next_start, None);
+        let second = AUDIT_LOG.with(|log| log.borrow().get(1)).unwrap();
+        assert_eq!(head.last_hash, Some(second.hash));
+        let third = AUDIT_LOG.with(|log| log.borrow().get(2)).unwrap();
+        assert_eq!(third.prev_hash, head.last_hash.unwrap());
+    }
+
+    #[test]
+    fn detects_a_broken_link() {
+        append(entry("first"));
+        append(entry("second"));
+        // Appended around `append`, so it does not link to its predecessor
+        let mut forged = entry("forged");
+        forged.index = 2;
+        forged.prev_hash = GENESIS_HASH.to_vec();
+        forged.hash = entry_hash(&forged);
+        AUDIT_LOG.with(|log| log.borrow_mut().append(&forged).unwrap());
+
+        let report = verify_chain(0, 10);
+        assert!(!report.valid);
+        assert_eq!(report.first_invalid_index, Some(2));
+        assert_eq!(report.entries_checked, 2);
+        assert_eq!(report.error.as_deref(), Some("Previous hash mismatch"));
+    }
+
+    #[test]
+    fn detects_an_altered_entry() {
+        append(entry("first"));
+        let mut altered = entry("altered");
+        altered.index = 1;
+        altered.prev_hash = AUDIT_LOG.with(|log| log.borrow().get(0)).unwrap().hash;
+        altered.hash = entry_hash(&altered);
+        altered.action = "rewritten".to_string();
+        AUDIT_LOG.with(|log| log.borrow_mut().append(&altered).unwrap());
+
+        let report = verify_chain(0, 10);
+        assert_eq!(report.first_invalid_index, Some(1));
+        assert_eq!(report.error.as_deref(), Some("Entry hash mismatch"));
+    }
+}
@@ -0,0 +1,306 @@
+use candid::{CandidType, Encode};
+use ic_certification::{
+    fork, fork_hash, labeled, labeled_hash, leaf, leaf_hash, pruned, AsHashTree, Hash, HashTree,
+    RbTree,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::types::{InvestmentOffer, PlatformStats};
+
+// Top-level labels of the certified tree, kept in ascending byte order so
+// that lookups on the client side can prove absence as well as presence.
+pub const LAST_BLOCK_HASH_LABEL: &[u8] = b"last_block_hash";
+pub const LAST_BLOCK_INDEX_LABEL: &[u8] = b"last_block_index";
+pub const OFFERS_LABEL: &[u8] = b"offers";
+pub const STATS_LABEL: &[u8] = b"stats";
+
+/// In-memory certified view of the data served by certified queries.
+///
+/// The tree is laid out as:
+///
+/// ```text
+/// /last_block_hash   -> hash of the latest ICRC-3 block
+/// /last_block_index  -> leb128(index of the latest ICRC-3 block)
+/// /offers/<offer_id> -> sha256(candid(InvestmentOffer))
+/// /stats             -> sha256(candid(PlatformStats))
+/// ```
+///
+/// The two `last_block_*` labels only exist once the ICRC-3 log has a block.
+///
+/// Its root hash is what the canister passes to `set_certified_data`.
+#[derive(Default)]
+pub struct CertifiedState {
+    offers: RbTree<Vec<u8>, Hash>,
+    stats: Hash,
+    tip: Option<(u64, Hash)>,
+}
+
+impl CertifiedState {
+    pub fn insert_offer(&mut self, offer: &InvestmentOffer) {
+        self.offers
+            .insert(offer.id.as_bytes().to_vec(), hash_candid(offer));
+    }
+
+    pub fn set_stats(&mut self, stats: &PlatformStats) {
+        self.stats = hash_candid(stats);
+    }
+
+    pub fn set_tip(&mut self, last_block_index: u64, last_block_hash: Hash) {
+        self.tip = Some((last_block_index, last_block_hash));
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        fold_hashes(
+            self.subtrees()
+                .into_iter()
+                .map(|(label, hash)| labeled_hash(label, &hash))
+                .collect(),
+        )
+    }
+
+    pub fn offer_witness(&self, offer_id: &str) -> HashTree {
+        self.witness(|label| {
+            (label == OFFERS_LABEL).then(|| self.offers.witness(offer_id.as_bytes()))
+        })
+    }
+
+    pub fn stats_witness(&self) -> HashTree {
+        self.witness(|label| (label == STATS_LABEL).then(|| self.stats.as_hash_tree()))
+    }
+
+    /// Witness for the ICRC-3 tip certificate, or `None` while the log is empty.
+    pub fn tip_witness(&self) -> Option<HashTree> {
+        let (index, hash) = self.tip?;
+        Some(self.witness(|label| {
+            if label == LAST_BLOCK_HASH_LABEL {
+                Some(leaf(hash.to_vec()))
+            } else if label == LAST_BLOCK_INDEX_LABEL {
+                Some(leaf(leb128(index)))
+            } else {
+                None
+            }
+        }))
+    }
+
+    // Label and content root hash of every top-level subtree, in label order.
+    fn subtrees(&self) -> Vec<(&'static [u8], Hash)> {
+        let mut subtrees = Vec::new();
+        if let Some((index, hash)) = self.tip {
+            subtrees.push((LAST_BLOCK_HASH_LABEL, leaf_hash(&hash)));
+            subtrees.push((LAST_BLOCK_INDEX_LABEL, leaf_hash(&leb128(index))));
+        }
+        subtrees.push((OFFERS_LABEL, self.offers.root_hash()));
+        subtrees.push((STATS_LABEL, self.stats.root_hash()));
+        subtrees
+    }
+
+    // Builds a witness revealing the subtrees for which `reveal` returns a
+    // tree and pruning every other one.
+    fn witness(&self, reveal: impl Fn(&[u8]) -> Option<HashTree>) -> HashTree {
+        let nodes = self
+            .subtrees()
+            .into_iter()
+            .map(|(label, hash)| match reveal(label) {
+                Some(tree) => labeled(label, tree),
+                None => pruned(labeled_hash(label, &hash)),
+            })
+            .collect::<Vec<_>>();
+
+        nodes
+            .into_iter()
+            .rev()
+            .reduce(|right, left| fork(left, right))
+            .expect("certified tree has at least one subtree")
+    }
+}
+
+/// Unsigned LEB128 encoding, as used for `last_block_index`.
+pub fn leb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+// Mirrors the shape produced by `CertifiedState::witness`.
+fn fold_hashes(hashes: Vec<Hash>) -> Hash {
+    hashes
+        .into_iter()
+        .rev()
+        .reduce(|right, left| fork_hash(&left, &right))
+        .expect("certified tree has at least one subtree")
+}
+
+thread_local! {
+    static CERTIFIED_STATE: RefCell<CertifiedState> = RefCell::new(CertifiedState::default());
+}
+
+/// SHA-256 of the Candid encoding of a value, as stored in the certified tree.
+pub fn hash_candid<T: CandidType>(value: &T) -> Hash {
+    Sha256::digest(Encode!(value).unwrap()).into()
+}
+
+/// Serializes a witness as self-describing CBOR, the format expected by agents.
+pub fn encode_witness(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer).unwrap();
+    serializer.into_inner()
+}
+
+pub fn certify_offer(offer: &InvestmentOffer) {
+    CERTIFIED_STATE.with(|state| state.borrow_mut().insert_offer(offer));
+    update_certified_data();
+}
+
+pub fn certify_stats(stats: &PlatformStats) {
+    CERTIFIED_STATE.with(|state| state.borrow_mut().set_stats(stats));
+    update_certified_data();
+}
+
+pub fn certify_tip(last_block_index: u64, last_block_hash: Hash) {
+    CERTIFIED_STATE.with(|state| {
+        state
+            .borrow_mut()
+            .set_tip(last_block_index, last_block_hash)
+    });
+    update_certified_data();
+}
+
+/// Replaces the certified state wholesale, e.g. after an upgrade wiped the heap.
+pub fn rebuild<'a>(
+    offers: impl Iterator<Item = &'a InvestmentOffer>,
+    stats: &PlatformStats,
+    tip: Option<(u64, Hash)>,
+) {
+    let mut state = CertifiedState::default();
+    for offer in offers {
+        state.insert_offer(offer);
+    }
+    state.set_stats(stats);
+    state.tip = tip;
+
+    CERTIFIED_STATE.with(|s| *s.borrow_mut() = state);
+    update_certified_data();
+}
+
+pub fn offer_witness(offer_id: &str) -> HashTree {
+    CERTIFIED_STATE.with(|state| state.borrow().offer_witness(offer_id))
+}
+
+pub fn stats_witness() -> HashTree {
+    CERTIFIED_STATE.with(|state| state.borrow().stats_witness())
+}
+
+pub fn tip_witness() -> Option<HashTree> {
+    CERTIFIED_STATE.with(|state| state.borrow().tip_witness())
+}
+
+fn update_certified_data() {
+    let root_hash = CERTIFIED_STATE.with(|state| state.borrow().root_hash());
+    ic_cdk::api::set_certified_data(&root_hash);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{OfferStatus, ProductType, QualityGrade};
+    use candid::Principal;
+    use ic_certification::LookupResult;
+
+    fn offer(id: &str) -> InvestmentOffer {
+        InvestmentOffer {
+            id: id.to_string(),
+            farmer: Principal::anonymous(),
+            product_name: "Maize".to_string(),
+            product_type: ProductType::Grains,
+            total_quantity: 1_000,
+            available_quantity: 1_000,
+            price_per_kg: 0.4,
+            description: String::new(),
+            harvest_date: "2025-09-01".to_string(),
+            location: "Kano".to_string(),
+            quality_grade: QualityGrade::Grade1,
+            minimum_investment: 100,
+            organization: None,
+            status: OfferStatus::Active,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    fn stats() -> PlatformStats {
+        PlatformStats {
+            total_users: 3,
+            total_offers: 2,
+            total_requests: 0,
+            total_transactions: 0,
+            active_offers: 2,
+        }
+    }
+
+    #[test]
+    fn offer_witness_verifies_against_certified_root() {
+        let mut state = CertifiedState::default();
+        let first = offer("offer_1");
+        state.insert_offer(&first);
+        state.insert_offer(&offer("offer_2"));
+        state.set_stats(&stats());
+
+        // A client holding the certificate learns `root_hash` from the
+        // certified data; the witness must reconstruct to exactly that value.
+        let witness = state.offer_witness("offer_1");
+        assert_eq!(witness.digest(), state.root_hash());
+
+        let expected = hash_candid(&first);
+        assert_eq!(
+            witness.lookup_path([OFFERS_LABEL, b"offer_1".as_slice()]),
+            LookupResult::Found(&expected[..])
+        );
+    }
+
+    #[test]
+    fn stats_witness_verifies_against_certified_root() {
+        let mut state = CertifiedState::default();
+        state.insert_offer(&offer("offer_1"));
+        state.set_stats(&stats());
+
+        let witness = state.stats_witness();
+        assert_eq!(witness.digest(), state.root_hash());
+
+        let expected = hash_candid(&stats());
+        assert_eq!(
+            witness.lookup_path([STATS_LABEL]),
+            LookupResult::Found(&expected[..])
+        );
+    }
+
+    #[test]
+    fn tip_witness_verifies_against_certified_root() {
+        let mut state = CertifiedState::default();
+        assert!(state.tip_witness().is_none());
+
+        state.insert_offer(&offer("offer_1"));
+        state.set_stats(&stats());
+        state.set_tip(300, [7; 32]);
+
+        let witness = state.tip_witness().unwrap();
+        assert_eq!(witness.digest(), state.root_hash());
+        assert_eq!(
+            witness.lookup_path([LAST_BLOCK_INDEX_LABEL]),
+            LookupResult::Found(&[0xac, 0x02][..])
+        );
+        assert_eq!(
+            witness.lookup_path([LAST_BLOCK_HASH_LABEL]),
+            LookupResult::Found(&[7; 32][..])
+        );
+    }
+}
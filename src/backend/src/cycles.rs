@@ -0,0 +1,341 @@
+use candid::Principal;
+use std::cell::Cell;
+
+use crate::types::{
+    CyclesContributor, CyclesMonitorConfig, CyclesSample, CyclesStatus, CyclesTopUp, EntityType,
+    NotificationKind, Permission, WalletReceiveResult,
+};
+use crate::{audit, inbox, notifier, permissions};
+use crate::{CYCLES_MONITOR_CONFIG, CYCLES_SAMPLES, CYCLES_TOP_UPS, USERS};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const DAY_NS: u64 = 24 * 60 * 60 * NANOS_PER_SEC;
+/// Samples older than this are dropped.
+const SAMPLE_RETENTION_NS: u64 = 30 * DAY_NS;
+/// Shortest sampled period a burn rate is derived from.
+const MIN_BURN_PERIOD_NS: u64 = 60 * 60 * NANOS_PER_SEC;
+const MAX_TOP_UP_PAGE_SIZE: u64 = 100;
+
+thread_local! {
+    // Kept on the heap: after an upgrade admins are alerted once more if
+    // the balance is still low.
+    static ALERT_RAISED: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn get_config() -> CyclesMonitorConfig {
+    CYCLES_MONITOR_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn set_config(config: CyclesMonitorConfig) {
+    CYCLES_MONITOR_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .expect("failed to write cycles monitor config");
+    });
+}
+
+pub fn configure(
+    actor: Principal,
+    config: CyclesMonitorConfig,
+) -> Result<CyclesMonitorConfig, String> {
+    if config.freeze_reserve >= config.alert_threshold {
+        return Err("Alert threshold must be above the freeze reserve".to_string());
+    }
+
+    let before = get_config();
+    set_config(config.clone());
+    audit::record(
+        actor,
+        "configure_cycles_monitor",
+        EntityType::Config,
+        "cycles_monitor",
+        Some(&before),
+        Some(&config),
+    );
+
+    Ok(config)
+}
+
+/// Accepts all cycles attached to the call and records who sent them.
+pub fn receive() -> WalletReceiveResult {
+    let available = ic_cdk::api::call::msg_cycles_available128();
+    if available == 0 {
+        return WalletReceiveResult { accepted: 0 };
+    }
+    let accepted = ic_cdk::api::call::msg_cycles_accept128(available);
+
+    let contributor = ic_cdk::caller();
+    let top_up = CYCLES_TOP_UPS.with(|top_ups| {
+        let mut top_ups = top_ups.borrow_mut();
+        let top_up = CyclesTopUp {
+            index: top_ups.last_key_value().map_or(0, |(index, _)| index + 1),
+            contributor,
+            amount: accepted,
+            timestamp: ic_cdk::api::time(),
+        };
+        top_ups.insert(top_up.index, top_up.clone());
+        top_up
+    });
+    audit::record(
+        contributor,
+        "wallet_receive",
+        EntityType::Config,
+        "cycles",
+        None,
+        Some(&top_up),
+    );
+
+    WalletReceiveResult {
+        accepted: u64::try_from(accepted).unwrap_or(u64::MAX),
+    }
+}
+
+pub fn top_ups(start: u64, limit: u64) -> Vec<CyclesTopUp> {
+    let limit = limit.min(MAX_TOP_UP_PAGE_SIZE) as usize;
+    CYCLES_TOP_UPS.with(|top_ups| {
+        top_ups
+            .borrow()
+            .range(start..)
+            .take(limit)
+            .map(|(_, top_up)| top_up)
+            .collect()
+    })
+}
+
+/// Everyone who has sent cycles, largest total first.
+pub fn contributors() -> Vec<CyclesContributor> {
+    let mut contributors: Vec<CyclesContributor> = Vec::new();
+    CYCLES_TOP_UPS.with(|top_ups| {
+        for (_, top_up) in top_ups.borrow().iter() {
+            match contributors
+                .iter_mut()
+                .find(|contributor| contributor.contributor == top_up.contributor)
+            {
+                Some(contributor) => {
+                    contributor.total += top_up.amount;
+                    contributor.top_ups += 1;
+                    contributor.last_top_up_at = top_up.timestamp;
+                }
+                None => contributors.push(CyclesContributor {
+                    contributor: top_up.contributor,
+                    total: top_up.amount,
+                    top_ups: 1,
+                    last_top_up_at: top_up.timestamp,
+                }),
+            }
+        }
+    });
+    contributors.sort_by_key(|contributor| std::cmp::Reverse(contributor.total));
+    contributors
+}
+
+fn topped_up_since(since: u64) -> u128 {
+    CYCLES_TOP_UPS.with(|top_ups| {
+        top_ups
+            .borrow()
+            .iter()
+            .filter(|(_, top_up)| top_up.timestamp > since)
+            .map(|(_, top_up)| top_up.amount)
+            .sum()
+    })
+}
+
+pub fn status() -> CyclesStatus {
+    status_at(ic_cdk::api::time(), ic_cdk::api::canister_balance128())
+}
+
+fn status_at(now: u64, balance: u128) -> CyclesStatus {
+    let config = get_config();
+    let oldest = CYCLES_SAMPLES.with(|samples| samples.borrow().first_key_value());
+
+    // Top-ups are added back so that they do not offset what was burned
+    let burn_rate_per_day = match &oldest {
+        Some((_, sample)) if now - sample.timestamp >= MIN_BURN_PERIOD_NS => {
+            let burned =
+                (sample.balance + topped_up_since(sample.timestamp)).saturating_sub(balance);
+            Some(burned * DAY_NS as u128 / (now - sample.timestamp) as u128)
+        }
+        _ => None,
+    };
+    let projected_freeze_at = match burn_rate_per_day {
+        Some(burn_rate) if burn_rate > 0 => {
+            let remaining = balance.saturating_sub(config.freeze_reserve);
+            let nanos = remaining * DAY_NS as u128 / burn_rate;
+            Some(now.saturating_add(u64::try_from(nanos).unwrap_or(u64::MAX)))
+        }
+        _ => None,
+    };
+
+    CyclesStatus {
+        balance,
+        below_threshold: balance < config.alert_threshold,
+        config,
+        burn_rate_per_day,
+        projected_freeze_at,
+        sampled_since: oldest.map(|(timestamp, _)| timestamp),
+        checked_at: now,
+    }
+}
+
+/// Records the current balance, drops expired samples and alerts admins
+/// when the balance first falls below the threshold.
+pub fn sample() {
+    let now = ic_cdk::api::time();
+    let balance = ic_cdk::api::canister_balance128();
+
+    CYCLES_SAMPLES.with(|samples| {
+        let mut samples = samples.borrow_mut();
+        samples.insert(
+            now,
+            CyclesSample {
+                timestamp: now,
+                balance,
+            },
+        );
+        let cutoff = now.saturating_sub(SAMPLE_RETENTION_NS);
+        let expired = samples
+            .range(..cutoff)
+            .map(|(timestamp, _)| timestamp)
+            .collect::<Vec<_>>();
+        for timestamp in expired {
+            samples.remove(&timestamp);
+        }
+    });
+
+    let below = balance < get_config().alert_threshold;
+    if below && !ALERT_RAISED.with(|raised| raised.replace(true)) {
+        ic_cdk::spawn(alert_admins(balance));
+    } else if !below {
+        ALERT_RAISED.with(|raised| raised.set(false));
+    }
+}
+
+async fn alert_admins(balance: u128) {
+    let status = status();
+    let projection = match status.projected_freeze_at {
+        Some(at) => format!(
+            " At the current burn rate it freezes in about {} hours.",
+            at.saturating_sub(status.checked_at) / (60 * 60 * NANOS_PER_SEC)
+        ),
+        None => String::new(),
+    };
+    let body = format!(
+        "The HarvestX backend holds {} cycles, below the alert threshold of {}.{} Top it up to keep it running.",
+        balance, status.config.alert_threshold, projection
+    );
+    ic_cdk::println!("{}", body);
+    inbox::notify_holders(Permission::ManageCycles, NotificationKind::LowCycles, &body);
+
+    let admins = permissions::holders(Permission::ManageCycles)
+        .into_iter()
+        .filter_map(|principal| USERS.with(|users| users.borrow().get(&principal)))
+        .filter(|user| user.has_verified_email())
+        .collect::<Vec<_>>();
+    for admin in admins {
+        let idempotency_key = format!(
+            "cycles-alert-{}-{}",
+            admin.principal.to_text(),
+            status.checked_at
+        );
+        if let Err(error) = notifier::send_email(
+            &admin.email,
+            "HarvestX backend is low on cycles",
+            &body,
+            &idempotency_key,
+        )
+        .await
+        {
+            ic_cdk::println!("Low-cycles alert to {} failed: {}", admin.email, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_NS: u64 = 60 * 60 * NANOS_PER_SEC;
+    const T: u64 = 1_700_000_000 * NANOS_PER_SEC;
+
+    fn sampled(timestamp: u64, balance: u128) {
+        CYCLES_SAMPLES.with(|samples| {
+            samples
+                .borrow_mut()
+                .insert(timestamp, CyclesSample { timestamp, balance })
+        });
+    }
+
+    fn topped_up(contributor: u8, amount: u128, timestamp: u64) {
+        CYCLES_TOP_UPS.with(|top_ups| {
+            let mut top_ups = top_ups.borrow_mut();
+            let index = top_ups.last_key_value().map_or(0, |(index, _)| index + 1);
+            top_ups.insert(
+                index,
+                CyclesTopUp {
+                    index,
+                    contributor: Principal::from_slice(&[contributor]),
+                    amount,
+                    timestamp,
+                },
+            );
+        });
+    }
+
+    #[test]
+    fn no_burn_rate_before_an_hour_of_samples() {
+        sampled(T, 1_000);
+        let status = status_at(T + HOUR_NS - 1, 900);
+        assert_eq!(status.burn_rate_per_day, None);
+        assert_eq!(status.projected_freeze_at, None);
+        assert_eq!(status.sampled_since, Some(T));
+    }
+
+    #[test]
+    fn top_ups_do_not_offset_the_burn_rate() {
+        set_config(CyclesMonitorConfig {
+            alert_threshold: 5_000,
+            freeze_reserve: 1_000,
+        });
+        sampled(T, 10_000);
+        // 10_000 + 2_000 topped up - 8_000 left = 4_000 burned in 12 hours
+        topped_up(1, 2_000, T + HOUR_NS);
+        let status = status_at(T + 12 * HOUR_NS, 8_000);
+
+        assert_eq!(status.burn_rate_per_day, Some(8_000));
+        // 7_000 above the reserve lasts 21 more hours
+        assert_eq!(status.projected_freeze_at, Some(T + 33 * HOUR_NS));
+        assert!(!status.below_threshold);
+    }
+
+    #[test]
+    fn no_projection_without_burn() {
+        sampled(T, 1_000);
+        let status = status_at(T + 2 * HOUR_NS, 2_000);
+        assert_eq!(status.burn_rate_per_day, Some(0));
+        assert_eq!(status.projected_freeze_at, None);
+        assert!(status.below_threshold);
+    }
+
+    #[test]
+    fn contributors_are_ranked_by_total() {
+        topped_up(1, 100, 1);
+        topped_up(2, 250, 2);
+        topped_up(1, 200, 3);
+
+        let contributors = contributors();
+        let ranked = contributors
+            .iter()
+            .map(|contributor| {
+                (
+                    contributor.total,
+                    contributor.top_ups,
+                    contributor.last_top_up_at,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, [(300, 2, 3), (250, 1, 2)]);
+
+        assert_eq!(top_ups(1, 1)[0].amount, 250);
+        assert_eq!(top_ups(0, u64::MAX).len(), 3);
+    }
+}
@@ -0,0 +1,303 @@
+use candid::Principal;
+use sha2::{Digest, Sha256};
+
+use crate::notifier;
+use crate::types::{EmailFailures, EmailVerification, EmailVerificationStatus};
+use crate::{EMAIL_FAILURES, EMAIL_VERIFICATIONS};
+
+const CODE_TTL_NS: u64 = 15 * 60 * 1_000_000_000;
+const RESEND_COOLDOWN_NS: u64 = 60 * 1_000_000_000;
+const MAX_ATTEMPTS: u32 = 5;
+const CODE_DIGITS: usize = 6;
+/// Wrong codes an address may see over all codes sent to it before it is
+/// locked; resending resets `MAX_ATTEMPTS` but not this.
+const MAX_FAILURES_PER_EMAIL: u32 = 20;
+const LOCKOUT_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+fn get(principal: &Principal) -> Option<EmailVerification> {
+    EMAIL_VERIFICATIONS.with(|pending| pending.borrow().get(principal))
+}
+
+fn hash_code(salt: &[u8], principal: &Principal, code: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(principal.as_slice());
+    hasher.update(code.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn to_status(pending: &EmailVerification) -> EmailVerificationStatus {
+    EmailVerificationStatus {
+        email: pending.email.clone(),
+        expires_at: pending.expires_at,
+        attempts_remaining: MAX_ATTEMPTS.saturating_sub(pending.attempts),
+    }
+}
+
+// Keyed by a hash so that the address itself is not kept after the
+// account is erased
+fn email_key(email: &str) -> [u8; 32] {
+    Sha256::digest(email.trim().to_lowercase().as_bytes()).into()
+}
+
+fn failures(email: &str) -> EmailFailures {
+    EMAIL_FAILURES
+        .with(|failures| failures.borrow().get(&email_key(email)))
+        .unwrap_or_default()
+}
+
+fn check_lockout(email: &str, now: u64) -> Result<(), String> {
+    match failures(email).locked_until {
+        Some(locked_until) if now < locked_until => Err(lockout_error(locked_until, now)),
+        _ => Ok(()),
+    }
+}
+
+fn lockout_error(locked_until: u64, now: u64) -> String {
+    format!(
+        "Too many incorrect codes for this email, try again in {} hours",
+        (locked_until - now).div_ceil(60 * 60 * 1_000_000_000)
+    )
+}
+
+/// Counts one more wrong code for an address, locking it once the total
+/// reaches `MAX_FAILURES_PER_EMAIL`. The count starts over after a lockout.
+fn add_failure(mut entry: EmailFailures, now: u64) -> EmailFailures {
+    if entry
+        .locked_until
+        .is_some_and(|locked_until| now >= locked_until)
+    {
+        entry = EmailFailures::default();
+    }
+    entry.failures += 1;
+    entry.last_failure_at = now;
+    if entry.failures >= MAX_FAILURES_PER_EMAIL {
+        entry.locked_until = Some(now + LOCKOUT_NS);
+    }
+    entry
+}
+
+fn check_cooldown(principal: &Principal, now: u64) -> Result<(), String> {
+    match get(principal) {
+        Some(pending) if now < pending.created_at + RESEND_COOLDOWN_NS => {
+            Err("A code was sent recently, please wait before requesting another".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Generates a one-time code for `email`, replacing any outstanding one, and
+/// sends it through the notifier. Only a salted hash of the code is stored.
+pub async fn issue_code(
+    principal: Principal,
+    email: String,
+) -> Result<EmailVerificationStatus, String> {
+    check_cooldown(&principal, ic_cdk::api::time())?;
+    check_lockout(&email, ic_cdk::api::time())?;
+
+    let (random,): (Vec<u8>,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(code, message)| format!("Failed to get randomness: {:?} {}", code, message))?;
+
+    // Another request may have issued a code while we were waiting
+    let now = ic_cdk::api::time();
+    check_cooldown(&principal, now)?;
+
+    let number = u32::from_be_bytes([random[0], random[1], random[2], random[3]]);
+    let code = format!("{:0width$}", number % 1_000_000, width = CODE_DIGITS);
+    let salt = random[4..20].to_vec();
+
+    let pending = EmailVerification {
+        email: email.clone(),
+        code_hash: hash_code(&salt, &principal, &code),
+        salt,
+        attempts: 0,
+        created_at: now,
+        expires_at: now + CODE_TTL_NS,
+    };
+    EMAIL_VERIFICATIONS.with(|verifications| {
+        verifications
+            .borrow_mut()
+            .insert(principal, pending.clone());
+    });
+
+    let body = format!(
+        "Your HarvestX verification code is {}. It expires in {} minutes.",
+        code,
+        CODE_TTL_NS / 60_000_000_000
+    );
+    let idempotency_key = format!("email-verification-{}-{}", principal.to_text(), now);
+
+    if let Err(error) =
+        notifier::send_email(&email, "Verify your email", &body, &idempotency_key).await
+    {
+        clear(&principal);
+        return Err(error);
+    }
+
+    Ok(to_status(&pending))
+}
+
+/// Checks `code` against the outstanding code for `principal`, which must
+/// have been issued for `email`. Wrong guesses count towards the attempt limit.
+pub fn verify_code(principal: Principal, email: &str, code: &str) -> Result<(), String> {
+    check_code(principal, email, code, ic_cdk::api::time())
+}
+
+fn check_code(principal: Principal, email: &str, code: &str, now: u64) -> Result<(), String> {
+    let mut pending = get(&principal).ok_or("No verification code has been requested")?;
+
+    if now >= pending.expires_at {
+        clear(&principal);
+        return Err("Verification code has expired".to_string());
+    }
+    if pending.email != email {
+        clear(&principal);
+        return Err("Email changed since the code was sent".to_string());
+    }
+    if pending.attempts >= MAX_ATTEMPTS {
+        return Err("Too many incorrect attempts, please request a new code".to_string());
+    }
+    check_lockout(email, now)?;
+
+    if hash_code(&pending.salt, &principal, code.trim()) != pending.code_hash {
+        let entry = add_failure(failures(email), now);
+        let locked_until = entry.locked_until;
+        EMAIL_FAILURES.with(|failures| failures.borrow_mut().insert(email_key(email), entry));
+        if let Some(locked_until) = locked_until {
+            clear(&principal);
+            return Err(lockout_error(locked_until, now));
+        }
+
+        pending.attempts += 1;
+        let remaining = MAX_ATTEMPTS - pending.attempts;
+        EMAIL_VERIFICATIONS.with(|verifications| {
+            verifications.borrow_mut().insert(principal, pending);
+        });
+        return Err(format!(
+            "Incorrect verification code, {} attempts remaining",
+            remaining
+        ));
+    }
+
+    clear(&principal);
+    EMAIL_FAILURES.with(|failures| failures.borrow_mut().remove(&email_key(email)));
+    Ok(())
+}
+
+pub fn status(principal: &Principal) -> Option<EmailVerificationStatus> {
+    get(principal)
+        .filter(|pending| ic_cdk::api::time() < pending.expires_at)
+        .map(|pending| to_status(&pending))
+}
+
+/// Drops any outstanding code, e.g. when the email it was sent to changes.
+pub fn clear(principal: &Principal) {
+    EMAIL_VERIFICATIONS.with(|verifications| verifications.borrow_mut().remove(principal));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "farmer@example.com";
+    const CODE: &str = "123456";
+
+    fn principal() -> Principal {
+        Principal::from_slice(&[1])
+    }
+
+    // Stores a code the way `issue_code` does, without the randomness
+    fn issue(now: u64) {
+        let salt = vec![7; 16];
+        EMAIL_VERIFICATIONS.with(|verifications| {
+            verifications.borrow_mut().insert(
+                principal(),
+                EmailVerification {
+                    email: EMAIL.to_string(),
+                    code_hash: hash_code(&salt, &principal(), CODE),
+                    salt,
+                    attempts: 0,
+                    created_at: now,
+                    expires_at: now + CODE_TTL_NS,
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn accepts_the_code_once() {
+        issue(0);
+        assert!(check_code(principal(), EMAIL, " 123456 ", 1).is_ok());
+        assert!(check_code(principal(), EMAIL, CODE, 2).is_err());
+    }
+
+    #[test]
+    fn code_expires_after_its_ttl() {
+        issue(0);
+        assert_eq!(
+            check_code(principal(), EMAIL, CODE, CODE_TTL_NS),
+            Err("Verification code has expired".to_string())
+        );
+        assert!(get(&principal()).is_none());
+    }
+
+    #[test]
+    fn code_is_bound_to_the_email_it_was_sent_to() {
+        issue(0);
+        assert!(check_code(principal(), "other@example.com", CODE, 1).is_err());
+        assert!(get(&principal()).is_none());
+    }
+
+    #[test]
+    fn locks_the_code_after_max_attempts() {
+        issue(0);
+        for attempt in 1..=MAX_ATTEMPTS {
+            let error = check_code(principal(), EMAIL, "000000", attempt as u64).unwrap_err();
+            assert!(error.contains(&format!("{} attempts remaining", MAX_ATTEMPTS - attempt)));
+        }
+        assert_eq!(
+            check_code(principal(), EMAIL, CODE, 10),
+            Err("Too many incorrect attempts, please request a new code".to_string())
+        );
+    }
+
+    #[test]
+    fn failures_add_up_across_codes_until_the_email_is_locked() {
+        let mut now = 0;
+        for _ in 0..MAX_FAILURES_PER_EMAIL / MAX_ATTEMPTS {
+            // A resend after the cooldown starts a fresh code
+            now += RESEND_COOLDOWN_NS;
+            assert!(check_cooldown(&principal(), now).is_ok());
+            issue(now);
+            for _ in 0..MAX_ATTEMPTS {
+                assert!(check_code(principal(), EMAIL, "000000", now).is_err());
+            }
+        }
+
+        assert_eq!(failures(EMAIL).locked_until, Some(now + LOCKOUT_NS));
+        assert!(get(&principal()).is_none());
+        assert!(check_lockout(EMAIL, now + LOCKOUT_NS - 1).is_err());
+        // The address is matched however it is written
+        assert!(check_lockout(" Farmer@Example.com", now).is_err());
+        assert!(check_lockout(EMAIL, now + LOCKOUT_NS).is_ok());
+    }
+
+    #[test]
+    fn count_starts_over_after_a_lockout_or_a_success() {
+        let locked = EmailFailures {
+            failures: MAX_FAILURES_PER_EMAIL,
+            locked_until: Some(100),
+            last_failure_at: 0,
+        };
+        let entry = add_failure(locked, 100);
+        assert_eq!(entry.failures, 1);
+        assert_eq!(entry.locked_until, None);
+
+        issue(0);
+        assert!(check_code(principal(), EMAIL, "000000", 1).is_err());
+        assert_eq!(failures(EMAIL).failures, 1);
+        assert!(check_code(principal(), EMAIL, CODE, 2).is_ok());
+        assert_eq!(failures(EMAIL), EmailFailures::default());
+    }
+}
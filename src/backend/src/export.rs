@@ -0,0 +1,235 @@
+use candid::Principal;
+
+use crate::types::{
+    DataExport, DataExportPage, ExportCursor, ExportFormat, ExportSection, ExportedMessage,
+    InvestmentRequest, MessageDirection, Transaction,
+};
+use crate::{archive, kyc, linking};
+use crate::{AUDIT_LOG, OFFERS, REQUESTS, TRANSACTIONS, USERS};
+
+/// Bumped whenever the shape of `DataExportPage` changes.
+pub const EXPORT_FORMAT_VERSION: u32 = 3;
+
+pub const DEFAULT_PAGE_SIZE: u64 = 100;
+pub const MAX_PAGE_SIZE: u64 = 500;
+
+const SECTIONS: [ExportSection; 6] = [
+    ExportSection::Profile,
+    ExportSection::Offers,
+    ExportSection::Requests,
+    ExportSection::Transactions,
+    ExportSection::Messages,
+    ExportSection::AuditEntries,
+];
+
+/// Builds one page of everything the platform holds about `subject`.
+///
+/// Sections are exported in a fixed order and a page may span several of
+/// them; `page_size` bounds the number of items across all sections.
+pub async fn export_page(
+    subject: Principal,
+    cursor: Option<ExportCursor>,
+    page_size: u64,
+    format: ExportFormat,
+) -> Result<DataExport, String> {
+    let page = build_page(subject, cursor, page_size.clamp(1, MAX_PAGE_SIZE)).await?;
+
+    match format {
+        ExportFormat::Candid => Ok(DataExport::Candid(Box::new(page))),
+        ExportFormat::Json => serde_json::to_string(&page)
+            .map(DataExport::Json)
+            .map_err(|e| format!("Failed to serialize export: {}", e)),
+    }
+}
+
+async fn build_page(
+    subject: Principal,
+    cursor: Option<ExportCursor>,
+    page_size: u64,
+) -> Result<DataExportPage, String> {
+    let cursor = cursor.unwrap_or(ExportCursor {
+        section: ExportSection::Profile,
+        offset: 0,
+    });
+    let first = SECTIONS
+        .iter()
+        .position(|section| *section == cursor.section)
+        .unwrap_or(0);
+
+    let mut page = DataExportPage {
+        version: EXPORT_FORMAT_VERSION,
+        subject,
+        generated_at: ic_cdk::api::time(),
+        profile: None,
+        kyc: None,
+        linked_principals: Vec::new(),
+        offers: Vec::new(),
+        requests: Vec::new(),
+        transactions: Vec::new(),
+        messages: Vec::new(),
+        audit_entries: Vec::new(),
+        next_cursor: None,
+    };
+    let mut remaining = page_size;
+    let mut offset = cursor.offset;
+
+    // `remaining` is at least one at the top of every iteration
+    for (position, section) in SECTIONS.iter().enumerate().skip(first) {
+        let total = match section {
+            ExportSection::Profile => {
+                let profile = USERS.with(|users| users.borrow().get(&subject));
+                let total = profile.is_some() as u64;
+                if offset == 0 {
+                    page.profile = profile;
+                    page.kyc = kyc::get(&subject);
+                    page.linked_principals = linking::linked_principals(&subject);
+                }
+                total
+            }
+            ExportSection::Offers => {
+                let offers = OFFERS.with(|offers| {
+                    offers
+                        .borrow()
+                        .iter()
+                        .filter(|(_, offer)| offer.farmer == subject)
+                        .map(|(_, offer)| offer)
+                        .collect::<Vec<_>>()
+                });
+                let total = offers.len() as u64;
+                page.offers = slice(offers, offset, remaining);
+                total
+            }
+            ExportSection::Requests => {
+                let requests = subject_requests(subject).await?;
+                let total = requests.len() as u64;
+                page.requests = slice(requests, offset, remaining);
+                total
+            }
+            ExportSection::Transactions => {
+                let transactions = subject_transactions(subject).await?;
+                let total = transactions.len() as u64;
+                page.transactions = slice(transactions, offset, remaining);
+                total
+            }
+            ExportSection::Messages => {
+                let messages = subject_messages(subject).await?;
+                let total = messages.len() as u64;
+                page.messages = slice(messages, offset, remaining);
+                total
+            }
+            ExportSection::AuditEntries => {
+                let subject_text = subject.to_text();
+                let entries = AUDIT_LOG.with(|log| {
+                    let log = log.borrow();
+                    (0..log.len())
+                        .filter_map(|index| log.get(index))
+                        .filter(|entry| entry.actor == subject || entry.entity_id == subject_text)
+                        .collect::<Vec<_>>()
+                });
+                let total = entries.len() as u64;
+                page.audit_entries = slice(entries, offset, remaining);
+                total
+            }
+        };
+
+        let taken = total.saturating_sub(offset).min(remaining);
+        remaining -= taken;
+
+        if offset + taken < total {
+            page.next_cursor = Some(ExportCursor {
+                section: *section,
+                offset: offset + taken,
+            });
+            break;
+        }
+
+        offset = 0;
+        if remaining == 0 && position + 1 < SECTIONS.len() {
+            page.next_cursor = Some(ExportCursor {
+                section: SECTIONS[position + 1],
+                offset: 0,
+            });
+            break;
+        }
+    }
+
+    Ok(page)
+}
+
+fn slice<T>(items: Vec<T>, offset: u64, limit: u64) -> Vec<T> {
+    items
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect()
+}
+
+// Requests the subject made, including archived ones
+async fn subject_requests(subject: Principal) -> Result<Vec<InvestmentRequest>, String> {
+    let mut requests = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .filter(|(_, req)| req.investor == subject)
+            .map(|(_, req)| req)
+            .collect::<Vec<_>>()
+    });
+    requests.extend(archive::fetch_requests(subject).await?);
+    Ok(requests)
+}
+
+// Transactions the subject is a party to, including archived ones
+async fn subject_transactions(subject: Principal) -> Result<Vec<Transaction>, String> {
+    let mut transactions = TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .filter(|(_, txn)| txn.farmer == subject || txn.investor == subject)
+            .map(|(_, txn)| txn)
+            .collect::<Vec<_>>()
+    });
+    transactions.extend(archive::fetch_transactions(subject).await?);
+    Ok(transactions)
+}
+
+// Messages attached to requests the subject sent, or received on their offers
+async fn subject_messages(subject: Principal) -> Result<Vec<ExportedMessage>, String> {
+    let mut messages = subject_requests(subject)
+        .await?
+        .into_iter()
+        .filter(|req| !req.message.is_empty())
+        .map(|req| ExportedMessage {
+            request_id: req.id,
+            offer_id: req.offer_id,
+            direction: MessageDirection::Sent,
+            message: req.message,
+            created_at: req.created_at,
+        })
+        .collect::<Vec<_>>();
+
+    let own_offers = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .iter()
+            .filter(|(_, offer)| offer.farmer == subject)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+    });
+    let received = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .filter(|(_, req)| own_offers.contains(&req.offer_id) && !req.message.is_empty())
+            .map(|(_, req)| ExportedMessage {
+                request_id: req.id,
+                offer_id: req.offer_id,
+                direction: MessageDirection::Received,
+                message: req.message,
+                created_at: req.created_at,
+            })
+            .collect::<Vec<_>>()
+    });
+    messages.extend(received);
+
+    Ok(messages)
+}
@@ -0,0 +1,315 @@
+use candid::Principal;
+
+use crate::types::{
+    FeeKind, FeeLine, FeeRevenueReport, FeeSchedule, ProductRevenue, ProductType,
+    TransactionStatus, TreasuryEntryKind,
+};
+use crate::{settings, treasury};
+use crate::{TRANSACTIONS, TREASURY_LEDGER};
+
+const MAX_BPS: u32 = 10_000;
+const MAX_TIERS: usize = 16;
+const MAX_PRODUCT_RATES: usize = 16;
+/// Trades older than this do not count towards a farmer's volume tier.
+const VOLUME_WINDOW_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+pub fn validate_schedule(schedule: &FeeSchedule) -> Result<(), String> {
+    let rates = std::iter::once(schedule.percentage_bps)
+        .chain(schedule.volume_tiers.iter().map(|tier| tier.percentage_bps))
+        .chain(
+            schedule
+                .product_rates
+                .iter()
+                .map(|rate| rate.percentage_bps),
+        );
+    for bps in rates {
+        if bps > MAX_BPS {
+            return Err("Fee percentages must be at most 10000 basis points".to_string());
+        }
+    }
+    if !schedule.flat_fee.is_finite() || schedule.flat_fee < 0.0 {
+        return Err("Flat fee must be a non-negative number".to_string());
+    }
+
+    if schedule.volume_tiers.len() > MAX_TIERS {
+        return Err(format!("At most {} volume tiers are allowed", MAX_TIERS));
+    }
+    for tier in &schedule.volume_tiers {
+        if !tier.min_amount.is_finite() || tier.min_amount < 0.0 {
+            return Err("Volume tier amounts must be non-negative numbers".to_string());
+        }
+    }
+    if schedule
+        .volume_tiers
+        .windows(2)
+        .any(|pair| pair[0].min_amount >= pair[1].min_amount)
+    {
+        return Err("Volume tiers must be sorted by increasing amount".to_string());
+    }
+
+    if schedule.product_rates.len() > MAX_PRODUCT_RATES {
+        return Err(format!(
+            "At most {} product rates are allowed",
+            MAX_PRODUCT_RATES
+        ));
+    }
+    for (position, rate) in schedule.product_rates.iter().enumerate() {
+        if schedule.product_rates[..position]
+            .iter()
+            .any(|other| other.product_type == rate.product_type)
+        {
+            return Err(format!("Duplicate rate for {:?}", rate.product_type));
+        }
+    }
+    Ok(())
+}
+
+/// Value the farmer traded over the last 30 days, refunds excluded. Older
+/// trades may have been archived, which does not affect the window.
+fn trailing_volume(farmer: &Principal) -> f64 {
+    let since = ic_cdk::api::time().saturating_sub(VOLUME_WINDOW_NS);
+    TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .filter(|(_, txn)| txn.farmer == *farmer && txn.created_at >= since)
+            .filter(|(_, txn)| txn.status != TransactionStatus::Refunded)
+            .map(|(_, txn)| txn.total_amount)
+            .sum()
+    })
+}
+
+// `volume` is only computed when the schedule has volume tiers
+fn percentage_bps(
+    schedule: &FeeSchedule,
+    product_type: &ProductType,
+    volume: impl FnOnce() -> f64,
+) -> u32 {
+    if let Some(rate) = schedule
+        .product_rates
+        .iter()
+        .find(|rate| rate.product_type == *product_type)
+    {
+        return rate.percentage_bps;
+    }
+    if schedule.volume_tiers.is_empty() {
+        return schedule.percentage_bps;
+    }
+    let volume = volume();
+    schedule
+        .volume_tiers
+        .iter()
+        .rev()
+        .find(|tier| volume >= tier.min_amount)
+        .map_or(schedule.percentage_bps, |tier| tier.percentage_bps)
+}
+
+/// Fee lines for a trade of `farmer`'s worth `amount` under the current
+/// schedule. The fees never add up to more than the trade itself.
+pub fn compute(farmer: &Principal, product_type: &ProductType, amount: f64) -> Vec<FeeLine> {
+    let schedule = settings::get().fee_schedule;
+    // The trade being priced counts towards the volume
+    let bps = percentage_bps(&schedule, product_type, || trailing_volume(farmer) + amount);
+    fee_lines(&schedule, bps, amount)
+}
+
+fn fee_lines(schedule: &FeeSchedule, bps: u32, amount: f64) -> Vec<FeeLine> {
+    let mut remaining = amount;
+    let mut lines = Vec::new();
+
+    let percentage = (amount * bps as f64 / MAX_BPS as f64).min(remaining);
+    if percentage > 0.0 {
+        remaining -= percentage;
+        lines.push(FeeLine {
+            kind: FeeKind::Percentage { bps },
+            amount: percentage,
+        });
+    }
+
+    let flat = schedule.flat_fee.min(remaining);
+    if flat > 0.0 {
+        lines.push(FeeLine {
+            kind: FeeKind::Flat,
+            amount: flat,
+        });
+    }
+
+    lines
+}
+
+pub fn total(lines: &[FeeLine]) -> f64 {
+    lines.iter().map(|line| line.amount).sum()
+}
+
+/// Fee income and refunds booked in `[from, to)` in the treasury
+/// currency, plus the fees still expected from unsettled transactions.
+pub fn revenue(from: u64, to: u64) -> FeeRevenueReport {
+    let currency = treasury::get_config().currency;
+    let mut report = FeeRevenueReport {
+        from,
+        to,
+        currency: currency.clone(),
+        fees_collected: 0.0,
+        fees_refunded: 0.0,
+        net_fees: 0.0,
+        settled_transactions: 0,
+        by_product_type: Vec::new(),
+        outstanding_fees: 0.0,
+    };
+
+    TREASURY_LEDGER.with(|ledger| {
+        for (_, entry) in ledger.borrow().iter() {
+            if entry.timestamp < from || entry.timestamp >= to || entry.currency != currency {
+                continue;
+            }
+            let signed = match entry.kind {
+                TreasuryEntryKind::FeeIncome => {
+                    report.fees_collected += entry.amount;
+                    report.settled_transactions += 1;
+                    entry.amount
+                }
+                TreasuryEntryKind::FeeRefund => {
+                    report.fees_refunded += entry.amount;
+                    -entry.amount
+                }
+                _ => continue,
+            };
+            if let Some(product_type) = entry.product_type {
+                match report
+                    .by_product_type
+                    .iter_mut()
+                    .find(|revenue| revenue.product_type == product_type)
+                {
+                    Some(revenue) => revenue.fees += signed,
+                    None => report.by_product_type.push(ProductRevenue {
+                        product_type,
+                        fees: signed,
+                    }),
+                }
+            }
+        }
+    });
+    report.net_fees = report.fees_collected - report.fees_refunded;
+
+    report.outstanding_fees = TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .filter(|(_, txn)| {
+                matches!(
+                    txn.status,
+                    TransactionStatus::Confirmed | TransactionStatus::Tokenized
+                )
+            })
+            .map(|(_, txn)| total(txn.fee_lines()))
+            .sum()
+    });
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ProductFeeRate, VolumeTier};
+
+    fn schedule() -> FeeSchedule {
+        FeeSchedule {
+            percentage_bps: 200,
+            flat_fee: 1.0,
+            volume_tiers: vec![
+                VolumeTier {
+                    min_amount: 1_000.0,
+                    percentage_bps: 150,
+                },
+                VolumeTier {
+                    min_amount: 10_000.0,
+                    percentage_bps: 100,
+                },
+            ],
+            product_rates: vec![ProductFeeRate {
+                product_type: ProductType::Nuts,
+                percentage_bps: 300,
+            }],
+        }
+    }
+
+    #[test]
+    fn product_rates_take_precedence_over_tiers() {
+        let bps = percentage_bps(&schedule(), &ProductType::Nuts, || {
+            panic!("volume is not needed")
+        });
+        assert_eq!(bps, 300);
+    }
+
+    #[test]
+    fn highest_tier_reached_applies() {
+        let bps = |volume: f64| percentage_bps(&schedule(), &ProductType::Grains, || volume);
+        assert_eq!(bps(999.0), 200);
+        assert_eq!(bps(1_000.0), 150);
+        assert_eq!(bps(9_999.0), 150);
+        assert_eq!(bps(50_000.0), 100);
+
+        let flat = FeeSchedule {
+            volume_tiers: vec![],
+            ..schedule()
+        };
+        assert_eq!(
+            percentage_bps(&flat, &ProductType::Grains, || 50_000.0),
+            200
+        );
+    }
+
+    #[test]
+    fn fees_never_exceed_the_trade() {
+        let lines = fee_lines(&schedule(), 200, 100.0);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(total(&lines), 3.0);
+
+        // The flat fee takes what the percentage leaves
+        let lines = fee_lines(&schedule(), 9_000, 10.0);
+        assert!(matches!(lines[1].kind, FeeKind::Flat));
+        assert_eq!(lines[1].amount, 1.0);
+        assert_eq!(total(&fee_lines(&schedule(), 9_500, 10.0)), 10.0);
+        assert_eq!(total(&fee_lines(&schedule(), 10_000, 10.0)), 10.0);
+        assert_eq!(fee_lines(&schedule(), 10_000, 10.0).len(), 1);
+
+        let free = FeeSchedule {
+            flat_fee: 0.0,
+            ..schedule()
+        };
+        assert!(fee_lines(&free, 0, 100.0).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        assert!(validate_schedule(&schedule()).is_ok());
+        assert!(validate_schedule(&FeeSchedule::default()).is_ok());
+
+        let mut over = schedule();
+        over.volume_tiers[1].percentage_bps = MAX_BPS + 1;
+        let mut unsorted = schedule();
+        unsorted.volume_tiers.reverse();
+        let mut duplicate = schedule();
+        duplicate.product_rates.push(ProductFeeRate {
+            product_type: ProductType::Nuts,
+            percentage_bps: 100,
+        });
+        let invalid = [
+            over,
+            unsorted,
+            duplicate,
+            FeeSchedule {
+                flat_fee: -1.0,
+                ..schedule()
+            },
+            FeeSchedule {
+                flat_fee: f64::INFINITY,
+                ..schedule()
+            },
+        ];
+        for schedule in invalid {
+            assert!(validate_schedule(&schedule).is_err(), "{:?}", schedule);
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use candid::{Func, Nat, Principal};
+use ic_certification::Hash;
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+
+use crate::types::{
+    ArchivedBlocks, BlockWithId, ConfigureArchiveRequest, EntityType, GetBlocksArgs, GetBlocksFn,
+    GetBlocksResult, Icrc3ArchiveInfo, Icrc3State, Icrc3Value, SupportedBlockType, Transaction,
+};
+use crate::{archive, audit, certification, fees};
+use crate::{ICRC3_BLOCKS, ICRC3_STATE};
+
+/// Block type for a trade created by accepting an investment request.
+pub const TRADE_BLOCK_TYPE: &str = "hx_trade";
+
+/// Block type for a transaction refunded through an admin proposal.
+pub const REFUND_BLOCK_TYPE: &str = "hx_refund";
+
+/// Block type for a transaction settled after delivery.
+pub const SETTLE_BLOCK_TYPE: &str = "hx_settle";
+
+const BLOCK_TYPES_URL: &str = "https://github.com/danielchukwuu/Lexfund#icrc-3-transaction-log";
+
+// Prices and amounts are `f64` on `Transaction`; blocks carry them as
+// naturals in millionths so that they hash deterministically.
+const AMOUNT_SCALE: f64 = 1_000_000.0;
+
+/// Most local blocks returned by one `icrc3_get_blocks` call, over all
+/// ranges; the same cap the archive canister applies.
+const MAX_BLOCKS_PER_RESPONSE: u64 = 1_000;
+
+thread_local! {
+    static ARCHIVING: Cell<bool> = const { Cell::new(false) };
+}
+
+impl Icrc3Value {
+    /// Representation-independent hash as defined by ICRC-3.
+    pub fn hash(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        match self {
+            Icrc3Value::Blob(bytes) => hasher.update(bytes),
+            Icrc3Value::Text(text) => hasher.update(text.as_bytes()),
+            Icrc3Value::Nat(nat) => {
+                let mut buf = Vec::new();
+                nat.encode(&mut buf).expect("failed to encode nat");
+                hasher.update(buf);
+            }
+            Icrc3Value::Int(int) => {
+                let mut buf = Vec::new();
+                int.encode(&mut buf).expect("failed to encode int");
+                hasher.update(buf);
+            }
+            Icrc3Value::Array(values) => {
+                for value in values {
+                    hasher.update(value.hash());
+                }
+            }
+            Icrc3Value::Map(entries) => {
+                let mut pairs = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let mut pair = Sha256::digest(key.as_bytes()).to_vec();
+                        pair.extend_from_slice(&value.hash());
+                        pair
+                    })
+                    .collect::<Vec<_>>();
+                pairs.sort();
+                for pair in pairs {
+                    hasher.update(pair);
+                }
+            }
+        }
+        hasher.finalize().into()
+    }
+}
+
+fn nat(value: u64) -> Icrc3Value {
+    Icrc3Value::Nat(Nat::from(value))
+}
+
+fn text(value: &str) -> Icrc3Value {
+    Icrc3Value::Text(value.to_string())
+}
+
+fn principal(value: &Principal) -> Icrc3Value {
+    Icrc3Value::Blob(value.as_slice().to_vec())
+}
+
+fn scaled(value: f64) -> Icrc3Value {
+    nat((value * AMOUNT_SCALE).round() as u64)
+}
+
+fn transaction_tx(transaction: &Transaction) -> Icrc3Value {
+    let mut fields = vec![
+        ("id".to_string(), text(&transaction.id)),
+        ("offer".to_string(), text(&transaction.offer_id)),
+        ("request".to_string(), text(&transaction.request_id)),
+        ("farmer".to_string(), principal(&transaction.farmer)),
+        ("investor".to_string(), principal(&transaction.investor)),
+        ("qty".to_string(), nat(transaction.quantity)),
+        ("price_e6".to_string(), scaled(transaction.price_per_kg)),
+        ("amt_e6".to_string(), scaled(transaction.total_amount)),
+    ];
+    if let Some(organization) = &transaction.organization {
+        fields.push(("org".to_string(), text(organization)));
+    }
+    if !transaction.fee_lines().is_empty() {
+        fields.push((
+            "fee_e6".to_string(),
+            scaled(fees::total(transaction.fee_lines())),
+        ));
+    }
+    Icrc3Value::Map(fields)
+}
+
+/// Appends a block recording `transaction` under the given block type.
+pub fn append_transaction_block(block_type: &str, transaction: &Transaction) {
+    append_block(block_type, transaction_tx(transaction));
+}
+
+fn append_block(block_type: &str, tx: Icrc3Value) {
+    let mut state = get_state();
+
+    let mut fields = Vec::new();
+    if let Some(phash) = &state.last_block_hash {
+        fields.push(("phash".to_string(), Icrc3Value::Blob(phash.clone())));
+    }
+    fields.push(("btype".to_string(), text(block_type)));
+    fields.push(("ts".to_string(), nat(ic_cdk::api::time())));
+    fields.push(("tx".to_string(), tx));
+
+    let block = Icrc3Value::Map(fields);
+    let hash = block.hash();
+    let index = state.log_length;
+
+    ICRC3_BLOCKS.with(|blocks| blocks.borrow_mut().insert(index, block));
+    state.log_length += 1;
+    state.last_block_hash = Some(hash.to_vec());
+    set_state(state);
+
+    certification::certify_tip(index, hash);
+    maybe_archive();
+}
+
+pub fn get_state() -> Icrc3State {
+    ICRC3_STATE.with(|state| state.borrow().get().clone())
+}
+
+pub fn set_state(state: Icrc3State) {
+    ICRC3_STATE.with(|cell| {
+        cell.borrow_mut()
+            .set(Icrc3State {
+                archiving: None,
+                ..state
+            })
+            .expect("failed to write ICRC-3 state");
+    });
+}
+
+/// The stored state with whether blocks are being archived.
+pub fn status() -> Icrc3State {
+    Icrc3State {
+        archiving: Some(ARCHIVING.with(|archiving| archiving.get())),
+        ..get_state()
+    }
+}
+
+/// Registers the archive canister and sets when and how much to archive.
+pub fn configure_archive(
+    actor: Principal,
+    request: ConfigureArchiveRequest,
+) -> Result<Icrc3State, String> {
+    if request.archive_threshold == 0 || request.archive_batch_size == 0 {
+        return Err("Archive threshold and batch size must be positive".to_string());
+    }
+
+    let mut state = get_state();
+    let before = state.clone();
+
+    // Blocks already moved out would become unreachable
+    if state.first_local_index > 0 && state.archive_canister != Some(request.archive_canister) {
+        return Err("Archive canister cannot be changed once blocks are archived".to_string());
+    }
+
+    state.archive_canister = Some(request.archive_canister);
+    state.archive_threshold = request.archive_threshold;
+    state.archive_batch_size = request.archive_batch_size;
+    set_state(state.clone());
+    audit::record(
+        actor,
+        "configure_icrc3_archive",
+        EntityType::Config,
+        "icrc3_archive",
+        Some(&before),
+        Some(&state),
+    );
+    maybe_archive();
+
+    Ok(status())
+}
+
+/// Index and hash of the latest block, used to restore the certified tip.
+pub fn tip() -> Option<(u64, Hash)> {
+    let state = get_state();
+    let hash = state.last_block_hash?;
+    Some((state.log_length - 1, hash.try_into().ok()?))
+}
+
+pub fn get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let state = get_state();
+    let mut blocks = Vec::new();
+    let mut archived_args = Vec::new();
+
+    for arg in args {
+        let start = to_u64(&arg.start).min(state.log_length);
+        let length = to_u64(&arg.length).min(MAX_BLOCKS_PER_RESPONSE);
+        let end = start.saturating_add(length).min(state.log_length);
+
+        if start < state.first_local_index {
+            let archived_end = end.min(state.first_local_index);
+            archived_args.push(GetBlocksArgs {
+                start: Nat::from(start),
+                length: Nat::from(archived_end - start),
+            });
+        }
+
+        ICRC3_BLOCKS.with(|stored| {
+            let stored = stored.borrow();
+            let local_start = start.max(state.first_local_index);
+            let remaining = MAX_BLOCKS_PER_RESPONSE - blocks.len() as u64;
+            let local_end = end.min(local_start.saturating_add(remaining));
+            for id in local_start..local_end {
+                if let Some(block) = stored.get(&id) {
+                    blocks.push(BlockWithId {
+                        id: Nat::from(id),
+                        block,
+                    });
+                }
+            }
+        });
+    }
+
+    let archived_blocks = match (state.archive_canister, archived_args.is_empty()) {
+        (Some(archive), false) => vec![ArchivedBlocks {
+            args: archived_args,
+            callback: GetBlocksFn(Func {
+                principal: archive,
+                method: "icrc3_get_blocks".to_string(),
+            }),
+        }],
+        _ => Vec::new(),
+    };
+
+    GetBlocksResult {
+        log_length: Nat::from(state.log_length),
+        blocks,
+        archived_blocks,
+    }
+}
+
+pub fn get_archives(from: Option<Principal>) -> Vec<Icrc3ArchiveInfo> {
+    let state = get_state();
+    match state.archive_canister {
+        Some(archive) if state.first_local_index > 0 && from.is_none_or(|from| from < archive) => {
+            vec![Icrc3ArchiveInfo {
+                canister_id: archive,
+                start: Nat::from(0u64),
+                end: Nat::from(state.first_local_index - 1),
+            }]
+        }
+        _ => Vec::new(),
+    }
+}
+
+pub fn supported_block_types() -> Vec<SupportedBlockType> {
+    [TRADE_BLOCK_TYPE, REFUND_BLOCK_TYPE, SETTLE_BLOCK_TYPE]
+        .iter()
+        .map(|block_type| SupportedBlockType {
+            block_type: block_type.to_string(),
+            url: BLOCK_TYPES_URL.to_string(),
+        })
+        .collect()
+}
+
+/// Starts moving the oldest local blocks to the archive canister if the
+/// local log has grown past the configured threshold.
+pub fn maybe_archive() {
+    let state = get_state();
+    let local_blocks = state.log_length - state.first_local_index;
+
+    if state.archive_canister.is_some()
+        && local_blocks > state.archive_threshold
+        && !ARCHIVING.with(|archiving| archiving.get())
+    {
+        ic_cdk::spawn(archive_blocks());
+    }
+}
+
+async fn archive_blocks() {
+    let _run = match archive::RunGuard::acquire(&ARCHIVING) {
+        Some(run) => run,
+        None => return,
+    };
+
+    let state = get_state();
+    let archive = match state.archive_canister {
+        Some(archive) => archive,
+        None => return,
+    };
+
+    let start = state.first_local_index;
+    let end = state
+        .log_length
+        .min(start.saturating_add(state.archive_batch_size));
+    let blocks = ICRC3_BLOCKS.with(|blocks| {
+        let blocks = blocks.borrow();
+        (start..end)
+            .filter_map(|index| blocks.get(&index))
+            .collect::<Vec<_>>()
+    });
+
+    let result: ic_cdk::api::call::CallResult<(Result<(), String>,)> =
+        ic_cdk::call(archive, "append_blocks", (start, blocks)).await;
+
+    match result {
+        Ok((Ok(()),)) => {
+            ICRC3_BLOCKS.with(|blocks| {
+                let mut blocks = blocks.borrow_mut();
+                for index in start..end {
+                    blocks.remove(&index);
+                }
+            });
+
+            // Re-read the state: blocks may have been appended while the
+            // call was in flight.
+            let mut state = get_state();
+            state.first_local_index = end;
+            set_state(state);
+        }
+        Ok((Err(error),)) => {
+            ic_cdk::println!("Archive rejected blocks {}..{}: {}", start, end, error);
+        }
+        Err((code, message)) => {
+            ic_cdk::println!(
+                "Failed to archive blocks {}..{}: {:?} {}",
+                start,
+                end,
+                code,
+                message
+            );
+        }
+    }
+}
+
+fn to_u64(value: &Nat) -> u64 {
+    u64::try_from(&value.0).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{FeeKind, FeeLine, TransactionStatus};
+    use candid::Int;
+    use ic_stable_structures::storable::{Bound, Storable};
+
+    fn hex(hash: Hash) -> String {
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // Test vectors from the ICRC-3 standard
+    #[test]
+    fn hashes_match_the_reference_vectors() {
+        assert_eq!(
+            hex(nat(42).hash()),
+            "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1"
+        );
+        assert_eq!(
+            hex(Icrc3Value::Int(Int::from(-42)).hash()),
+            "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc"
+        );
+        assert_eq!(
+            hex(text("Hello, World!").hash()),
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+        assert_eq!(
+            hex(Icrc3Value::Blob(vec![1, 2, 3, 4]).hash()),
+            "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a"
+        );
+        assert_eq!(
+            hex(Icrc3Value::Array(vec![nat(3), text("foo"), Icrc3Value::Blob(vec![5, 6])]).hash()),
+            "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6"
+        );
+
+        let account = |third: u8| {
+            Icrc3Value::Blob(vec![
+                0x00, 0xab, third, 0xef, 0x00, 0x12, 0x34, 0x00, 0x56, 0x78, 0x9a, 0x00, 0xbc,
+                0xde, 0xf0, 0x00, 0x01, 0x23, 0x45, 0x67, 0x89, 0x00, 0xab, 0xcd, 0xef, 0x01,
+            ])
+        };
+        let map = Icrc3Value::Map(vec![
+            ("from".to_string(), account(0xcd)),
+            ("to".to_string(), account(0x0d)),
+            ("amount".to_string(), nat(42)),
+            ("created_at".to_string(), nat(1_699_218_263)),
+            ("memo".to_string(), nat(0)),
+        ]);
+        assert_eq!(
+            hex(map.hash()),
+            "c56ece650e1de4269c5bdeff7875949e3e2033f85b2d193c2ff4f7f78bdcfc75"
+        );
+    }
+
+    #[test]
+    fn map_hash_does_not_depend_on_field_order() {
+        let forward = Icrc3Value::Map(vec![
+            ("a".to_string(), nat(1)),
+            ("b".to_string(), text("x")),
+        ]);
+        let backward = Icrc3Value::Map(vec![
+            ("b".to_string(), text("x")),
+            ("a".to_string(), nat(1)),
+        ]);
+        assert_eq!(forward.hash(), backward.hash());
+    }
+
+    #[test]
+    fn largest_trade_block_fits_its_bound() {
+        let id = "x".repeat(32);
+        let principal = Principal::from_slice(&[0xff; 29]);
+        let transaction = Transaction {
+            id: id.clone(),
+            offer_id: id.clone(),
+            request_id: id.clone(),
+            farmer: principal,
+            investor: principal,
+            quantity: u64::MAX,
+            price_per_kg: 1e12,
+            total_amount: 1e12,
+            status: TransactionStatus::Completed,
+            created_at: u64::MAX,
+            updated_at: u64::MAX,
+            tokenized_at: Some(u64::MAX),
+            organization: Some(id),
+            fees: Some(vec![
+                FeeLine {
+                    kind: FeeKind::Percentage { bps: 10_000 },
+                    amount: 1e12,
+                },
+                FeeLine {
+                    kind: FeeKind::Flat,
+                    amount: 1e12,
+                },
+            ]),
+            settled_at: Some(u64::MAX),
+        };
+        let block = Icrc3Value::Map(vec![
+            ("phash".to_string(), Icrc3Value::Blob(vec![0xff; 32])),
+            ("btype".to_string(), text(REFUND_BLOCK_TYPE)),
+            ("ts".to_string(), nat(u64::MAX)),
+            ("tx".to_string(), transaction_tx(&transaction)),
+        ]);
+
+        let Bound::Bounded { max_size, .. } = Icrc3Value::BOUND else {
+            panic!("Icrc3Value must be bounded");
+        };
+        assert!(block.to_bytes().len() <= max_size as usize);
+    }
+}
@@ -0,0 +1,318 @@
+use candid::Principal;
+
+use crate::permissions;
+use crate::types::{
+    InvestmentOffer, InvestmentRequest, Notification, NotificationKind, NotificationPage,
+    Permission, RequestStatus, Transaction,
+};
+use crate::{NOTIFICATIONS, OFFERS};
+
+/// Older notifications are dropped once a user has this many.
+const MAX_NOTIFICATIONS_PER_USER: usize = 200;
+const MAX_PAGE_SIZE: u64 = 50;
+// Product names are free text; messages quoting them stay short
+const MAX_MESSAGE_LEN: usize = 256;
+
+fn prefix(recipient: &Principal) -> String {
+    format!("{}|", recipient.to_text())
+}
+
+// Zero-padded so that keys sort by id
+fn key(recipient: &Principal, id: u64) -> String {
+    format!("{}{:020}", prefix(recipient), id)
+}
+
+// The recipient's notifications, oldest first
+fn inbox(recipient: &Principal) -> Vec<Notification> {
+    let prefix = prefix(recipient);
+    NOTIFICATIONS.with(|notifications| {
+        notifications
+            .borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, notification)| notification)
+            .collect()
+    })
+}
+
+fn notify(
+    recipient: Principal,
+    kind: NotificationKind,
+    message: String,
+    offer_id: Option<String>,
+    request_id: Option<String>,
+    transaction_id: Option<String>,
+) {
+    let existing = inbox(&recipient);
+    let notification = Notification {
+        id: existing.last().map_or(0, |last| last.id + 1),
+        recipient,
+        kind,
+        message: message.chars().take(MAX_MESSAGE_LEN).collect(),
+        offer_id,
+        request_id,
+        transaction_id,
+        read: false,
+        created_at: ic_cdk::api::time(),
+    };
+
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let overflow = (existing.len() + 1).saturating_sub(MAX_NOTIFICATIONS_PER_USER);
+        for old in existing.iter().take(overflow) {
+            notifications.remove(&key(&recipient, old.id));
+        }
+        notifications.insert(key(&recipient, notification.id), notification);
+    });
+}
+
+fn offer(offer_id: &str) -> Option<InvestmentOffer> {
+    OFFERS.with(|offers| offers.borrow().get(&offer_id.to_string()))
+}
+
+fn product_name(offer_id: &str) -> String {
+    offer(offer_id).map_or_else(|| "an offer".to_string(), |offer| offer.product_name)
+}
+
+fn notify_request(
+    recipient: Principal,
+    kind: NotificationKind,
+    message: String,
+    req: &InvestmentRequest,
+) {
+    notify(
+        recipient,
+        kind,
+        message,
+        Some(req.offer_id.clone()),
+        Some(req.id.clone()),
+        None,
+    );
+}
+
+fn notify_transaction(
+    recipient: Principal,
+    kind: NotificationKind,
+    message: String,
+    txn: &Transaction,
+) {
+    notify(
+        recipient,
+        kind,
+        message,
+        Some(txn.offer_id.clone()),
+        Some(txn.request_id.clone()),
+        Some(txn.id.clone()),
+    );
+}
+
+/// Tells the farmer about a new request for their offer.
+pub fn on_request_created(req: &InvestmentRequest) {
+    let offer = match offer(&req.offer_id) {
+        Some(offer) => offer,
+        None => return,
+    };
+    let message = format!(
+        "New request for {} kg of {} at {} per kg",
+        req.requested_quantity, offer.product_name, req.offered_price_per_kg
+    );
+    notify_request(
+        offer.farmer,
+        NotificationKind::RequestReceived,
+        message,
+        req,
+    );
+}
+
+/// Tells the investor their request was accepted or rejected.
+pub fn on_request_answered(req: &InvestmentRequest) {
+    let (kind, verb) = match req.status {
+        RequestStatus::Accepted => (NotificationKind::RequestAccepted, "accepted"),
+        RequestStatus::Rejected => (NotificationKind::RequestRejected, "rejected"),
+        _ => return,
+    };
+    let message = format!(
+        "Your request for {} kg of {} was {}",
+        req.requested_quantity,
+        product_name(&req.offer_id),
+        verb
+    );
+    notify_request(req.investor, kind, message, req);
+}
+
+/// Tells both sides that a request expired unanswered.
+pub fn on_request_expired(req: &InvestmentRequest) {
+    let offer = offer(&req.offer_id);
+    let product_name = product_name(&req.offer_id);
+    notify_request(
+        req.investor,
+        NotificationKind::RequestExpired,
+        format!(
+            "Your request for {} kg of {} expired without an answer",
+            req.requested_quantity, product_name
+        ),
+        req,
+    );
+    if let Some(offer) = offer {
+        notify_request(
+            offer.farmer,
+            NotificationKind::RequestExpired,
+            format!(
+                "A request for {} kg of {} expired without an answer",
+                req.requested_quantity, product_name
+            ),
+            req,
+        );
+    }
+}
+
+pub fn on_request_cancelled(req: &InvestmentRequest) {
+    let message = format!(
+        "Your request for {} kg of {} was cancelled by an administrator",
+        req.requested_quantity,
+        product_name(&req.offer_id)
+    );
+    notify_request(
+        req.investor,
+        NotificationKind::RequestCancelled,
+        message,
+        req,
+    );
+}
+
+pub fn on_settled(txn: &Transaction) {
+    let message = format!(
+        "The trade of {} kg of {} was settled",
+        txn.quantity,
+        product_name(&txn.offer_id)
+    );
+    notify_transaction(
+        txn.farmer,
+        NotificationKind::TransactionSettled,
+        message.clone(),
+        txn,
+    );
+    notify_transaction(
+        txn.investor,
+        NotificationKind::TransactionSettled,
+        message,
+        txn,
+    );
+}
+
+pub fn on_refunded(txn: &Transaction) {
+    let message = format!(
+        "The trade of {} kg of {} was refunded",
+        txn.quantity,
+        product_name(&txn.offer_id)
+    );
+    notify_transaction(
+        txn.farmer,
+        NotificationKind::TransactionRefunded,
+        message.clone(),
+        txn,
+    );
+    notify_transaction(
+        txn.investor,
+        NotificationKind::TransactionRefunded,
+        message,
+        txn,
+    );
+}
+
+pub fn on_paid_out(txn: &Transaction) {
+    let message = format!(
+        "The proceeds of the trade of {} kg of {} were paid out",
+        txn.quantity,
+        product_name(&txn.offer_id)
+    );
+    notify_transaction(txn.farmer, NotificationKind::PayoutRecorded, message, txn);
+}
+
+/// Puts `message` in the inbox of every active account holding `permission`.
+pub fn notify_holders(permission: Permission, kind: NotificationKind, message: &str) {
+    for holder in permissions::holders(permission) {
+        notify(holder, kind.clone(), message.to_string(), None, None, None);
+    }
+}
+
+/// A page of the recipient's notifications, newest first, starting below
+/// id `before` when given.
+pub fn page(
+    recipient: &Principal,
+    before: Option<u64>,
+    limit: u64,
+    unread_only: bool,
+) -> NotificationPage {
+    let notifications = inbox(recipient);
+    let unread_count = notifications
+        .iter()
+        .filter(|notification| !notification.read)
+        .count() as u64;
+
+    let limit = limit.min(MAX_PAGE_SIZE) as usize;
+    let mut matching = notifications
+        .into_iter()
+        .rev()
+        .filter(|notification| before.is_none_or(|before| notification.id < before))
+        .filter(|notification| !unread_only || !notification.read);
+    let page = matching.by_ref().take(limit).collect::<Vec<_>>();
+    let next_before = match matching.next() {
+        Some(_) => page.last().map(|notification| notification.id),
+        None => None,
+    };
+
+    NotificationPage {
+        notifications: page,
+        next_before,
+        unread_count,
+    }
+}
+
+pub fn unread_count(recipient: &Principal) -> u64 {
+    inbox(recipient)
+        .iter()
+        .filter(|notification| !notification.read)
+        .count() as u64
+}
+
+/// Marks the given notifications, or all of them with `None`, as read.
+/// Returns how many were unread.
+pub fn mark_read(recipient: &Principal, ids: Option<Vec<u64>>) -> u64 {
+    let unread = inbox(recipient)
+        .into_iter()
+        .filter(|notification| !notification.read)
+        .filter(|notification| {
+            ids.as_ref()
+                .is_none_or(|ids| ids.contains(&notification.id))
+        })
+        .collect::<Vec<_>>();
+
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        for notification in &unread {
+            notifications.insert(
+                key(recipient, notification.id),
+                Notification {
+                    read: true,
+                    ..notification.clone()
+                },
+            );
+        }
+    });
+    unread.len() as u64
+}
+
+/// Drops the recipient's notifications, which may name their trades.
+pub fn erase(recipient: &Principal) {
+    let ids = inbox(recipient)
+        .iter()
+        .map(|notification| notification.id)
+        .collect::<Vec<_>>();
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        for id in ids {
+            notifications.remove(&key(recipient, id));
+        }
+    });
+}
@@ -0,0 +1,250 @@
+use candid::Principal;
+
+use crate::types::Permission;
+use crate::USERS;
+use crate::{linking, pause, permissions};
+
+// Ingress filtering. `canister_inspect_message` runs on a single replica
+// before an update call is accepted, so it only saves cycles on calls that
+// would be rejected anyway; every endpoint still performs its own checks.
+// Methods missing from the lists below are rejected, so new endpoints have
+// to be added here.
+
+/// Larger payloads are dropped before decoding.
+const MAX_PAYLOAD_BYTES: usize = 32 * 1024;
+
+/// Methods anyone may call, including the anonymous principal.
+const PUBLIC_METHODS: &[&str] = &[
+    "get_available_offers",
+    "get_certified_offer",
+    "get_certified_platform_stats",
+    "get_kyc_policy",
+    "get_multisig_policy",
+    "get_offer_by_id",
+    "get_pause_state",
+    "get_organization",
+    "get_organization_offers",
+    "get_platform_counters",
+    "get_platform_stats",
+    "get_platform_stats_series",
+    "get_price_history",
+    "get_public_settings",
+    "get_reference_prices",
+    "health_check",
+    "http_request",
+    "icrc10_supported_standards",
+    "icrc3_get_archives",
+    "icrc3_get_blocks",
+    "icrc3_get_tip_certificate",
+    "icrc3_supported_block_types",
+    "list_roles",
+    "wallet_receive",
+];
+
+/// Updates that keep working in maintenance mode; every other update is
+/// rejected while it is on.
+const MAINTENANCE_METHODS: &[&str] = &["pause", "reconcile_treasury", "unpause", "wallet_receive"];
+
+/// Methods for authenticated principals that have no account yet.
+const UNREGISTERED_METHODS: &[&str] = &["get_current_user", "link_principal", "register_user"];
+
+/// Methods that require a registered account but no particular permission.
+const ACCOUNT_METHODS: &[&str] = &[
+    "create_agricultural_offer",
+    "create_link_challenge",
+    "create_organization",
+    "deactivate_account",
+    "delete_account",
+    "export_my_data",
+    "get_email_verification_status",
+    "get_farmer_analytics",
+    "get_farmer_offers",
+    "get_farmer_transactions",
+    "get_investor_requests",
+    "get_investor_transactions",
+    "get_linked_principals",
+    "get_my_kyc",
+    "get_my_notifications",
+    "get_my_organizations",
+    "get_my_permissions",
+    "get_my_portfolio",
+    "get_organization_members",
+    "get_organization_revenue",
+    "get_organization_transactions",
+    "get_requests_for_offer",
+    "get_unread_notification_count",
+    "mark_all_notifications_read",
+    "mark_notifications_read",
+    "reactivate_account",
+    "remove_org_member",
+    "request_email_verification",
+    "respond_to_investment_request",
+    "revoke_linked_principal",
+    "set_org_member",
+    "settle_transaction",
+    "submit_kyc",
+    "update_profile",
+    "verify_email",
+];
+
+/// Methods gated on a permission.
+fn required_permission(method: &str) -> Option<Permission> {
+    let permission = match method {
+        "create_investment_request" => Permission::CreateInvestmentRequest,
+        "get_pending_kyc" | "review_kyc" => Permission::ReviewKyc,
+        "get_all_users" | "get_user_permissions" => Permission::ViewUsers,
+        "update_user_role" | "define_role" | "delete_role" | "grant_role" | "revoke_role" => {
+            Permission::ManageRoles
+        }
+        "export_user_data" => Permission::ExportUserData,
+        "get_audit_log" | "verify_audit_chain" => Permission::ViewAuditLog,
+        "configure_icrc3_archive"
+        | "configure_record_archive"
+        | "get_icrc3_archive_state"
+        | "get_record_archive_state"
+        | "archive_old_records" => Permission::ManageArchive,
+        "configure_notifier" | "get_notifier_config" => Permission::ManageNotifier,
+        "configure_kyc_policy" => Permission::ManageKycPolicy,
+        "get_platform_settings" | "update_platform_settings" => Permission::ManageSettings,
+        "pause" | "unpause" => Permission::PausePlatform,
+        "get_fee_revenue"
+        | "get_treasury_config"
+        | "get_treasury_balances"
+        | "get_treasury_summary"
+        | "reconcile_treasury" => Permission::ViewRevenue,
+        "configure_treasury" | "record_payout" => Permission::ManageTreasury,
+        "configure_cycles_monitor"
+        | "get_cycles_contributors"
+        | "get_cycles_status"
+        | "get_cycles_top_ups" => Permission::ManageCycles,
+        "configure_rate_limits"
+        | "get_rate_limit_config"
+        | "get_rate_limit_overrides"
+        | "set_rate_limit_override" => Permission::ManageRateLimits,
+        "propose_admin_action"
+        | "approve_admin_action"
+        | "withdraw_admin_proposal"
+        | "get_admin_proposals"
+        | "get_admin_proposal" => Permission::ApproveAdminActions,
+        _ => return None,
+    };
+    Some(permission)
+}
+
+/// Decides whether an ingress message for `method` is accepted, with the
+/// reason when it is not.
+pub fn check(method: &str, caller: Principal, payload_size: usize) -> Result<(), String> {
+    if payload_size > MAX_PAYLOAD_BYTES {
+        return Err(format!(
+            "Payload of {} bytes exceeds the limit of {} bytes",
+            payload_size, MAX_PAYLOAD_BYTES
+        ));
+    }
+
+    // Only updates pass through here; queries are not inspected
+    if pause::in_maintenance()
+        && !MAINTENANCE_METHODS.contains(&method)
+        && !PUBLIC_METHODS.contains(&method)
+    {
+        return Err("The platform is in maintenance mode".to_string());
+    }
+
+    if PUBLIC_METHODS.contains(&method) {
+        return Ok(());
+    }
+
+    // Controllers operate the canister, e.g. from deployment scripts
+    if ic_cdk::api::is_controller(&caller) {
+        return Ok(());
+    }
+
+    if caller == Principal::anonymous() {
+        return Err("Authentication required".to_string());
+    }
+
+    if UNREGISTERED_METHODS.contains(&method) {
+        return Ok(());
+    }
+
+    if let Some(permission) = required_permission(method) {
+        return permissions::authorize(permission).map(drop);
+    }
+
+    if ACCOUNT_METHODS.contains(&method) {
+        let account = linking::resolve(caller);
+        if USERS.with(|users| !users.borrow().contains_key(&account)) {
+            return Err("User not found".to_string());
+        }
+        return Ok(());
+    }
+
+    Err(format!("Method {} cannot be called via ingress", method))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::PauseState;
+    use crate::PAUSE_STATE;
+
+    fn maintenance() {
+        PAUSE_STATE.with(|state| {
+            state
+                .borrow_mut()
+                .set(PauseState {
+                    platform: true,
+                    ..PauseState::default()
+                })
+                .unwrap()
+        });
+    }
+
+    #[test]
+    fn rejects_oversized_payloads() {
+        let anonymous = Principal::anonymous();
+        assert!(check("health_check", anonymous, MAX_PAYLOAD_BYTES).is_ok());
+        assert!(check("health_check", anonymous, MAX_PAYLOAD_BYTES + 1).is_err());
+    }
+
+    #[test]
+    fn public_methods_accept_anyone_even_in_maintenance() {
+        maintenance();
+        for method in PUBLIC_METHODS {
+            assert!(
+                check(method, Principal::anonymous(), 0).is_ok(),
+                "{}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn maintenance_rejects_other_updates() {
+        maintenance();
+        let caller = Principal::from_slice(&[1]);
+        for method in ["register_user", "create_agricultural_offer", "review_kyc"] {
+            assert_eq!(
+                check(method, caller, 0),
+                Err("The platform is in maintenance mode".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn each_method_is_listed_once() {
+        let lists = [PUBLIC_METHODS, UNREGISTERED_METHODS, ACCOUNT_METHODS];
+        let mut methods = lists.concat();
+        for list in lists {
+            assert!(
+                list.iter()
+                    .all(|method| required_permission(method).is_none()),
+                "{:?}",
+                list
+            );
+        }
+        let count = methods.len();
+        methods.sort();
+        methods.dedup();
+        assert_eq!(methods.len(), count);
+    }
+}
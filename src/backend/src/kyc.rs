@@ -0,0 +1,353 @@
+use candid::Principal;
+
+use crate::types::{
+    EntityType, KycDetails, KycDocument, KycPolicy, KycRecord, KycStatus, ReviewKycRequest,
+    SubmitKycRequest,
+};
+use crate::{audit, validation};
+use crate::{KYC_POLICY, KYC_RECORDS, USERS};
+
+const MAX_DOCUMENTS: usize = 10;
+const MAX_DOCUMENT_KIND_LEN: usize = 64;
+const MAX_LEGAL_NAME_LEN: usize = 128;
+const MAX_ADDRESS_LEN: usize = 256;
+const MAX_REASON_LEN: usize = 512;
+
+pub fn get_policy() -> KycPolicy {
+    KYC_POLICY.with(|policy| policy.borrow().get().clone())
+}
+
+pub fn set_policy(policy: KycPolicy) {
+    KYC_POLICY.with(|cell| {
+        cell.borrow_mut()
+            .set(policy)
+            .expect("failed to write KYC policy");
+    });
+}
+
+pub fn configure_policy(actor: Principal, policy: KycPolicy) -> Result<KycPolicy, String> {
+    validate_policy(&policy)?;
+
+    let before = get_policy();
+    set_policy(policy.clone());
+    audit::record(
+        actor,
+        "configure_kyc_policy",
+        EntityType::Config,
+        "kyc_policy",
+        Some(&before),
+        Some(&policy),
+    );
+
+    Ok(policy)
+}
+
+fn validate_policy(policy: &KycPolicy) -> Result<(), String> {
+    let thresholds = [policy.offer_value_threshold, policy.request_value_threshold];
+    if thresholds
+        .iter()
+        .flatten()
+        .any(|threshold| !threshold.is_finite() || *threshold < 0.0)
+    {
+        return Err("Thresholds must be non-negative numbers".to_string());
+    }
+    if policy.validity_ns == 0 {
+        return Err("Validity period must be positive".to_string());
+    }
+    Ok(())
+}
+
+/// The stored record with `status` reflecting expiry as of now.
+pub fn get(principal: &Principal) -> Option<KycRecord> {
+    let mut record = KYC_RECORDS.with(|records| records.borrow().get(principal))?;
+    if is_expired(&record, ic_cdk::api::time()) {
+        record.status = KycStatus::Expired;
+    }
+    Some(record)
+}
+
+pub fn status(principal: &Principal) -> KycStatus {
+    get(principal)
+        .map(|record| record.status)
+        .unwrap_or(KycStatus::Unverified)
+}
+
+fn is_expired(record: &KycRecord, now: u64) -> bool {
+    record.status == KycStatus::Verified
+        && record
+            .expires_at
+            .is_some_and(|expires_at| now >= expires_at)
+}
+
+/// Fails unless `principal` is verified or `value` is below `threshold`.
+pub fn check(principal: &Principal, value: f64, threshold: Option<f64>) -> Result<(), String> {
+    match threshold {
+        Some(threshold) if value >= threshold => match status(principal) {
+            KycStatus::Verified => Ok(()),
+            status => Err(format!(
+                "Identity verification required (current status: {:?})",
+                status
+            )),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn validate_details(details: KycDetails) -> Result<KycDetails, String> {
+    let country = details.country.trim().to_uppercase();
+    if country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err("Country must be a two-letter ISO 3166 code".to_string());
+    }
+
+    Ok(KycDetails {
+        legal_name: validation::validate_text(
+            "Legal name",
+            &details.legal_name,
+            MAX_LEGAL_NAME_LEN,
+        )?,
+        country,
+        address: validation::validate_text("Address", &details.address, MAX_ADDRESS_LEN)?,
+        date_of_birth: details
+            .date_of_birth
+            .map(|date| validation::validate_text("Date of birth", &date, 10))
+            .transpose()?,
+    })
+}
+
+fn validate_documents(documents: Vec<KycDocument>) -> Result<Vec<KycDocument>, String> {
+    if documents.is_empty() || documents.len() > MAX_DOCUMENTS {
+        return Err(format!(
+            "Between 1 and {} documents are required",
+            MAX_DOCUMENTS
+        ));
+    }
+
+    documents
+        .into_iter()
+        .map(|document| {
+            if document.sha256.len() != 32 {
+                return Err("Document hashes must be SHA-256 digests".to_string());
+            }
+            Ok(KycDocument {
+                kind: validation::validate_text(
+                    "Document kind",
+                    &document.kind,
+                    MAX_DOCUMENT_KIND_LEN,
+                )?,
+                sha256: document.sha256,
+            })
+        })
+        .collect()
+}
+
+/// Stores a new submission for review, replacing a rejected or expired one.
+pub fn submit(principal: Principal, request: SubmitKycRequest) -> Result<KycRecord, String> {
+    match status(&principal) {
+        KycStatus::Pending => return Err("A submission is already pending review".to_string()),
+        KycStatus::Verified => return Err("Identity is already verified".to_string()),
+        _ => {}
+    }
+
+    let before = KYC_RECORDS.with(|records| records.borrow().get(&principal));
+    let record = KycRecord {
+        principal,
+        status: KycStatus::Pending,
+        details: validate_details(request.details)?,
+        documents: validate_documents(request.documents)?,
+        submitted_at: ic_cdk::api::time(),
+        reviewed_by: None,
+        reviewed_at: None,
+        expires_at: None,
+        rejection_reason: None,
+    };
+
+    store(principal, "submit_kyc", before.as_ref(), &record);
+    Ok(record)
+}
+
+/// Approves or rejects a pending submission.
+pub fn review(reviewer: Principal, request: ReviewKycRequest) -> Result<KycRecord, String> {
+    if reviewer == request.principal {
+        return Err("Cannot review your own submission".to_string());
+    }
+
+    let before = KYC_RECORDS
+        .with(|records| records.borrow().get(&request.principal))
+        .ok_or("No submission found")?;
+    if before.status != KycStatus::Pending {
+        return Err("Submission is not pending review".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+    let mut record = before.clone();
+    record.reviewed_by = Some(reviewer);
+    record.reviewed_at = Some(now);
+
+    if request.approve {
+        record.status = KycStatus::Verified;
+        record.expires_at = Some(now + get_policy().validity_ns);
+    } else {
+        let reason = request.reason.unwrap_or_default();
+        record.status = KycStatus::Rejected;
+        record.rejection_reason = Some(validation::validate_text(
+            "Rejection reason",
+            &reason,
+            MAX_REASON_LEN,
+        )?);
+    }
+
+    store(reviewer, "review_kyc", Some(&before), &record);
+    Ok(record)
+}
+
+pub fn pending() -> Vec<KycRecord> {
+    KYC_RECORDS.with(|records| {
+        records
+            .borrow()
+            .iter()
+            .filter(|(_, record)| record.status == KycStatus::Pending)
+            .map(|(_, record)| record)
+            .collect()
+    })
+}
+
+/// Marks verifications past their validity period as expired, on both the
+/// record and the profile.
+pub fn expire_verifications() {
+    let now = ic_cdk::api::time();
+    let expired = KYC_RECORDS.with(|records| {
+        records
+            .borrow()
+            .iter()
+            .filter(|(_, record)| is_expired(record, now))
+            .map(|(_, record)| record)
+            .collect::<Vec<_>>()
+    });
+
+    for before in expired {
+        let record = KycRecord {
+            status: KycStatus::Expired,
+            ..before.clone()
+        };
+        store(ic_cdk::id(), "expire_kyc", Some(&before), &record);
+    }
+}
+
+/// Drops the caller's submission, which holds personal data.
+pub fn erase(principal: &Principal) {
+    KYC_RECORDS.with(|records| records.borrow_mut().remove(principal));
+}
+
+// Writes the record, mirrors its status onto the profile and audits both
+fn store(actor: Principal, action: &str, before: Option<&KycRecord>, record: &KycRecord) {
+    let principal = record.principal;
+
+    KYC_RECORDS.with(|records| {
+        records.borrow_mut().insert(principal, record.clone());
+    });
+    audit::record(
+        actor,
+        action,
+        EntityType::Kyc,
+        &principal.to_text(),
+        before,
+        Some(record),
+    );
+
+    if let Some(before) = USERS.with(|users| users.borrow().get(&principal)) {
+        let mut user = before.clone();
+        user.kyc_status = Some(record.status);
+        user.updated_at = ic_cdk::api::time();
+
+        USERS.with(|users| {
+            users.borrow_mut().insert(principal, user.clone());
+        });
+        audit::record(
+            actor,
+            action,
+            EntityType::User,
+            &principal.to_text(),
+            Some(&before),
+            Some(&user),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(status: KycStatus, expires_at: Option<u64>) -> KycRecord {
+        KycRecord {
+            principal: Principal::from_slice(&[1]),
+            status,
+            details: details(),
+            documents: vec![],
+            submitted_at: 0,
+            reviewed_by: None,
+            reviewed_at: None,
+            expires_at,
+            rejection_reason: None,
+        }
+    }
+
+    fn details() -> KycDetails {
+        KycDetails {
+            legal_name: " Jane Farmer ".to_string(),
+            country: "ke".to_string(),
+            address: "Nairobi".to_string(),
+            date_of_birth: None,
+        }
+    }
+
+    #[test]
+    fn only_verified_records_expire() {
+        let verified = record(KycStatus::Verified, Some(100));
+        assert!(!is_expired(&verified, 99));
+        assert!(is_expired(&verified, 100));
+        assert!(!is_expired(&record(KycStatus::Pending, Some(100)), 200));
+        assert!(!is_expired(&record(KycStatus::Verified, None), u64::MAX));
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        let policy = |threshold, validity_ns| KycPolicy {
+            offer_value_threshold: threshold,
+            request_value_threshold: None,
+            validity_ns,
+        };
+        assert!(validate_policy(&KycPolicy::default()).is_ok());
+        assert!(validate_policy(&policy(None, 1)).is_ok());
+        assert!(validate_policy(&policy(Some(-1.0), 1)).is_err());
+        assert!(validate_policy(&policy(Some(f64::NAN), 1)).is_err());
+        assert!(validate_policy(&policy(Some(f64::INFINITY), 1)).is_err());
+        assert!(validate_policy(&policy(Some(0.0), 0)).is_err());
+    }
+
+    #[test]
+    fn normalizes_details() {
+        let valid = validate_details(details()).unwrap();
+        assert_eq!(valid.legal_name, "Jane Farmer");
+        assert_eq!(valid.country, "KE");
+
+        for country in ["KEN", "K1", ""] {
+            let details = KycDetails {
+                country: country.to_string(),
+                ..details()
+            };
+            assert!(validate_details(details).is_err());
+        }
+    }
+
+    #[test]
+    fn requires_sha256_documents_within_the_limit() {
+        let document = |sha256: Vec<u8>| KycDocument {
+            kind: "passport".to_string(),
+            sha256,
+        };
+        assert!(validate_documents(vec![document(vec![0; 32])]).is_ok());
+        assert!(validate_documents(vec![]).is_err());
+        assert!(validate_documents(vec![document(vec![0; 31])]).is_err());
+        assert!(validate_documents(vec![document(vec![0; 32]); MAX_DOCUMENTS + 1]).is_err());
+    }
+}
@@ -1,11 +1,42 @@
-use candid::{Deserialize, Principal};
+// The crate is named after its dfx canister
+#![allow(non_snake_case)]
+
+use candid::Principal;
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager, VirtualMemory},
-    DefaultMemoryImpl, StableBTreeMap,
+    DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog,
 };
 use std::cell::RefCell;
-
+use std::time::Duration;
+
+mod analytics;
+mod archive;
+mod audit;
+mod certification;
+mod cycles;
+mod email_verification;
+mod export;
+mod fees;
+mod icrc3;
+mod inbox;
+mod inspect;
+mod kyc;
+mod linking;
+mod metrics;
+mod multisig;
+mod notifier;
+mod organizations;
+mod pause;
+mod permissions;
+mod portfolio;
+mod prices;
+mod rate_limit;
+mod settings;
+mod stats;
+mod treasury;
 mod types;
+mod validation;
 use types::*;
 
 // Memory management
@@ -15,6 +46,81 @@ const USERS_MEMORY_ID: MemoryId = MemoryId::new(0);
 const OFFERS_MEMORY_ID: MemoryId = MemoryId::new(1);
 const REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(2);
 const TRANSACTIONS_MEMORY_ID: MemoryId = MemoryId::new(3);
+const AUDIT_LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(4);
+const AUDIT_LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(5);
+const ICRC3_BLOCKS_MEMORY_ID: MemoryId = MemoryId::new(6);
+const ICRC3_STATE_MEMORY_ID: MemoryId = MemoryId::new(7);
+const RECORD_ARCHIVE_STATE_MEMORY_ID: MemoryId = MemoryId::new(8);
+const ARCHIVED_PARTIES_MEMORY_ID: MemoryId = MemoryId::new(9);
+const EMAIL_VERIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const NOTIFIER_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(11);
+const KYC_RECORDS_MEMORY_ID: MemoryId = MemoryId::new(12);
+const KYC_POLICY_MEMORY_ID: MemoryId = MemoryId::new(13);
+const LINKED_PRINCIPALS_MEMORY_ID: MemoryId = MemoryId::new(14);
+const LINK_CHALLENGES_MEMORY_ID: MemoryId = MemoryId::new(15);
+const ORGANIZATIONS_MEMORY_ID: MemoryId = MemoryId::new(16);
+const ORG_MEMBERS_MEMORY_ID: MemoryId = MemoryId::new(17);
+const CUSTOM_ROLES_MEMORY_ID: MemoryId = MemoryId::new(18);
+const ROLE_GRANTS_MEMORY_ID: MemoryId = MemoryId::new(19);
+const PROPOSALS_MEMORY_ID: MemoryId = MemoryId::new(20);
+const MULTISIG_POLICY_MEMORY_ID: MemoryId = MemoryId::new(21);
+const RATE_LIMIT_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(22);
+const RATE_LIMIT_OVERRIDES_MEMORY_ID: MemoryId = MemoryId::new(23);
+const PLATFORM_SETTINGS_MEMORY_ID: MemoryId = MemoryId::new(24);
+const PAUSE_STATE_MEMORY_ID: MemoryId = MemoryId::new(25);
+const TREASURY_LEDGER_MEMORY_ID: MemoryId = MemoryId::new(26);
+const TREASURY_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(27);
+const PRICE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(28);
+const PLATFORM_COUNTERS_MEMORY_ID: MemoryId = MemoryId::new(29);
+const STATS_SERIES_MEMORY_ID: MemoryId = MemoryId::new(30);
+const CYCLES_MONITOR_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(31);
+const CYCLES_SAMPLES_MEMORY_ID: MemoryId = MemoryId::new(32);
+const CYCLES_TOP_UPS_MEMORY_ID: MemoryId = MemoryId::new(33);
+const NOTIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(34);
+const EMAIL_FAILURES_MEMORY_ID: MemoryId = MemoryId::new(35);
+
+/// Every stable memory region by name, as reported by `/metrics`.
+const MEMORY_REGIONS: &[(&str, MemoryId)] = &[
+    ("users", USERS_MEMORY_ID),
+    ("offers", OFFERS_MEMORY_ID),
+    ("requests", REQUESTS_MEMORY_ID),
+    ("transactions", TRANSACTIONS_MEMORY_ID),
+    ("audit_log_index", AUDIT_LOG_INDEX_MEMORY_ID),
+    ("audit_log_data", AUDIT_LOG_DATA_MEMORY_ID),
+    ("icrc3_blocks", ICRC3_BLOCKS_MEMORY_ID),
+    ("icrc3_state", ICRC3_STATE_MEMORY_ID),
+    ("record_archive_state", RECORD_ARCHIVE_STATE_MEMORY_ID),
+    ("archived_parties", ARCHIVED_PARTIES_MEMORY_ID),
+    ("email_verifications", EMAIL_VERIFICATIONS_MEMORY_ID),
+    ("notifier_config", NOTIFIER_CONFIG_MEMORY_ID),
+    ("kyc_records", KYC_RECORDS_MEMORY_ID),
+    ("kyc_policy", KYC_POLICY_MEMORY_ID),
+    ("linked_principals", LINKED_PRINCIPALS_MEMORY_ID),
+    ("link_challenges", LINK_CHALLENGES_MEMORY_ID),
+    ("organizations", ORGANIZATIONS_MEMORY_ID),
+    ("org_members", ORG_MEMBERS_MEMORY_ID),
+    ("custom_roles", CUSTOM_ROLES_MEMORY_ID),
+    ("role_grants", ROLE_GRANTS_MEMORY_ID),
+    ("proposals", PROPOSALS_MEMORY_ID),
+    ("multisig_policy", MULTISIG_POLICY_MEMORY_ID),
+    ("rate_limit_config", RATE_LIMIT_CONFIG_MEMORY_ID),
+    ("rate_limit_overrides", RATE_LIMIT_OVERRIDES_MEMORY_ID),
+    ("platform_settings", PLATFORM_SETTINGS_MEMORY_ID),
+    ("pause_state", PAUSE_STATE_MEMORY_ID),
+    ("treasury_ledger", TREASURY_LEDGER_MEMORY_ID),
+    ("treasury_config", TREASURY_CONFIG_MEMORY_ID),
+    ("price_history", PRICE_HISTORY_MEMORY_ID),
+    ("platform_counters", PLATFORM_COUNTERS_MEMORY_ID),
+    ("stats_series", STATS_SERIES_MEMORY_ID),
+    ("cycles_monitor_config", CYCLES_MONITOR_CONFIG_MEMORY_ID),
+    ("cycles_samples", CYCLES_SAMPLES_MEMORY_ID),
+    ("cycles_top_ups", CYCLES_TOP_UPS_MEMORY_ID),
+    ("notifications", NOTIFICATIONS_MEMORY_ID),
+    ("email_failures", EMAIL_FAILURES_MEMORY_ID),
+];
+
+const MAX_AUDIT_PAGE_SIZE: u64 = 100;
+const MAX_AUDIT_VERIFY_BATCH: u64 = 1_000;
 
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
@@ -36,8 +142,198 @@ thread_local! {
     static TRANSACTIONS: RefCell<StableBTreeMap<String, Transaction, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TRANSACTIONS_MEMORY_ID)))
     );
+
+    static AUDIT_LOG: RefCell<StableLog<AuditEntry, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_LOG_INDEX_MEMORY_ID)),
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_LOG_DATA_MEMORY_ID)),
+        )
+        .expect("failed to initialize audit log")
+    );
+
+    static ICRC3_BLOCKS: RefCell<StableBTreeMap<u64, Icrc3Value, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ICRC3_BLOCKS_MEMORY_ID)))
+    );
+
+    static ICRC3_STATE: RefCell<StableCell<Icrc3State, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ICRC3_STATE_MEMORY_ID)),
+            Icrc3State::default(),
+        )
+        .expect("failed to initialize ICRC-3 state")
+    );
+
+    static RECORD_ARCHIVE_STATE: RefCell<StableCell<RecordArchiveState, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(RECORD_ARCHIVE_STATE_MEMORY_ID)),
+            RecordArchiveState::default(),
+        )
+        .expect("failed to initialize record archive state")
+    );
+
+    // Number of archived records each principal is a party to
+    static ARCHIVED_PARTIES: RefCell<StableBTreeMap<Principal, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ARCHIVED_PARTIES_MEMORY_ID)))
+    );
+
+    // Outstanding email verification code per principal
+    static EMAIL_VERIFICATIONS: RefCell<StableBTreeMap<Principal, EmailVerification, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EMAIL_VERIFICATIONS_MEMORY_ID)))
+    );
+
+    // Wrong codes per email address, keyed by a hash of the address
+    static EMAIL_FAILURES: RefCell<StableBTreeMap<[u8; 32], EmailFailures, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EMAIL_FAILURES_MEMORY_ID)))
+    );
+
+    static NOTIFIER_CONFIG: RefCell<StableCell<NotifierConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(NOTIFIER_CONFIG_MEMORY_ID)),
+            NotifierConfig::default(),
+        )
+        .expect("failed to initialize notifier config")
+    );
+
+    static KYC_RECORDS: RefCell<StableBTreeMap<Principal, KycRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(KYC_RECORDS_MEMORY_ID)))
+    );
+
+    static KYC_POLICY: RefCell<StableCell<KycPolicy, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(KYC_POLICY_MEMORY_ID)),
+            KycPolicy::default(),
+        )
+        .expect("failed to initialize KYC policy")
+    );
+
+    // Linked principal -> the account it acts for
+    static LINKED_PRINCIPALS: RefCell<StableBTreeMap<Principal, LinkedPrincipal, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(LINKED_PRINCIPALS_MEMORY_ID)))
+    );
+
+    // Keyed by the hex SHA-256 of the challenge
+    static LINK_CHALLENGES: RefCell<StableBTreeMap<String, LinkChallenge, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(LINK_CHALLENGES_MEMORY_ID)))
+    );
+
+    static ORGANIZATIONS: RefCell<StableBTreeMap<String, Organization, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ORGANIZATIONS_MEMORY_ID)))
+    );
+
+    // Keyed by `<organization_id>|<principal>`
+    static ORG_MEMBERS: RefCell<StableBTreeMap<String, OrgMember, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ORG_MEMBERS_MEMORY_ID)))
+    );
+
+    static CUSTOM_ROLES: RefCell<StableBTreeMap<String, RoleDefinition, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CUSTOM_ROLES_MEMORY_ID)))
+    );
+
+    static ROLE_GRANTS: RefCell<StableBTreeMap<Principal, RoleGrants, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ROLE_GRANTS_MEMORY_ID)))
+    );
+
+    static PROPOSALS: RefCell<StableBTreeMap<u64, AdminProposal, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(PROPOSALS_MEMORY_ID)))
+    );
+
+    static MULTISIG_POLICY: RefCell<StableCell<MultisigPolicy, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MULTISIG_POLICY_MEMORY_ID)),
+            MultisigPolicy::default(),
+        )
+        .expect("failed to initialize multisig policy")
+    );
+
+    static RATE_LIMIT_CONFIG: RefCell<StableCell<RateLimitConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(RATE_LIMIT_CONFIG_MEMORY_ID)),
+            RateLimitConfig::default(),
+        )
+        .expect("failed to initialize rate limit config")
+    );
+
+    static RATE_LIMIT_OVERRIDES: RefCell<StableBTreeMap<Principal, RateLimitOverride, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(RATE_LIMIT_OVERRIDES_MEMORY_ID)))
+    );
+
+    static PLATFORM_SETTINGS: RefCell<StableCell<PlatformSettings, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(PLATFORM_SETTINGS_MEMORY_ID)),
+            PlatformSettings::default(),
+        )
+        .expect("failed to initialize platform settings")
+    );
+
+    static PAUSE_STATE: RefCell<StableCell<PauseState, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(PAUSE_STATE_MEMORY_ID)),
+            PauseState::default(),
+        )
+        .expect("failed to initialize pause state")
+    );
+
+    static TREASURY_LEDGER: RefCell<StableBTreeMap<u64, TreasuryEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TREASURY_LEDGER_MEMORY_ID)))
+    );
+
+    static TREASURY_CONFIG: RefCell<StableCell<TreasuryConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(TREASURY_CONFIG_MEMORY_ID)),
+            TreasuryConfig::default(),
+        )
+        .expect("failed to initialize treasury config")
+    );
+
+    static PRICE_HISTORY: RefCell<StableBTreeMap<u64, PricePoint, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(PRICE_HISTORY_MEMORY_ID)))
+    );
+
+    static PLATFORM_COUNTERS: RefCell<StableCell<PlatformCounters, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(PLATFORM_COUNTERS_MEMORY_ID)),
+            PlatformCounters::default(),
+        )
+        .expect("failed to initialize platform counters")
+    );
+
+    // Daily buckets keyed by the start of the day
+    static STATS_SERIES: RefCell<StableBTreeMap<u64, StatsBucket, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(STATS_SERIES_MEMORY_ID)))
+    );
+
+    static CYCLES_MONITOR_CONFIG: RefCell<StableCell<CyclesMonitorConfig, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(CYCLES_MONITOR_CONFIG_MEMORY_ID)),
+            CyclesMonitorConfig::default(),
+        )
+        .expect("failed to initialize cycles monitor config")
+    );
+
+    // Balance samples keyed by timestamp
+    static CYCLES_SAMPLES: RefCell<StableBTreeMap<u64, CyclesSample, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CYCLES_SAMPLES_MEMORY_ID)))
+    );
+
+    static CYCLES_TOP_UPS: RefCell<StableBTreeMap<u64, CyclesTopUp, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(CYCLES_TOP_UPS_MEMORY_ID)))
+    );
+
+    // Keyed by recipient and per-recipient id, see `inbox::key`
+    static NOTIFICATIONS: RefCell<StableBTreeMap<String, Notification, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(NOTIFICATIONS_MEMORY_ID)))
+    );
 }
 
+const RECORD_ARCHIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const KYC_EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const PROPOSAL_EXPIRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const CYCLES_SAMPLE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const REQUEST_EXPIRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+const ERASED_DISPLAY_NAME: &str = "Deleted user";
+
 // Utility functions
 fn get_current_time() -> u64 {
     ic_cdk::api::time()
@@ -47,22 +343,97 @@ fn generate_id(prefix: &str) -> String {
     format!("{}_{}", prefix, get_current_time())
 }
 
+// Linked principals act as the account they are linked to
 fn get_caller() -> Principal {
-    ic_cdk::caller()
+    linking::resolve(ic_cdk::caller())
+}
+
+fn compute_platform_stats() -> PlatformStats {
+    let archived = archive::get_state();
+
+    PlatformStats {
+        total_users: USERS.with(|users| users.borrow().len()),
+        total_offers: OFFERS.with(|offers| offers.borrow().len()),
+        total_requests: REQUESTS.with(|requests| requests.borrow().len())
+            + archived.archived_requests,
+        total_transactions: TRANSACTIONS.with(|transactions| transactions.borrow().len())
+            + archived.archived_transactions,
+        active_offers: stats::active_offers(),
+    }
+}
+
+fn refresh_certified_stats() {
+    certification::certify_stats(&compute_platform_stats());
+}
+
+// Canister lifecycle
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    settings::init(args.and_then(|args| args.settings));
+    stats::seed();
+    rebuild_certified_state();
+    start_timers();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // The certified tree lives on the heap, so it has to be recomputed
+    // from stable memory after every upgrade. Timers do not survive
+    // upgrades either.
+    prices::seed();
+    stats::seed();
+    rebuild_certified_state();
+    start_timers();
+}
+
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    match inspect::check(
+        &method,
+        ic_cdk::caller(),
+        ic_cdk::api::call::arg_data_raw_size(),
+    ) {
+        Ok(()) => ic_cdk::api::call::accept_message(),
+        Err(reason) => ic_cdk::trap(&reason),
+    }
+}
+
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(RECORD_ARCHIVE_INTERVAL, || {
+        ic_cdk::spawn(async {
+            if let Err(error) = archive::archive_old_records().await {
+                ic_cdk::println!("Record archiving skipped: {}", error);
+            }
+            archive::erase_pending().await;
+            refresh_certified_stats();
+        })
+    });
+    ic_cdk_timers::set_timer_interval(KYC_EXPIRY_INTERVAL, kyc::expire_verifications);
+    ic_cdk_timers::set_timer_interval(PROPOSAL_EXPIRY_INTERVAL, multisig::expire_proposals);
+    ic_cdk_timers::set_timer_interval(RATE_LIMIT_PRUNE_INTERVAL, rate_limit::prune);
+    ic_cdk_timers::set_timer_interval(CYCLES_SAMPLE_INTERVAL, cycles::sample);
+    ic_cdk_timers::set_timer_interval(REQUEST_EXPIRY_INTERVAL, expire_requests);
 }
 
-fn is_authenticated() -> bool {
-    get_caller() != Principal::anonymous()
+fn rebuild_certified_state() {
+    let offers = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .iter()
+            .map(|(_, offer)| offer)
+            .collect::<Vec<_>>()
+    });
+    certification::rebuild(offers.iter(), &compute_platform_stats(), icrc3::tip());
 }
 
 // User management functions
 #[ic_cdk::query]
 fn get_current_user() -> ApiResponse<Option<UserProfile>> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
-    }
-
-    let caller = get_caller();
+    let caller = match permissions::identify() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
     let user = USERS.with(|users| users.borrow().get(&caller));
 
     ApiResponse::success(user)
@@ -70,111 +441,1505 @@ fn get_current_user() -> ApiResponse<Option<UserProfile>> {
 
 #[ic_cdk::update]
 fn register_user(request: RegisterUserRequest) -> ApiResponse<UserProfile> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
+    let _call = metrics::track("register_user");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
     }
-
-    let caller = get_caller();
+    let caller = match permissions::identify() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
 
     // Check if user already exists
     if USERS.with(|users| users.borrow().contains_key(&caller)) {
         return ApiResponse::error("User already registered".to_string());
     }
 
+    if matches!(request.role, UserRole::Verifier) {
+        return ApiResponse::error("Verifier role must be granted by an admin".to_string());
+    }
+    // Controllers register the first admin; everyone else is granted the
+    // role through update_user_role or a proposal
+    if matches!(request.role, UserRole::Admin) && !ic_cdk::api::is_controller(&caller) {
+        return ApiResponse::error("Admin role must be granted by an admin".to_string());
+    }
+
+    let display_name = match validation::validate_display_name(&request.display_name) {
+        Ok(display_name) => display_name,
+        Err(error) => return ApiResponse::error(error),
+    };
+    let email = match validation::validate_email(&request.email) {
+        Ok(email) => email,
+        Err(error) => return ApiResponse::error(error),
+    };
+
     let now = get_current_time();
     let user = UserProfile {
         principal: caller,
         role: request.role,
-        display_name: request.display_name,
-        email: request.email,
+        display_name,
+        email,
+        email_verified: Some(false),
+        kyc_status: Some(kyc::status(&caller)),
+        status: Some(AccountStatus::Active),
         created_at: now,
         updated_at: now,
     };
 
-    USERS.with(|users| {
-        users.borrow_mut().insert(caller, user.clone());
+    USERS.with(|users| {
+        users.borrow_mut().insert(caller, user.clone());
+    });
+    stats::on_user(None, &user);
+    audit::record(
+        caller,
+        "register_user",
+        EntityType::User,
+        &caller.to_text(),
+        None,
+        Some(&user),
+    );
+    if matches!(user.role, UserRole::Admin) {
+        if let Err(error) = permissions::set_admin_grant(caller, caller, Some(caller)) {
+            return ApiResponse::error(error);
+        }
+    }
+    refresh_certified_stats();
+
+    ApiResponse::success(user)
+}
+
+#[ic_cdk::update]
+fn update_profile(request: UpdateProfileRequest) -> ApiResponse<UserProfile> {
+    let _call = metrics::track("update_profile");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let (caller, mut user) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if matches!(user.account_status(), AccountStatus::Erased) {
+        return ApiResponse::error("Account has been erased".to_string());
+    }
+
+    let before = user.clone();
+
+    if let Some(display_name) = request.display_name {
+        match validation::validate_display_name(&display_name) {
+            Ok(display_name) => user.display_name = display_name,
+            Err(error) => return ApiResponse::error(error),
+        }
+    }
+    if let Some(email) = request.email {
+        match validation::validate_email(&email) {
+            Ok(email) if email != user.email => {
+                user.email = email;
+                user.email_verified = Some(false);
+                email_verification::clear(&caller);
+            }
+            Ok(_) => {}
+            Err(error) => return ApiResponse::error(error),
+        }
+    }
+
+    user.updated_at = get_current_time();
+    USERS.with(|users| {
+        users.borrow_mut().insert(caller, user.clone());
+    });
+    audit::record(
+        caller,
+        "update_profile",
+        EntityType::User,
+        &caller.to_text(),
+        Some(&before),
+        Some(&user),
+    );
+
+    ApiResponse::success(user)
+}
+
+#[ic_cdk::update]
+fn deactivate_account() -> ApiResponse<UserProfile> {
+    let _call = metrics::track("deactivate_account");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    set_own_account_status("deactivate_account", AccountStatus::Deactivated)
+}
+
+#[ic_cdk::update]
+fn reactivate_account() -> ApiResponse<UserProfile> {
+    let _call = metrics::track("reactivate_account");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    set_own_account_status("reactivate_account", AccountStatus::Active)
+}
+
+// Toggles the caller between active and deactivated; erased accounts are final
+fn set_own_account_status(action: &str, status: AccountStatus) -> ApiResponse<UserProfile> {
+    let (caller, mut user) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if matches!(user.account_status(), AccountStatus::Erased) {
+        return ApiResponse::error("Account has been erased".to_string());
+    }
+
+    let before = user.clone();
+    user.status = Some(status);
+    user.updated_at = get_current_time();
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(caller, user.clone());
+    });
+    audit::record(
+        caller,
+        action,
+        EntityType::User,
+        &caller.to_text(),
+        Some(&before),
+        Some(&user),
+    );
+
+    ApiResponse::success(user)
+}
+
+/// Erases the caller's personal data. Offers, requests and transactions are
+/// kept for compliance, but the caller's open offers and pending requests are
+/// cancelled and free-text request messages are cleared, including in
+/// archived requests.
+#[ic_cdk::update]
+async fn delete_account() -> ApiResponse<UserProfile> {
+    let _call = metrics::track("delete_account");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let (caller, mut user) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if matches!(user.account_status(), AccountStatus::Erased) {
+        return ApiResponse::error("Account has been erased".to_string());
+    }
+
+    let now = get_current_time();
+
+    // Withdraw the caller's active offers; organization offers stay with the organization
+    let offers = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .iter()
+            .filter(|(_, offer)| offer.farmer == caller && offer.organization.is_none())
+            .filter(|(_, offer)| matches!(offer.status, OfferStatus::Active))
+            .map(|(_, offer)| offer)
+            .collect::<Vec<_>>()
+    });
+    let withdrawn_offer_ids = offers
+        .iter()
+        .map(|offer| offer.id.clone())
+        .collect::<Vec<_>>();
+
+    for before in offers {
+        let mut offer = before.clone();
+        offer.status = OfferStatus::Cancelled;
+        offer.updated_at = now;
+
+        OFFERS.with(|offers| {
+            offers.borrow_mut().insert(offer.id.clone(), offer.clone());
+        });
+        stats::on_offer(Some(&before), &offer);
+        audit::record(
+            caller,
+            "delete_account",
+            EntityType::Offer,
+            &offer.id,
+            Some(&before),
+            Some(&offer),
+        );
+        certification::certify_offer(&offer);
+    }
+
+    // Cancel pending requests by or to the caller and clear the caller's messages
+    let requests = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .filter(|(_, req)| {
+                req.investor == caller || withdrawn_offer_ids.contains(&req.offer_id)
+            })
+            .map(|(_, req)| req)
+            .collect::<Vec<_>>()
+    });
+
+    for before in requests {
+        let mut req = before.clone();
+        if matches!(req.status, RequestStatus::Pending) {
+            req.status = RequestStatus::Cancelled;
+        }
+        if req.investor == caller {
+            req.message = String::new();
+        }
+        req.updated_at = now;
+
+        REQUESTS.with(|requests| {
+            requests.borrow_mut().insert(req.id.clone(), req.clone());
+        });
+        stats::on_request(Some(&before), &req);
+        audit::record(
+            caller,
+            "delete_account",
+            EntityType::Request,
+            &req.id,
+            Some(&before),
+            Some(&req),
+        );
+    }
+
+    let before = user.clone();
+    user.display_name = ERASED_DISPLAY_NAME.to_string();
+    user.email = String::new();
+    user.email_verified = Some(false);
+    user.kyc_status = Some(KycStatus::Unverified);
+    user.status = Some(AccountStatus::Erased);
+    user.updated_at = now;
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(caller, user.clone());
+    });
+    audit::record(
+        caller,
+        "delete_account",
+        EntityType::User,
+        &caller.to_text(),
+        Some(&before),
+        Some(&user),
+    );
+    email_verification::clear(&caller);
+    kyc::erase(&caller);
+    inbox::erase(&caller);
+    linking::revoke_all(ic_cdk::caller(), caller);
+    refresh_certified_stats();
+
+    // The local erasure stands even if this fails; the archiving timer retries
+    if let Err(error) = archive::erase_party(caller).await {
+        ic_cdk::println!("Archive erasure of {} deferred: {}", caller, error);
+    }
+
+    ApiResponse::success(user)
+}
+
+// Email verification functions
+/// Sends a one-time code to the caller's email address.
+#[ic_cdk::update]
+async fn request_email_verification() -> ApiResponse<EmailVerificationStatus> {
+    let _call = metrics::track("request_email_verification");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let (caller, user) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if matches!(user.account_status(), AccountStatus::Erased) {
+        return ApiResponse::error("Account has been erased".to_string());
+    }
+    if user.has_verified_email() {
+        return ApiResponse::error("Email is already verified".to_string());
+    }
+    if let Err(limited) = rate_limit::consume(caller, &[RateLimitedAction::SendMessage]) {
+        return ApiResponse::rate_limited(limited);
+    }
+
+    match email_verification::issue_code(caller, user.email).await {
+        Ok(status) => {
+            audit::record(
+                caller,
+                "request_email_verification",
+                EntityType::User,
+                &caller.to_text(),
+                None,
+                Some(&status),
+            );
+            ApiResponse::success(status)
+        }
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn verify_email(code: String) -> ApiResponse<UserProfile> {
+    let _call = metrics::track("verify_email");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let (caller, mut user) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = email_verification::verify_code(caller, &user.email, &code) {
+        return ApiResponse::error(error);
+    }
+
+    let before = user.clone();
+    user.email_verified = Some(true);
+    user.updated_at = get_current_time();
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(caller, user.clone());
+    });
+    audit::record(
+        caller,
+        "verify_email",
+        EntityType::User,
+        &caller.to_text(),
+        Some(&before),
+        Some(&user),
+    );
+
+    ApiResponse::success(user)
+}
+
+#[ic_cdk::query]
+fn get_email_verification_status() -> ApiResponse<Option<EmailVerificationStatus>> {
+    let (caller, _) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(email_verification::status(&caller))
+}
+
+#[ic_cdk::update]
+fn configure_notifier(config: NotifierConfig) -> ApiResponse<NotifierConfig> {
+    let _call = metrics::track("configure_notifier");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageNotifier) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match notifier::configure(caller, config) {
+        Ok(config) => ApiResponse::success(notifier::redacted(config)),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_notifier_config() -> ApiResponse<NotifierConfig> {
+    if let Err(error) = permissions::authorize(Permission::ManageNotifier) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(notifier::redacted(notifier::get_config()))
+}
+
+/// Prometheus metrics at `/metrics`. Responses are not certified, so
+/// scrape through the raw domain.
+#[ic_cdk::query]
+fn http_request(request: HttpRequest) -> HttpGatewayResponse {
+    metrics::http_request(request)
+}
+
+#[ic_cdk::query]
+fn transform_relay_response(args: TransformArgs) -> HttpResponse {
+    notifier::transform(args)
+}
+
+// Account linking functions
+/// Issues a one-time challenge for adding another principal to the
+/// caller's account. The new principal redeems it with `link_principal`.
+#[ic_cdk::update]
+async fn create_link_challenge(label: String) -> ApiResponse<LinkChallengeResponse> {
+    let _call = metrics::track("create_link_challenge");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match linking::create_challenge(caller, label).await {
+        Ok(response) => ApiResponse::success(response),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn link_principal(challenge: String) -> ApiResponse<LinkedPrincipal> {
+    let _call = metrics::track("link_principal");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    // The new principal links itself, so it is used as is rather than resolved
+    if let Err(error) = permissions::identify() {
+        return ApiResponse::error(error);
+    }
+
+    match linking::link(ic_cdk::caller(), &challenge) {
+        Ok(link) => ApiResponse::success(link),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_linked_principals() -> ApiResponse<Vec<LinkedPrincipal>> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(linking::linked_principals(&caller))
+}
+
+/// Unlinks a principal from the caller's account. Any principal of the
+/// account may call this, including the one being unlinked.
+#[ic_cdk::update]
+fn revoke_linked_principal(principal: Principal) -> ApiResponse<LinkedPrincipal> {
+    let _call = metrics::track("revoke_linked_principal");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    // A lost or compromised key can be unlinked from a deactivated account too
+    let (caller, _) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match linking::revoke(ic_cdk::caller(), caller, principal) {
+        Ok(link) => ApiResponse::success(link),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Organization functions
+#[ic_cdk::update]
+fn create_organization(request: CreateOrganizationRequest) -> ApiResponse<Organization> {
+    let _call = metrics::track("create_organization");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match organizations::create(caller, request) {
+        Ok(organization) => ApiResponse::success(organization),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_organization(organization_id: String) -> ApiResponse<Option<Organization>> {
+    ApiResponse::success(organizations::get(&organization_id))
+}
+
+/// Organizations the caller belongs to, with the caller's role in each.
+#[ic_cdk::query]
+fn get_my_organizations() -> ApiResponse<Vec<OrgMember>> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(organizations::memberships(&caller))
+}
+
+#[ic_cdk::query]
+fn get_organization_members(organization_id: String) -> ApiResponse<Vec<OrgMember>> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if !organizations::has_role(&organization_id, &caller, OrgRole::Viewer) {
+        return ApiResponse::error("Access denied - not an organization member".to_string());
+    }
+
+    ApiResponse::success(organizations::members(&organization_id))
+}
+
+/// Adds a member or changes their role (owners only).
+#[ic_cdk::update]
+fn set_org_member(
+    organization_id: String,
+    principal: Principal,
+    role: OrgRole,
+) -> ApiResponse<OrgMember> {
+    let _call = metrics::track("set_org_member");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    let member = linking::resolve(principal);
+    match organizations::set_member(caller, &organization_id, member, role) {
+        Ok(member) => ApiResponse::success(member),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+/// Removes a member (owners only), or lets the caller leave.
+#[ic_cdk::update]
+fn remove_org_member(organization_id: String, principal: Principal) -> ApiResponse<OrgMember> {
+    let _call = metrics::track("remove_org_member");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let (caller, _) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    let member = linking::resolve(principal);
+    match organizations::remove_member(caller, &organization_id, member) {
+        Ok(member) => ApiResponse::success(member),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_organization_offers(organization_id: String) -> ApiResponse<Vec<InvestmentOffer>> {
+    let offers = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .iter()
+            .filter(|(_, offer)| offer.organization.as_deref() == Some(organization_id.as_str()))
+            .map(|(_, offer)| offer)
+            .collect::<Vec<_>>()
+    });
+
+    ApiResponse::success(offers)
+}
+
+#[ic_cdk::query]
+fn get_organization_transactions(organization_id: String) -> ApiResponse<Vec<Transaction>> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if !organizations::has_role(&organization_id, &caller, OrgRole::Viewer) {
+        return ApiResponse::error("Access denied - not an organization member".to_string());
+    }
+
+    ApiResponse::success(organizations::transactions(&organization_id))
+}
+
+#[ic_cdk::query]
+fn get_organization_revenue(organization_id: String) -> ApiResponse<OrganizationRevenue> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if !organizations::has_role(&organization_id, &caller, OrgRole::Viewer) {
+        return ApiResponse::error("Access denied - not an organization member".to_string());
+    }
+
+    ApiResponse::success(organizations::revenue(&organization_id))
+}
+
+// Identity verification (KYC) functions
+#[ic_cdk::update]
+fn submit_kyc(request: SubmitKycRequest) -> ApiResponse<KycRecord> {
+    let _call = metrics::track("submit_kyc");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let (caller, user) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if matches!(user.account_status(), AccountStatus::Erased) {
+        return ApiResponse::error("Account has been erased".to_string());
+    }
+
+    match kyc::submit(caller, request) {
+        Ok(record) => ApiResponse::success(record),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_my_kyc() -> ApiResponse<Option<KycRecord>> {
+    let (caller, _) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(kyc::get(&caller))
+}
+
+#[ic_cdk::query]
+fn get_pending_kyc() -> ApiResponse<Vec<KycRecord>> {
+    if let Err(error) = permissions::authorize(Permission::ReviewKyc) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(kyc::pending())
+}
+
+#[ic_cdk::update]
+fn review_kyc(request: ReviewKycRequest) -> ApiResponse<KycRecord> {
+    let _call = metrics::track("review_kyc");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ReviewKyc) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match kyc::review(caller, request) {
+        Ok(record) => ApiResponse::success(record),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Platform settings functions
+#[ic_cdk::query]
+fn get_public_settings() -> PublicSettings {
+    settings::public()
+}
+
+/// Day or week candles for one product type, for pricing offers and judging
+/// deals.
+#[ic_cdk::query]
+fn get_price_history(query: PriceHistoryQuery) -> ApiResponse<Vec<PriceCandle>> {
+    match prices::history(query) {
+        Ok(candles) => ApiResponse::success(candles),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_reference_prices(
+    product_type: Option<ProductType>,
+    region: Option<String>,
+) -> Vec<ReferencePrice> {
+    prices::reference_prices(product_type, region)
+}
+
+#[ic_cdk::query]
+fn get_platform_settings() -> ApiResponse<PlatformSettings> {
+    if let Err(error) = permissions::authorize(Permission::ManageSettings) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(settings::get())
+}
+
+#[ic_cdk::update]
+fn update_platform_settings(new_settings: PlatformSettings) -> ApiResponse<PlatformSettings> {
+    let _call = metrics::track("update_platform_settings");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageSettings) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match settings::update(caller, new_settings) {
+        Ok(new_settings) => ApiResponse::success(new_settings),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Cycles functions
+/// Accepts cycles sent by any canister, such as a cycles wallet, and
+/// records the sender.
+#[ic_cdk::update]
+fn wallet_receive() -> WalletReceiveResult {
+    let _call = metrics::track("wallet_receive");
+    cycles::receive()
+}
+
+#[ic_cdk::query]
+fn get_cycles_status() -> ApiResponse<CyclesStatus> {
+    if let Err(error) = permissions::authorize(Permission::ManageCycles) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(cycles::status())
+}
+
+#[ic_cdk::query]
+fn get_cycles_top_ups(start: u64, limit: u64) -> ApiResponse<Vec<CyclesTopUp>> {
+    if let Err(error) = permissions::authorize(Permission::ManageCycles) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(cycles::top_ups(start, limit))
+}
+
+#[ic_cdk::query]
+fn get_cycles_contributors() -> ApiResponse<Vec<CyclesContributor>> {
+    if let Err(error) = permissions::authorize(Permission::ManageCycles) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(cycles::contributors())
+}
+
+#[ic_cdk::update]
+fn configure_cycles_monitor(config: CyclesMonitorConfig) -> ApiResponse<CyclesMonitorConfig> {
+    let _call = metrics::track("configure_cycles_monitor");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageCycles) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match cycles::configure(caller, config) {
+        Ok(config) => ApiResponse::success(config),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Treasury functions
+#[ic_cdk::query]
+fn get_treasury_config() -> ApiResponse<TreasuryConfig> {
+    if let Err(error) = permissions::authorize(Permission::ViewRevenue) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(treasury::get_config())
+}
+
+#[ic_cdk::update]
+fn configure_treasury(config: TreasuryConfig) -> ApiResponse<TreasuryConfig> {
+    let _call = metrics::track("configure_treasury");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageTreasury) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match treasury::configure(caller, config) {
+        Ok(config) => ApiResponse::success(config),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_treasury_balances() -> ApiResponse<Vec<TreasuryBalance>> {
+    if let Err(error) = permissions::authorize(Permission::ViewRevenue) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(treasury::balances())
+}
+
+#[ic_cdk::query]
+fn get_treasury_summary(
+    from: u64,
+    to: u64,
+    bucket: TimeBucket,
+) -> ApiResponse<Vec<TreasuryPeriodSummary>> {
+    if let Err(error) = permissions::authorize(Permission::ViewRevenue) {
+        return ApiResponse::error(error);
+    }
+
+    match treasury::summaries(from, to, bucket) {
+        Ok(summaries) => ApiResponse::success(summaries),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// An update call: the ledger usually lives on another subnet, out of
+// reach of composite queries
+#[ic_cdk::update]
+async fn reconcile_treasury() -> ApiResponse<ReconciliationReport> {
+    let _call = metrics::track("reconcile_treasury");
+    if let Err(error) = permissions::authorize(Permission::ViewRevenue) {
+        return ApiResponse::error(error);
+    }
+
+    match treasury::reconcile().await {
+        Ok(report) => ApiResponse::success(report),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn record_payout(transaction_id: String, reference: Option<String>) -> ApiResponse<TreasuryEntry> {
+    let _call = metrics::track("record_payout");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageTreasury) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(paused) = pause::check(PausableFeature::Settlements) {
+        return ApiResponse::paused(paused);
+    }
+
+    match treasury::record_payout(caller, &transaction_id, reference) {
+        Ok(entry) => ApiResponse::success(entry),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Emergency pause functions
+#[ic_cdk::query]
+fn get_pause_state() -> PauseState {
+    pause::get()
+}
+
+/// Pauses `feature`, or the whole platform when it is `None`. Queries keep
+/// working; affected updates fail with `ErrorKind::Paused`.
+#[ic_cdk::update]
+fn pause(feature: Option<PausableFeature>, reason: Option<String>) -> ApiResponse<PauseState> {
+    let _call = metrics::track("pause");
+    let caller = match pause::authorize() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match pause::set_paused(caller, feature, true, reason) {
+        Ok(state) => ApiResponse::success(state),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn unpause(feature: Option<PausableFeature>) -> ApiResponse<PauseState> {
+    let _call = metrics::track("unpause");
+    let caller = match pause::authorize() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match pause::set_paused(caller, feature, false, None) {
+        Ok(state) => ApiResponse::success(state),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Rate limiting functions
+#[ic_cdk::query]
+fn get_rate_limit_config() -> ApiResponse<RateLimitConfig> {
+    if let Err(error) = permissions::authorize(Permission::ManageRateLimits) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(rate_limit::get_config())
+}
+
+#[ic_cdk::update]
+fn configure_rate_limits(config: RateLimitConfig) -> ApiResponse<RateLimitConfig> {
+    let _call = metrics::track("configure_rate_limits");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageRateLimits) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match rate_limit::configure(caller, config) {
+        Ok(config) => ApiResponse::success(config),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_rate_limit_overrides() -> ApiResponse<Vec<(Principal, RateLimitOverride)>> {
+    if let Err(error) = permissions::authorize(Permission::ManageRateLimits) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(rate_limit::overrides())
+}
+
+/// Exempts a trusted account from rate limiting or gives it its own limits;
+/// `None` restores the defaults.
+#[ic_cdk::update]
+fn set_rate_limit_override(
+    principal: Principal,
+    rate_limit_override: Option<RateLimitOverride>,
+) -> ApiResponse<Option<RateLimitOverride>> {
+    let _call = metrics::track("set_rate_limit_override");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageRateLimits) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match rate_limit::set_override(caller, principal, rate_limit_override.clone()) {
+        Ok(()) => ApiResponse::success(rate_limit_override),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_kyc_policy() -> KycPolicy {
+    kyc::get_policy()
+}
+
+#[ic_cdk::update]
+fn configure_kyc_policy(policy: KycPolicy) -> ApiResponse<KycPolicy> {
+    let _call = metrics::track("configure_kyc_policy");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageKycPolicy) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match kyc::configure_policy(caller, policy) {
+        Ok(policy) => ApiResponse::success(policy),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Composite so that archived transactions and requests are included
+#[ic_cdk::query(composite = true)]
+async fn export_my_data(request: ExportRequest) -> ApiResponse<DataExport> {
+    let (caller, _) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+    let page_size = request.page_size.unwrap_or(export::DEFAULT_PAGE_SIZE);
+
+    match export::export_page(caller, request.cursor, page_size, request.format).await {
+        Ok(export) => ApiResponse::success(export),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+/// Admin variant for regulator requests. An update call, so that every
+/// export of someone else's data is recorded in the audit log.
+#[ic_cdk::update]
+async fn export_user_data(principal: Principal, request: ExportRequest) -> ApiResponse<DataExport> {
+    let _call = metrics::track("export_user_data");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ExportUserData) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    audit::record(
+        caller,
+        "export_user_data",
+        EntityType::User,
+        &principal.to_text(),
+        None::<&UserProfile>,
+        None,
+    );
+
+    let page_size = request.page_size.unwrap_or(export::DEFAULT_PAGE_SIZE);
+
+    match export::export_page(principal, request.cursor, page_size, request.format).await {
+        Ok(export) => ApiResponse::success(export),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn update_user_role(principal: Principal, new_role: UserRole) -> ApiResponse<UserProfile> {
+    let _call = metrics::track("update_user_role");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageRoles) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match permissions::set_user_role(caller, principal, new_role) {
+        Ok(user) => ApiResponse::success(user),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Permission functions
+#[ic_cdk::query]
+fn list_roles() -> Vec<RoleDefinition> {
+    permissions::list_roles()
+}
+
+#[ic_cdk::update]
+fn define_role(request: DefineRoleRequest) -> ApiResponse<RoleDefinition> {
+    let _call = metrics::track("define_role");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageRoles) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match permissions::define_role(caller, request) {
+        Ok(role) => ApiResponse::success(role),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn delete_role(name: String) -> ApiResponse<RoleDefinition> {
+    let _call = metrics::track("delete_role");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageRoles) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match permissions::delete_role(caller, &name) {
+        Ok(role) => ApiResponse::success(role),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn grant_role(principal: Principal, name: String) -> ApiResponse<RoleGrants> {
+    let _call = metrics::track("grant_role");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageRoles) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match permissions::grant_role(caller, linking::resolve(principal), &name) {
+        Ok(grants) => ApiResponse::success(grants),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn revoke_role(principal: Principal, name: String) -> ApiResponse<RoleGrants> {
+    let _call = metrics::track("revoke_role");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageRoles) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match permissions::revoke_role(caller, linking::resolve(principal), &name) {
+        Ok(grants) => ApiResponse::success(grants),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_my_permissions() -> ApiResponse<EffectivePermissions> {
+    let (caller, _) = match permissions::account() {
+        Ok(account) => account,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match permissions::effective_permissions(&caller) {
+        Some(permissions) => ApiResponse::success(permissions),
+        None => ApiResponse::error("User not found".to_string()),
+    }
+}
+
+#[ic_cdk::query]
+fn get_user_permissions(principal: Principal) -> ApiResponse<EffectivePermissions> {
+    if let Err(error) = permissions::authorize(Permission::ViewUsers) {
+        return ApiResponse::error(error);
+    }
+
+    match permissions::effective_permissions(&linking::resolve(principal)) {
+        Some(permissions) => ApiResponse::success(permissions),
+        None => ApiResponse::error("User not found".to_string()),
+    }
+}
+
+// Multi-signature admin functions
+/// Proposes a sensitive admin action. The proposer's approval counts
+/// towards the threshold.
+#[ic_cdk::update]
+fn propose_admin_action(action: AdminAction) -> ApiResponse<AdminProposal> {
+    let _call = metrics::track("propose_admin_action");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ApproveAdminActions) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match multisig::propose(caller, action) {
+        Ok(proposal) => ApiResponse::success(proposal),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+/// Approves a proposal; the action executes once the threshold is reached.
+#[ic_cdk::update]
+fn approve_admin_action(proposal_id: u64) -> ApiResponse<AdminProposal> {
+    let _call = metrics::track("approve_admin_action");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ApproveAdminActions) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match multisig::approve(caller, proposal_id) {
+        Ok(proposal) => ApiResponse::success(proposal),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::update]
+fn withdraw_admin_proposal(proposal_id: u64) -> ApiResponse<AdminProposal> {
+    let _call = metrics::track("withdraw_admin_proposal");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ApproveAdminActions) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    match multisig::withdraw(caller, proposal_id) {
+        Ok(proposal) => ApiResponse::success(proposal),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_admin_proposals(only_open: bool) -> ApiResponse<Vec<AdminProposal>> {
+    if let Err(error) = permissions::authorize(Permission::ApproveAdminActions) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(multisig::list(only_open))
+}
+
+#[ic_cdk::query]
+fn get_admin_proposal(proposal_id: u64) -> ApiResponse<Option<AdminProposal>> {
+    if let Err(error) = permissions::authorize(Permission::ApproveAdminActions) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(multisig::get(proposal_id))
+}
+
+#[ic_cdk::query]
+fn get_multisig_policy() -> MultisigPolicy {
+    multisig::get_policy()
+}
+
+// Forced cancellations and refunds, only reachable through proposals
+fn force_cancel_offer(actor: Principal, offer_id: &str) -> Result<(), String> {
+    let before = OFFERS
+        .with(|offers| offers.borrow().get(&offer_id.to_string()))
+        .ok_or("Offer not found")?;
+
+    if !matches!(before.status, OfferStatus::Active) {
+        return Err("Offer is not active".to_string());
+    }
+
+    let now = get_current_time();
+    let mut offer = before.clone();
+    offer.status = OfferStatus::Cancelled;
+    offer.updated_at = now;
+
+    OFFERS.with(|offers| {
+        offers.borrow_mut().insert(offer.id.clone(), offer.clone());
+    });
+    stats::on_offer(Some(&before), &offer);
+    audit::record(
+        actor,
+        "force_cancel_offer",
+        EntityType::Offer,
+        &offer.id,
+        Some(&before),
+        Some(&offer),
+    );
+    certification::certify_offer(&offer);
+
+    let pending = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .filter(|(_, req)| req.offer_id == offer.id)
+            .filter(|(_, req)| matches!(req.status, RequestStatus::Pending))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+    });
+    for request_id in pending {
+        force_cancel_request(actor, &request_id)?;
+    }
+
+    refresh_certified_stats();
+    Ok(())
+}
+
+fn force_cancel_request(actor: Principal, request_id: &str) -> Result<(), String> {
+    let before = REQUESTS
+        .with(|requests| requests.borrow().get(&request_id.to_string()))
+        .ok_or("Investment request not found")?;
+
+    if !matches!(before.status, RequestStatus::Pending) {
+        return Err("Request already processed".to_string());
+    }
+
+    let mut req = before.clone();
+    req.status = RequestStatus::Cancelled;
+    req.updated_at = get_current_time();
+
+    REQUESTS.with(|requests| {
+        requests.borrow_mut().insert(req.id.clone(), req.clone());
+    });
+    stats::on_request(Some(&before), &req);
+    audit::record(
+        actor,
+        "force_cancel_request",
+        EntityType::Request,
+        &req.id,
+        Some(&before),
+        Some(&req),
+    );
+    inbox::on_request_cancelled(&req);
+    refresh_certified_stats();
+
+    Ok(())
+}
+
+/// Marks pending requests past their `expires_at` as expired.
+fn expire_requests() {
+    let now = get_current_time();
+    let expired = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .filter(|(_, req)| {
+                matches!(req.status, RequestStatus::Pending) && req.expires_at <= now
+            })
+            .map(|(_, req)| req)
+            .collect::<Vec<_>>()
+    });
+
+    for before in expired {
+        let mut req = before.clone();
+        req.status = RequestStatus::Expired;
+        req.updated_at = now;
+
+        REQUESTS.with(|requests| {
+            requests.borrow_mut().insert(req.id.clone(), req.clone());
+        });
+        stats::on_request(Some(&before), &req);
+        audit::record(
+            ic_cdk::id(),
+            "expire_request",
+            EntityType::Request,
+            &req.id,
+            Some(&before),
+            Some(&req),
+        );
+        inbox::on_request_expired(&req);
+    }
+}
+
+fn refund_transaction(actor: Principal, transaction_id: &str) -> Result<(), String> {
+    let before = TRANSACTIONS
+        .with(|transactions| transactions.borrow().get(&transaction_id.to_string()))
+        .ok_or("Transaction not found")?;
+
+    if matches!(before.status, TransactionStatus::Refunded) {
+        return Err("Transaction already refunded".to_string());
+    }
+    if treasury::is_paid_out(&before.id) {
+        return Err("Transaction has already been paid out".to_string());
+    }
+    pause::check(PausableFeature::Settlements).map_err(|paused| paused.message())?;
+
+    let mut txn = before.clone();
+    txn.status = TransactionStatus::Refunded;
+    txn.updated_at = get_current_time();
+
+    treasury::on_refunded(actor, &txn, before.settled_at.is_some());
+    prices::on_refunded(&txn.id);
+
+    TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow_mut()
+            .insert(txn.id.clone(), txn.clone());
     });
+    stats::on_transaction(Some(&before), &txn);
+    inbox::on_refunded(&txn);
+    audit::record(
+        actor,
+        "refund_transaction",
+        EntityType::Transaction,
+        &txn.id,
+        Some(&before),
+        Some(&txn),
+    );
+    icrc3::append_transaction_block(icrc3::REFUND_BLOCK_TYPE, &txn);
+    refresh_certified_stats();
 
-    ApiResponse::success(user)
+    Ok(())
 }
 
+// Offer management functions
 #[ic_cdk::update]
-fn update_user_role(principal: Principal, new_role: UserRole) -> ApiResponse<UserProfile> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
+fn create_agricultural_offer(request: CreateOfferRequest) -> ApiResponse<InvestmentOffer> {
+    let _call = metrics::track("create_agricultural_offer");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
     }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
 
-    let caller = get_caller();
-
-    // Check if caller is admin
-    if !USERS.with(|users| {
-        users
-            .borrow()
-            .get(&caller)
-            .map(|user| matches!(user.role, UserRole::Admin))
-            .unwrap_or(false)
-    }) {
-        return ApiResponse::error("Admin access required".to_string());
+    if let Err(paused) = pause::check(PausableFeature::Offers) {
+        return ApiResponse::paused(paused);
     }
 
-    USERS.with(|users| {
-        let mut users_map = users.borrow_mut();
-        match users_map.get(&principal) {
-            Some(mut user) => {
-                user.role = new_role;
-                user.updated_at = get_current_time();
-                users_map.insert(principal, user.clone());
-                ApiResponse::success(user)
+    // Selling for an organization takes its manager role instead of the permission
+    match &request.organization_id {
+        Some(organization_id) => {
+            if organizations::get(organization_id).is_none() {
+                return ApiResponse::error("Organization not found".to_string());
+            }
+            if !organizations::has_role(organization_id, &caller, OrgRole::Manager) {
+                return ApiResponse::error("Organization manager role required".to_string());
             }
-            None => ApiResponse::error("User not found".to_string()),
         }
-    })
-}
-
-// Offer management functions
-#[ic_cdk::update]
-fn create_agricultural_offer(request: CreateOfferRequest) -> ApiResponse<InvestmentOffer> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
-    }
-
-    let caller = get_caller();
-
-    // Check if user is farmer
-    let user_role = USERS.with(|users| users.borrow().get(&caller).map(|user| user.role.clone()));
-
-    match user_role {
-        Some(UserRole::Farmer) | Some(UserRole::Admin) => {
-            let now = get_current_time();
-            let offer_id = generate_id("offer");
-
-            let offer = InvestmentOffer {
-                id: offer_id.clone(),
-                farmer: caller,
-                product_name: request.product_name,
-                product_type: request.product_type,
-                total_quantity: request.total_quantity,
-                available_quantity: request.total_quantity, // Initially all available
-                price_per_kg: request.price_per_kg,
-                description: request.description,
-                harvest_date: request.harvest_date,
-                location: request.location,
-                quality_grade: request.quality_grade,
-                minimum_investment: request.minimum_investment,
-                status: OfferStatus::Active,
-                created_at: now,
-                updated_at: now,
-            };
-
-            OFFERS.with(|offers| {
-                offers.borrow_mut().insert(offer_id, offer.clone());
-            });
-
-            ApiResponse::success(offer)
+        None => {
+            if let Err(error) = permissions::check(&caller, Permission::CreateOffer) {
+                return ApiResponse::error(error);
+            }
         }
-        Some(_) => ApiResponse::error("Farmer role required".to_string()),
-        None => ApiResponse::error("User not found".to_string()),
     }
+
+    if let Err(error) = settings::check_offer(&request) {
+        return ApiResponse::error(error);
+    }
+
+    let value = request.total_quantity as f64 * request.price_per_kg;
+    if let Err(error) = kyc::check(&caller, value, kyc::get_policy().offer_value_threshold) {
+        return ApiResponse::error(error);
+    }
+    if let Err(limited) = rate_limit::consume(caller, &[RateLimitedAction::CreateOffer]) {
+        return ApiResponse::rate_limited(limited);
+    }
+
+    let now = get_current_time();
+    let offer_id = generate_id("offer");
+
+    let offer = InvestmentOffer {
+        id: offer_id.clone(),
+        farmer: caller,
+        product_name: request.product_name,
+        product_type: request.product_type,
+        total_quantity: request.total_quantity,
+        available_quantity: request.total_quantity, // Initially all available
+        price_per_kg: request.price_per_kg,
+        description: request.description,
+        harvest_date: request.harvest_date,
+        location: request.location,
+        quality_grade: request.quality_grade,
+        minimum_investment: request.minimum_investment,
+        organization: request.organization_id,
+        status: OfferStatus::Active,
+        created_at: now,
+        updated_at: now,
+    };
+
+    OFFERS.with(|offers| {
+        offers.borrow_mut().insert(offer_id, offer.clone());
+    });
+    stats::on_offer(None, &offer);
+    audit::record(
+        caller,
+        "create_agricultural_offer",
+        EntityType::Offer,
+        &offer.id,
+        None,
+        Some(&offer),
+    );
+    certification::certify_offer(&offer);
+    refresh_certified_stats();
+
+    ApiResponse::success(offer)
 }
 
 #[ic_cdk::query]
@@ -193,11 +1958,10 @@ fn get_available_offers() -> ApiResponse<Vec<InvestmentOffer>> {
 
 #[ic_cdk::query]
 fn get_farmer_offers() -> ApiResponse<Vec<InvestmentOffer>> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
-    }
-
-    let caller = get_caller();
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
     let offers = OFFERS.with(|offers| {
         offers
             .borrow()
@@ -210,87 +1974,155 @@ fn get_farmer_offers() -> ApiResponse<Vec<InvestmentOffer>> {
     ApiResponse::success(offers)
 }
 
+/// Funding, fill rate, pricing and pending obligations for the caller's
+/// offers.
+#[ic_cdk::query]
+fn get_farmer_analytics() -> ApiResponse<FarmerAnalytics> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(analytics::farmer(caller))
+}
+
 #[ic_cdk::query]
 fn get_offer_by_id(offer_id: String) -> ApiResponse<Option<InvestmentOffer>> {
     let offer = OFFERS.with(|offers| offers.borrow().get(&offer_id));
     ApiResponse::success(offer)
 }
 
+#[ic_cdk::query]
+fn get_certified_offer(
+    offer_id: String,
+) -> ApiResponse<CertifiedResponse<Option<InvestmentOffer>>> {
+    let certificate = match ic_cdk::api::data_certificate() {
+        Some(certificate) => certificate,
+        None => return ApiResponse::error("Certificate only available in query calls".to_string()),
+    };
+
+    let offer = OFFERS.with(|offers| offers.borrow().get(&offer_id));
+    let witness = certification::offer_witness(&offer_id);
+
+    ApiResponse::success(CertifiedResponse {
+        data: offer,
+        certificate,
+        witness: certification::encode_witness(&witness),
+    })
+}
+
 // Investment request functions
 #[ic_cdk::update]
 fn create_investment_request(request: CreateInvestmentRequest) -> ApiResponse<InvestmentRequest> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
-    }
-
-    let caller = get_caller();
-
-    // Check if user is investor
-    let user_role = USERS.with(|users| users.borrow().get(&caller).map(|user| user.role.clone()));
-
-    match user_role {
-        Some(UserRole::Investor) | Some(UserRole::Admin) => {
-            // Verify offer exists and is active
-            let offer_valid = OFFERS.with(|offers| {
-                offers
-                    .borrow()
-                    .get(&request.offer_id)
-                    .map(|offer| {
-                        matches!(offer.status, OfferStatus::Active)
-                            && offer.available_quantity >= request.requested_quantity
-                    })
-                    .unwrap_or(false)
-            });
-
-            if !offer_valid {
-                return ApiResponse::error("Invalid offer or insufficient quantity".to_string());
-            }
+    let _call = metrics::track("create_investment_request");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::CreateInvestmentRequest) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
 
-            let now = get_current_time();
-            let request_id = generate_id("req");
-            let expires_at = now + (7 * 24 * 60 * 60 * 1_000_000_000); // 7 days in nanoseconds
-
-            let investment_request = InvestmentRequest {
-                id: request_id.clone(),
-                offer_id: request.offer_id,
-                investor: caller,
-                requested_quantity: request.requested_quantity,
-                offered_price_per_kg: request.offered_price_per_kg,
-                total_offered: request.requested_quantity as f64 * request.offered_price_per_kg,
-                message: request.message,
-                status: RequestStatus::Pending,
-                created_at: now,
-                updated_at: now,
-                expires_at,
-            };
-
-            REQUESTS.with(|requests| {
-                requests
-                    .borrow_mut()
-                    .insert(request_id, investment_request.clone());
-            });
-
-            ApiResponse::success(investment_request)
-        }
-        Some(_) => ApiResponse::error("Investor role required".to_string()),
-        None => ApiResponse::error("User not found".to_string()),
+    if let Err(paused) = pause::check(PausableFeature::Requests) {
+        return ApiResponse::paused(paused);
+    }
+
+    let settings = settings::get();
+    let pending = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .filter(|(_, req)| req.investor == caller)
+            .filter(|(_, req)| matches!(req.status, RequestStatus::Pending))
+            .count()
+    });
+    if pending >= settings.max_pending_requests_per_investor as usize {
+        return ApiResponse::error(format!(
+            "At most {} pending requests are allowed",
+            settings.max_pending_requests_per_investor
+        ));
+    }
+
+    // Verify offer exists and is active
+    let offer_valid = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .get(&request.offer_id)
+            .map(|offer| {
+                matches!(offer.status, OfferStatus::Active)
+                    && offer.available_quantity >= request.requested_quantity
+            })
+            .unwrap_or(false)
+    });
+
+    if !offer_valid {
+        return ApiResponse::error("Invalid offer or insufficient quantity".to_string());
+    }
+
+    let value = request.requested_quantity as f64 * request.offered_price_per_kg;
+    if let Err(error) = kyc::check(&caller, value, kyc::get_policy().request_value_threshold) {
+        return ApiResponse::error(error);
     }
+
+    let mut actions = vec![RateLimitedAction::CreateRequest];
+    if !request.message.is_empty() {
+        actions.push(RateLimitedAction::SendMessage);
+    }
+    if let Err(limited) = rate_limit::consume(caller, &actions) {
+        return ApiResponse::rate_limited(limited);
+    }
+
+    let now = get_current_time();
+    let request_id = generate_id("req");
+    let expires_at = now + settings.request_ttl_ns;
+
+    let investment_request = InvestmentRequest {
+        id: request_id.clone(),
+        offer_id: request.offer_id,
+        investor: caller,
+        requested_quantity: request.requested_quantity,
+        offered_price_per_kg: request.offered_price_per_kg,
+        total_offered: request.requested_quantity as f64 * request.offered_price_per_kg,
+        message: request.message,
+        status: RequestStatus::Pending,
+        created_at: now,
+        updated_at: now,
+        expires_at,
+    };
+
+    REQUESTS.with(|requests| {
+        requests
+            .borrow_mut()
+            .insert(request_id, investment_request.clone());
+    });
+    stats::on_request(None, &investment_request);
+    inbox::on_request_created(&investment_request);
+    audit::record(
+        caller,
+        "create_investment_request",
+        EntityType::Request,
+        &investment_request.id,
+        None,
+        Some(&investment_request),
+    );
+    refresh_certified_stats();
+
+    ApiResponse::success(investment_request)
 }
 
 #[ic_cdk::query]
 fn get_requests_for_offer(offer_id: String) -> ApiResponse<Vec<InvestmentRequest>> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
-    }
-
-    let caller = get_caller();
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
 
-    // Verify caller is the farmer for this offer
+    // Verify caller is the farmer for this offer or a member of its organization
     let is_offer_owner = OFFERS.with(|offers| {
         offers
             .borrow()
             .get(&offer_id)
-            .map(|offer| offer.farmer == caller)
+            .map(|offer| organizations::can_view_offer(&offer, &caller))
             .unwrap_or(false)
     });
 
@@ -310,14 +2142,14 @@ fn get_requests_for_offer(offer_id: String) -> ApiResponse<Vec<InvestmentRequest
     ApiResponse::success(requests)
 }
 
-#[ic_cdk::query]
-fn get_investor_requests() -> ApiResponse<Vec<InvestmentRequest>> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
-    }
-
-    let caller = get_caller();
-    let requests = REQUESTS.with(|requests| {
+// Composite so that requests moved to the archive canister are still returned
+#[ic_cdk::query(composite = true)]
+async fn get_investor_requests() -> ApiResponse<Vec<InvestmentRequest>> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+    let mut requests = REQUESTS.with(|requests| {
         requests
             .borrow()
             .iter()
@@ -326,6 +2158,11 @@ fn get_investor_requests() -> ApiResponse<Vec<InvestmentRequest>> {
             .collect::<Vec<_>>()
     });
 
+    match archive::fetch_requests(caller).await {
+        Ok(archived) => requests.extend(archived.into_iter().filter(|req| req.investor == caller)),
+        Err(error) => return ApiResponse::error(error),
+    }
+
     ApiResponse::success(requests)
 }
 
@@ -334,11 +2171,20 @@ fn get_investor_requests() -> ApiResponse<Vec<InvestmentRequest>> {
 fn respond_to_investment_request(
     request: RespondToRequestRequest,
 ) -> ApiResponse<InvestmentRequest> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
+    let _call = metrics::track("respond_to_investment_request");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
     }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
 
-    let caller = get_caller();
+    if request.accept {
+        if let Err(paused) = pause::check(PausableFeature::Acceptances) {
+            return ApiResponse::paused(paused);
+        }
+    }
 
     // Get the investment request
     let investment_request = REQUESTS.with(|requests| requests.borrow().get(&request.request_id));
@@ -348,18 +2194,11 @@ fn respond_to_investment_request(
         None => return ApiResponse::error("Investment request not found".to_string()),
     };
 
-    // Verify caller is the farmer for this offer
-    let is_offer_owner = OFFERS.with(|offers| {
-        offers
-            .borrow()
-            .get(&investment_request.offer_id)
-            .map(|offer| offer.farmer == caller)
-            .unwrap_or(false)
-    });
-
-    if !is_offer_owner {
-        return ApiResponse::error("Access denied - not offer owner".to_string());
-    }
+    // Verify caller is the farmer for this offer or manages its organization
+    let offer = match OFFERS.with(|offers| offers.borrow().get(&investment_request.offer_id)) {
+        Some(offer) if organizations::can_manage_offer(&offer, &caller) => offer,
+        _ => return ApiResponse::error("Access denied - not offer owner".to_string()),
+    };
 
     // Check if request is still pending
     if !matches!(investment_request.status, RequestStatus::Pending) {
@@ -367,6 +2206,11 @@ fn respond_to_investment_request(
     }
 
     let now = get_current_time();
+    // Expired requests are swept periodically; until then they are refused here
+    if now >= investment_request.expires_at {
+        return ApiResponse::error("Request has expired".to_string());
+    }
+    let request_before = investment_request.clone();
 
     if request.accept {
         // Accept the request - create transaction and update availability
@@ -377,7 +2221,7 @@ fn respond_to_investment_request(
             id: transaction_id.clone(),
             offer_id: investment_request.offer_id.clone(),
             request_id: investment_request.id.clone(),
-            farmer: caller,
+            farmer: offer.farmer,
             investor: investment_request.investor,
             quantity: investment_request.requested_quantity,
             price_per_kg: investment_request.offered_price_per_kg,
@@ -386,12 +2230,20 @@ fn respond_to_investment_request(
             created_at: now,
             updated_at: now,
             tokenized_at: None,
+            organization: offer.organization.clone(),
+            fees: Some(fees::compute(
+                &offer.farmer,
+                &offer.product_type,
+                investment_request.total_offered,
+            )),
+            settled_at: None,
         };
 
         // Update offer availability
         OFFERS.with(|offers| {
             let mut offers_map = offers.borrow_mut();
             if let Some(mut offer) = offers_map.get(&investment_request.offer_id) {
+                let offer_before = offer.clone();
                 offer.available_quantity -= investment_request.requested_quantity;
                 offer.updated_at = now;
 
@@ -400,11 +2252,33 @@ fn respond_to_investment_request(
                     offer.status = OfferStatus::Completed;
                 }
 
+                audit::record(
+                    caller,
+                    "respond_to_investment_request",
+                    EntityType::Offer,
+                    &offer.id,
+                    Some(&offer_before),
+                    Some(&offer),
+                );
+                certification::certify_offer(&offer);
+                stats::on_offer(Some(&offer_before), &offer);
                 offers_map.insert(investment_request.offer_id.clone(), offer);
             }
         });
 
         // Store transaction
+        audit::record(
+            caller,
+            "respond_to_investment_request",
+            EntityType::Transaction,
+            &transaction.id,
+            None,
+            Some(&transaction),
+        );
+        icrc3::append_transaction_block(icrc3::TRADE_BLOCK_TYPE, &transaction);
+        treasury::on_accepted(caller, &transaction);
+        prices::record(&offer, &transaction);
+        stats::on_transaction(None, &transaction);
         TRANSACTIONS.with(|transactions| {
             transactions
                 .borrow_mut()
@@ -423,19 +2297,103 @@ fn respond_to_investment_request(
             .borrow_mut()
             .insert(request.request_id, investment_request.clone());
     });
+    stats::on_request(Some(&request_before), &investment_request);
+    inbox::on_request_answered(&investment_request);
+    audit::record(
+        caller,
+        "respond_to_investment_request",
+        EntityType::Request,
+        &investment_request.id,
+        Some(&request_before),
+        Some(&investment_request),
+    );
+    refresh_certified_stats();
 
     ApiResponse::success(investment_request)
 }
 
 // Transaction functions
+/// Confirms delivery of a trade: completes the transaction and moves its
+/// fees to the treasury. Called by the investor, or by an admin with
+/// `ManageTrades`.
+#[ic_cdk::update]
+fn settle_transaction(transaction_id: String) -> ApiResponse<Transaction> {
+    let _call = metrics::track("settle_transaction");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(paused) = pause::check(PausableFeature::Settlements) {
+        return ApiResponse::paused(paused);
+    }
+
+    let before = match TRANSACTIONS.with(|transactions| transactions.borrow().get(&transaction_id))
+    {
+        Some(txn) => txn,
+        None => return ApiResponse::error("Transaction not found".to_string()),
+    };
+
+    if before.investor != caller && permissions::check(&caller, Permission::ManageTrades).is_err() {
+        return ApiResponse::error("Only the investor can settle a transaction".to_string());
+    }
+    if !matches!(
+        before.status,
+        TransactionStatus::Confirmed | TransactionStatus::Tokenized
+    ) {
+        return ApiResponse::error("Transaction cannot be settled".to_string());
+    }
+
+    let now = get_current_time();
+    let mut txn = before.clone();
+    txn.status = TransactionStatus::Completed;
+    txn.settled_at = Some(now);
+    txn.updated_at = now;
+
+    TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow_mut()
+            .insert(txn.id.clone(), txn.clone());
+    });
+    stats::on_transaction(Some(&before), &txn);
+    inbox::on_settled(&txn);
+    audit::record(
+        caller,
+        "settle_transaction",
+        EntityType::Transaction,
+        &txn.id,
+        Some(&before),
+        Some(&txn),
+    );
+    treasury::on_settled(caller, &txn);
+    icrc3::append_transaction_block(icrc3::SETTLE_BLOCK_TYPE, &txn);
+
+    ApiResponse::success(txn)
+}
+
+/// Fee income booked between `from` and `to` (nanoseconds, defaulting to
+/// all time).
 #[ic_cdk::query]
-fn get_farmer_transactions() -> ApiResponse<Vec<Transaction>> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
+fn get_fee_revenue(from: Option<u64>, to: Option<u64>) -> ApiResponse<FeeRevenueReport> {
+    if let Err(error) = permissions::authorize(Permission::ViewRevenue) {
+        return ApiResponse::error(error);
     }
 
-    let caller = get_caller();
-    let transactions = TRANSACTIONS.with(|transactions| {
+    ApiResponse::success(fees::revenue(from.unwrap_or(0), to.unwrap_or(u64::MAX)))
+}
+
+// Transaction queries are composite so that transactions moved to the
+// archive canister are still returned
+#[ic_cdk::query(composite = true)]
+async fn get_farmer_transactions() -> ApiResponse<Vec<Transaction>> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+    let mut transactions = TRANSACTIONS.with(|transactions| {
         transactions
             .borrow()
             .iter()
@@ -444,17 +2402,23 @@ fn get_farmer_transactions() -> ApiResponse<Vec<Transaction>> {
             .collect::<Vec<_>>()
     });
 
+    match archive::fetch_transactions(caller).await {
+        Ok(archived) => {
+            transactions.extend(archived.into_iter().filter(|txn| txn.farmer == caller))
+        }
+        Err(error) => return ApiResponse::error(error),
+    }
+
     ApiResponse::success(transactions)
 }
 
-#[ic_cdk::query]
-fn get_investor_transactions() -> ApiResponse<Vec<Transaction>> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
-    }
-
-    let caller = get_caller();
-    let transactions = TRANSACTIONS.with(|transactions| {
+#[ic_cdk::query(composite = true)]
+async fn get_investor_transactions() -> ApiResponse<Vec<Transaction>> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+    let mut transactions = TRANSACTIONS.with(|transactions| {
         transactions
             .borrow()
             .iter()
@@ -463,29 +2427,90 @@ fn get_investor_transactions() -> ApiResponse<Vec<Transaction>> {
             .collect::<Vec<_>>()
     });
 
+    match archive::fetch_transactions(caller).await {
+        Ok(archived) => {
+            transactions.extend(archived.into_iter().filter(|txn| txn.investor == caller))
+        }
+        Err(error) => return ApiResponse::error(error),
+    }
+
     ApiResponse::success(transactions)
 }
 
-// Admin functions
+// Notification functions
 #[ic_cdk::query]
-fn get_all_users() -> ApiResponse<Vec<UserProfile>> {
-    if !is_authenticated() {
-        return ApiResponse::error("Authentication required".to_string());
+fn get_my_notifications(
+    before: Option<u64>,
+    limit: u64,
+    unread_only: bool,
+) -> ApiResponse<NotificationPage> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(inbox::page(&caller, before, limit, unread_only))
+}
+
+#[ic_cdk::query]
+fn get_unread_notification_count() -> ApiResponse<u64> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(inbox::unread_count(&caller))
+}
+
+/// Marks the given notifications as read; returns how many were unread.
+#[ic_cdk::update]
+fn mark_notifications_read(ids: Vec<u64>) -> ApiResponse<u64> {
+    let _call = metrics::track("mark_notifications_read");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
     }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
 
-    let caller = get_caller();
+    ApiResponse::success(inbox::mark_read(&caller, Some(ids)))
+}
 
-    // Check if caller is admin
-    let is_admin = USERS.with(|users| {
-        users
-            .borrow()
-            .get(&caller)
-            .map(|user| matches!(user.role, UserRole::Admin))
-            .unwrap_or(false)
-    });
+#[ic_cdk::update]
+fn mark_all_notifications_read() -> ApiResponse<u64> {
+    let _call = metrics::track("mark_all_notifications_read");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    ApiResponse::success(inbox::mark_read(&caller, None))
+}
+
+/// The caller's trades as positions, valued at the latest prices traded
+/// for the same product type and grade.
+#[ic_cdk::query(composite = true)]
+async fn get_my_portfolio() -> ApiResponse<Portfolio> {
+    let caller = match permissions::authenticate() {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
 
-    if !is_admin {
-        return ApiResponse::error("Admin access required".to_string());
+    match portfolio::build(caller).await {
+        Ok(portfolio) => ApiResponse::success(portfolio),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+// Admin functions
+#[ic_cdk::query]
+fn get_all_users() -> ApiResponse<Vec<UserProfile>> {
+    if let Err(error) = permissions::authorize(Permission::ViewUsers) {
+        return ApiResponse::error(error);
     }
 
     let users = USERS.with(|users| {
@@ -499,23 +2524,186 @@ fn get_all_users() -> ApiResponse<Vec<UserProfile>> {
     ApiResponse::success(users)
 }
 
+#[ic_cdk::query]
+fn get_audit_log(start: u64, limit: u64) -> ApiResponse<AuditLogPage> {
+    if let Err(error) = permissions::authorize(Permission::ViewAuditLog) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(audit::page(start, limit.min(MAX_AUDIT_PAGE_SIZE)))
+}
+
+#[ic_cdk::query]
+fn verify_audit_chain(start: u64, length: u64) -> ApiResponse<AuditChainReport> {
+    if let Err(error) = permissions::authorize(Permission::ViewAuditLog) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(audit::verify_chain(
+        start,
+        length.min(MAX_AUDIT_VERIFY_BATCH),
+    ))
+}
+
 #[ic_cdk::query]
 fn get_platform_stats() -> ApiResponse<PlatformStats> {
-    let stats = PlatformStats {
-        total_users: USERS.with(|users| users.borrow().len() as u64),
-        total_offers: OFFERS.with(|offers| offers.borrow().len() as u64),
-        total_requests: REQUESTS.with(|requests| requests.borrow().len() as u64),
-        total_transactions: TRANSACTIONS.with(|transactions| transactions.borrow().len() as u64),
-        active_offers: OFFERS.with(|offers| {
-            offers
-                .borrow()
-                .iter()
-                .filter(|(_, offer)| matches!(offer.status, OfferStatus::Active))
-                .count() as u64
-        }),
+    ApiResponse::success(compute_platform_stats())
+}
+
+#[ic_cdk::query]
+fn get_platform_counters() -> PlatformCounters {
+    stats::counters()
+}
+
+/// New users, offers and requests and accepted volume per day or week.
+#[ic_cdk::query]
+fn get_platform_stats_series(
+    from: u64,
+    to: u64,
+    bucket: TimeBucket,
+) -> ApiResponse<Vec<StatsBucket>> {
+    match stats::series(from, to, bucket) {
+        Ok(buckets) => ApiResponse::success(buckets),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_certified_platform_stats() -> ApiResponse<CertifiedResponse<PlatformStats>> {
+    let certificate = match ic_cdk::api::data_certificate() {
+        Some(certificate) => certificate,
+        None => return ApiResponse::error("Certificate only available in query calls".to_string()),
+    };
+
+    ApiResponse::success(CertifiedResponse {
+        data: compute_platform_stats(),
+        certificate,
+        witness: certification::encode_witness(&certification::stats_witness()),
+    })
+}
+
+// ICRC-3 transaction log
+#[ic_cdk::query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    icrc3::get_blocks(args)
+}
+
+#[ic_cdk::query]
+fn icrc3_get_archives(args: GetArchivesArgs) -> Vec<Icrc3ArchiveInfo> {
+    icrc3::get_archives(args.from)
+}
+
+#[ic_cdk::query]
+fn icrc3_get_tip_certificate() -> Option<Icrc3DataCertificate> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let witness = certification::tip_witness()?;
+
+    Some(Icrc3DataCertificate {
+        certificate,
+        hash_tree: certification::encode_witness(&witness),
+    })
+}
+
+#[ic_cdk::query]
+fn icrc3_supported_block_types() -> Vec<SupportedBlockType> {
+    icrc3::supported_block_types()
+}
+
+#[ic_cdk::query]
+fn icrc10_supported_standards() -> Vec<SupportedStandard> {
+    vec![
+        SupportedStandard {
+            name: "ICRC-3".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10".to_string(),
+        },
+    ]
+}
+
+#[ic_cdk::update]
+fn configure_icrc3_archive(request: ConfigureArchiveRequest) -> ApiResponse<Icrc3State> {
+    let _call = metrics::track("configure_icrc3_archive");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageArchive) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
+    };
+
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match icrc3::configure_archive(caller, request) {
+        Ok(state) => ApiResponse::success(state),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_icrc3_archive_state() -> ApiResponse<Icrc3State> {
+    if let Err(error) = permissions::authorize(Permission::ManageArchive) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(icrc3::status())
+}
+
+// Record archive functions
+#[ic_cdk::update]
+fn configure_record_archive(
+    request: ConfigureRecordArchiveRequest,
+) -> ApiResponse<RecordArchiveState> {
+    let _call = metrics::track("configure_record_archive");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    let caller = match permissions::authorize(Permission::ManageArchive) {
+        Ok(caller) => caller,
+        Err(error) => return ApiResponse::error(error),
     };
 
-    ApiResponse::success(stats)
+    if let Err(error) = multisig::require_single_signer() {
+        return ApiResponse::error(error);
+    }
+
+    match archive::configure(caller, request) {
+        Ok(state) => ApiResponse::success(state),
+        Err(error) => ApiResponse::error(error),
+    }
+}
+
+#[ic_cdk::query]
+fn get_record_archive_state() -> ApiResponse<RecordArchiveState> {
+    if let Err(error) = permissions::authorize(Permission::ManageArchive) {
+        return ApiResponse::error(error);
+    }
+
+    ApiResponse::success(archive::status())
+}
+
+/// Runs one archiving batch immediately instead of waiting for the timer.
+#[ic_cdk::update]
+async fn archive_old_records() -> ApiResponse<ArchiveRunSummary> {
+    let _call = metrics::track("archive_old_records");
+    if let Err(paused) = pause::check_platform() {
+        return ApiResponse::paused(paused);
+    }
+    if let Err(error) = permissions::authorize(Permission::ManageArchive) {
+        return ApiResponse::error(error);
+    }
+
+    let result = archive::archive_old_records().await;
+    refresh_certified_stats();
+
+    match result {
+        Ok(summary) => ApiResponse::success(summary),
+        Err(error) => ApiResponse::error(error),
+    }
 }
 
 // Health check
@@ -525,4 +2713,4 @@ fn health_check() -> String {
 }
 
 // Export Candid interface
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
@@ -0,0 +1,174 @@
+use candid::Principal;
+use sha2::{Digest, Sha256};
+
+use crate::types::{EntityType, LinkChallenge, LinkChallengeResponse, LinkedPrincipal};
+use crate::{audit, validation};
+use crate::{LINKED_PRINCIPALS, LINK_CHALLENGES, USERS};
+
+const CHALLENGE_TTL_NS: u64 = 10 * 60 * 1_000_000_000;
+const MAX_LINKED_PRINCIPALS: usize = 10;
+const MAX_LABEL_LEN: usize = 64;
+
+/// The account `principal` acts for: itself, unless it has been linked to
+/// another account.
+pub fn resolve(principal: Principal) -> Principal {
+    LINKED_PRINCIPALS
+        .with(|links| links.borrow().get(&principal))
+        .map(|link| link.account)
+        .unwrap_or(principal)
+}
+
+pub fn linked_principals(account: &Principal) -> Vec<LinkedPrincipal> {
+    LINKED_PRINCIPALS.with(|links| {
+        links
+            .borrow()
+            .iter()
+            .filter(|(_, link)| link.account == *account)
+            .map(|(_, link)| link)
+            .collect()
+    })
+}
+
+// Challenges are stored under the hex SHA-256 of the token, never the token
+fn challenge_key(challenge: &str) -> String {
+    hex(&Sha256::digest(challenge.trim().as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Issues a one-time challenge that lets another principal join `account`.
+pub async fn create_challenge(
+    account: Principal,
+    label: String,
+) -> Result<LinkChallengeResponse, String> {
+    let label = validation::validate_text("Label", &label, MAX_LABEL_LEN)?;
+
+    if linked_principals(&account).len() >= MAX_LINKED_PRINCIPALS {
+        return Err(format!(
+            "At most {} principals can be linked to an account",
+            MAX_LINKED_PRINCIPALS
+        ));
+    }
+
+    let (random,): (Vec<u8>,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(code, message)| format!("Failed to get randomness: {:?} {}", code, message))?;
+
+    let now = ic_cdk::api::time();
+    let challenge = hex(&random);
+    let pending = LinkChallenge {
+        account,
+        label,
+        created_at: now,
+        expires_at: now + CHALLENGE_TTL_NS,
+    };
+
+    LINK_CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        // Drop this account's earlier and everyone's expired challenges
+        let stale = challenges
+            .iter()
+            .filter(|(_, old)| old.account == account || old.expires_at <= now)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in stale {
+            challenges.remove(&key);
+        }
+        challenges.insert(challenge_key(&challenge), pending);
+    });
+
+    Ok(LinkChallengeResponse {
+        challenge,
+        expires_at: now + CHALLENGE_TTL_NS,
+    })
+}
+
+/// Links `principal`, the caller presenting `challenge`, to the account that
+/// issued it. The challenge can be used once.
+pub fn link(principal: Principal, challenge: &str) -> Result<LinkedPrincipal, String> {
+    let key = challenge_key(challenge);
+    let pending = LINK_CHALLENGES
+        .with(|challenges| challenges.borrow_mut().remove(&key))
+        .ok_or("Unknown or already used challenge")?;
+
+    if ic_cdk::api::time() >= pending.expires_at {
+        return Err("Challenge has expired".to_string());
+    }
+    if principal == pending.account {
+        return Err("Cannot link an account to itself".to_string());
+    }
+    if LINKED_PRINCIPALS.with(|links| links.borrow().contains_key(&principal)) {
+        return Err("Principal is already linked to an account".to_string());
+    }
+    if USERS.with(|users| users.borrow().contains_key(&principal)) {
+        return Err("Principal is registered as a separate account".to_string());
+    }
+    if USERS.with(|users| !users.borrow().contains_key(&pending.account)) {
+        return Err("Account no longer exists".to_string());
+    }
+
+    let link = LinkedPrincipal {
+        principal,
+        account: pending.account,
+        label: pending.label,
+        linked_at: ic_cdk::api::time(),
+    };
+    LINKED_PRINCIPALS.with(|links| {
+        links.borrow_mut().insert(principal, link.clone());
+    });
+    audit::record(
+        principal,
+        "link_principal",
+        EntityType::User,
+        &link.account.to_text(),
+        None,
+        Some(&link),
+    );
+
+    Ok(link)
+}
+
+/// Removes `principal` from `account`. `actor` is the principal making the
+/// call, which may be the linked principal itself.
+pub fn revoke(
+    actor: Principal,
+    account: Principal,
+    principal: Principal,
+) -> Result<LinkedPrincipal, String> {
+    let link = LINKED_PRINCIPALS
+        .with(|links| links.borrow().get(&principal))
+        .filter(|link| link.account == account)
+        .ok_or("Principal is not linked to this account")?;
+
+    LINKED_PRINCIPALS.with(|links| links.borrow_mut().remove(&principal));
+    audit::record(
+        actor,
+        "revoke_linked_principal",
+        EntityType::User,
+        &account.to_text(),
+        Some(&link),
+        None,
+    );
+
+    Ok(link)
+}
+
+/// Unlinks every principal of `account` and drops its open challenges.
+pub fn revoke_all(actor: Principal, account: Principal) {
+    for link in linked_principals(&account) {
+        let _ = revoke(actor, account, link.principal);
+    }
+    LINK_CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        let keys = challenges
+            .iter()
+            .filter(|(_, pending)| pending.account == account)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in keys {
+            challenges.remove(&key);
+        }
+    });
+}
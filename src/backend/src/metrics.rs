@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{Display, Write};
+
+use crate::types::{HttpGatewayResponse, HttpRequest};
+use crate::{stats, MEMORY_MANAGER, MEMORY_REGIONS, OFFERS, REQUESTS, TRANSACTIONS, USERS};
+use ic_stable_structures::Memory as _;
+
+const WASM_PAGE_BYTES: u64 = 64 * 1024;
+/// Update calls whose instruction counts are kept.
+const MAX_RECENT_CALLS: usize = 100;
+
+struct CallSample {
+    method: &'static str,
+    instructions: u64,
+}
+
+#[derive(Default)]
+struct CallCounts {
+    calls: u64,
+    errors: u64,
+}
+
+struct ActiveCall {
+    method: &'static str,
+    failed: bool,
+}
+
+thread_local! {
+    // Heap only: counters restart from zero after an upgrade, which
+    // Prometheus treats as a counter reset.
+    static CALL_COUNTS: RefCell<BTreeMap<&'static str, CallCounts>> =
+        const { RefCell::new(BTreeMap::new()) };
+    static RECENT_CALLS: RefCell<VecDeque<CallSample>> = const { RefCell::new(VecDeque::new()) };
+    static ACTIVE_CALL: RefCell<Option<ActiveCall>> = const { RefCell::new(None) };
+}
+
+/// Measures an update call until dropped. Create one at the top of every
+/// update endpoint.
+pub struct CallTracker {
+    method: &'static str,
+}
+
+pub fn track(method: &'static str) -> CallTracker {
+    ACTIVE_CALL.with(|active| {
+        *active.borrow_mut() = Some(ActiveCall {
+            method,
+            failed: false,
+        })
+    });
+    CallTracker { method }
+}
+
+/// Marks the call being tracked as failed; called when an error response
+/// is built. Outside of a tracked call this does nothing.
+pub fn note_failure() {
+    ACTIVE_CALL.with(|active| {
+        if let Some(call) = active.borrow_mut().as_mut() {
+            call.failed = true;
+        }
+    });
+}
+
+impl Drop for CallTracker {
+    fn drop(&mut self) {
+        // Calls interleave at await points, so only trust the flag if it
+        // still belongs to this call
+        let failed = ACTIVE_CALL.with(|active| match active.borrow_mut().take() {
+            Some(call) if call.method == self.method => call.failed,
+            _ => false,
+        });
+        // Instructions of the current message execution, i.e. since the
+        // last await in async endpoints
+        let instructions = ic_cdk::api::performance_counter(0);
+
+        CALL_COUNTS.with(|counts| {
+            let mut counts = counts.borrow_mut();
+            let counts = counts.entry(self.method).or_default();
+            counts.calls += 1;
+            if failed {
+                counts.errors += 1;
+            }
+        });
+        RECENT_CALLS.with(|recent| {
+            let mut recent = recent.borrow_mut();
+            if recent.len() == MAX_RECENT_CALLS {
+                recent.pop_front();
+            }
+            recent.push_back(CallSample {
+                method: self.method,
+                instructions,
+            });
+        });
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn heap_bytes() -> u64 {
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_BYTES
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_bytes() -> u64 {
+    0
+}
+
+// Writes metrics in the Prometheus text exposition format
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn metric(&mut self, name: &str, kind: &str, help: &str) {
+        writeln!(self.text, "# HELP harvestx_{} {}", name, help).unwrap();
+        writeln!(self.text, "# TYPE harvestx_{} {}", name, kind).unwrap();
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        write!(self.text, "harvestx_{}", name).unwrap();
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, value))
+                .collect::<Vec<_>>();
+            write!(self.text, "{{{}}}", labels.join(",")).unwrap();
+        }
+        writeln!(self.text, " {}", value).unwrap();
+    }
+
+    fn counts<T: std::fmt::Debug>(&mut self, name: &str, label: &str, counts: &[(T, u64)]) {
+        for (key, count) in counts {
+            let value = format!("{:?}", key);
+            self.sample(name, &[(label, value.as_str())], count);
+        }
+    }
+}
+
+pub fn render() -> String {
+    let mut out = Exposition {
+        text: String::new(),
+    };
+
+    out.metric("cycles_balance", "gauge", "Cycles held by the canister.");
+    out.sample("cycles_balance", &[], ic_cdk::api::canister_balance128());
+
+    out.metric("heap_memory_bytes", "gauge", "Size of the Wasm heap.");
+    out.sample("heap_memory_bytes", &[], heap_bytes());
+
+    out.metric(
+        "stable_memory_bytes",
+        "gauge",
+        "Stable memory in use, in total and per memory region.",
+    );
+    out.sample(
+        "stable_memory_bytes",
+        &[],
+        ic_cdk::api::stable::stable64_size() * WASM_PAGE_BYTES,
+    );
+    MEMORY_MANAGER.with(|manager| {
+        let manager = manager.borrow();
+        for (name, id) in MEMORY_REGIONS {
+            out.sample(
+                "stable_memory_bytes",
+                &[("memory", *name)],
+                manager.get(*id).size() * WASM_PAGE_BYTES,
+            );
+        }
+    });
+
+    let counters = stats::counters();
+    out.metric("users", "gauge", "Registered users by role.");
+    out.counts("users", "role", &counters.users_by_role);
+    out.metric("offers", "gauge", "Offers by status.");
+    out.counts("offers", "status", &counters.offers_by_status);
+    out.metric("requests", "gauge", "Investment requests by status.");
+    out.counts("requests", "status", &counters.requests_by_status);
+    out.metric("transactions", "gauge", "Transactions by status.");
+    out.counts("transactions", "status", &counters.transactions_by_status);
+
+    out.metric(
+        "stored_records",
+        "gauge",
+        "Records held by the backend, excluding archived ones.",
+    );
+    out.sample(
+        "stored_records",
+        &[("kind", "users")],
+        USERS.with(|users| users.borrow().len()),
+    );
+    out.sample(
+        "stored_records",
+        &[("kind", "offers")],
+        OFFERS.with(|offers| offers.borrow().len()),
+    );
+    out.sample(
+        "stored_records",
+        &[("kind", "requests")],
+        REQUESTS.with(|requests| requests.borrow().len()),
+    );
+    out.sample(
+        "stored_records",
+        &[("kind", "transactions")],
+        TRANSACTIONS.with(|transactions| transactions.borrow().len()),
+    );
+
+    CALL_COUNTS.with(|counts| {
+        let counts = counts.borrow();
+        out.metric(
+            "update_calls_total",
+            "counter",
+            "Update calls by endpoint since the last upgrade.",
+        );
+        for (method, counts) in counts.iter() {
+            out.sample("update_calls_total", &[("method", *method)], counts.calls);
+        }
+        out.metric(
+            "update_errors_total",
+            "counter",
+            "Update calls that returned an error, by endpoint, since the last upgrade.",
+        );
+        for (method, counts) in counts.iter() {
+            out.sample("update_errors_total", &[("method", *method)], counts.errors);
+        }
+    });
+
+    RECENT_CALLS.with(|recent| {
+        let recent = recent.borrow();
+        let mut latest: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut max: BTreeMap<&str, u64> = BTreeMap::new();
+        for sample in recent.iter() {
+            latest.insert(sample.method, sample.instructions);
+            let entry = max.entry(sample.method).or_default();
+            *entry = (*entry).max(sample.instructions);
+        }
+
+        out.metric(
+            "update_instructions_last",
+            "gauge",
+            "Instructions used by the latest update call of each endpoint.",
+        );
+        for (method, instructions) in &latest {
+            out.sample(
+                "update_instructions_last",
+                &[("method", *method)],
+                instructions,
+            );
+        }
+        out.metric(
+            "update_instructions_max",
+            "gauge",
+            "Most instructions used by an update call of each endpoint among the last 100 calls.",
+        );
+        for (method, instructions) in &max {
+            out.sample(
+                "update_instructions_max",
+                &[("method", *method)],
+                instructions,
+            );
+        }
+    });
+
+    out.text
+}
+
+/// Serves `/metrics`; every other path is not found.
+pub fn http_request(request: HttpRequest) -> HttpGatewayResponse {
+    let path = request.url.split('?').next().unwrap_or_default();
+    if request.method != "GET" || path != "/metrics" {
+        return HttpGatewayResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"Not found".to_vec(),
+        };
+    }
+
+    HttpGatewayResponse {
+        status_code: 200,
+        headers: vec![(
+            "Content-Type".to_string(),
+            "text/plain; version=0.0.4".to_string(),
+        )],
+        body: render().into_bytes(),
+    }
+}
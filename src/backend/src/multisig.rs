@@ -0,0 +1,421 @@
+use candid::Principal;
+
+use crate::types::{
+    AdminAction, AdminProposal, EntityType, MultisigPolicy, Permission, ProposalStatus,
+};
+use crate::{
+    archive, audit, cycles, icrc3, kyc, notifier, permissions, rate_limit, settings, treasury,
+};
+use crate::{MULTISIG_POLICY, PROPOSALS, USERS};
+
+pub fn get_policy() -> MultisigPolicy {
+    MULTISIG_POLICY.with(|policy| policy.borrow().get().clone())
+}
+
+fn set_policy(policy: MultisigPolicy) {
+    MULTISIG_POLICY.with(|cell| {
+        cell.borrow_mut()
+            .set(policy)
+            .expect("failed to write multisig policy");
+    });
+}
+
+/// Fails when sensitive actions must go through a proposal, i.e. whenever
+/// more than one approval is required.
+pub fn require_single_signer() -> Result<(), String> {
+    if get_policy().threshold > 1 {
+        return Err("This action requires an approved proposal (propose_admin_action)".to_string());
+    }
+    Ok(())
+}
+
+/// The permission a proposer needs for the action itself.
+fn required_permission(action: &AdminAction) -> Permission {
+    match action {
+        AdminAction::UpdateUserRole { .. }
+        | AdminAction::DefineRole(_)
+        | AdminAction::DeleteRole { .. }
+        | AdminAction::GrantRole { .. }
+        | AdminAction::RevokeRole { .. } => Permission::ManageRoles,
+        AdminAction::ConfigureIcrc3Archive(_) | AdminAction::ConfigureRecordArchive(_) => {
+            Permission::ManageArchive
+        }
+        AdminAction::ConfigureKycPolicy(_) => Permission::ManageKycPolicy,
+        AdminAction::ConfigureNotifier(_) => Permission::ManageNotifier,
+        AdminAction::ConfigureRateLimits(_) | AdminAction::SetRateLimitOverride { .. } => {
+            Permission::ManageRateLimits
+        }
+        AdminAction::UpdateSettings(_) => Permission::ManageSettings,
+        AdminAction::ConfigureTreasury(_) => Permission::ManageTreasury,
+        AdminAction::ConfigureCyclesMonitor(_) => Permission::ManageCycles,
+        AdminAction::CancelOffer { .. }
+        | AdminAction::CancelRequest { .. }
+        | AdminAction::RefundTransaction { .. } => Permission::ManageTrades,
+        AdminAction::SetMultisigPolicy(_) => Permission::ApproveAdminActions,
+    }
+}
+
+// Accounts whose approvals count (N)
+fn approver_count() -> usize {
+    let principals = USERS.with(|users| {
+        users
+            .borrow()
+            .iter()
+            .map(|(principal, _)| principal)
+            .collect::<Vec<_>>()
+    });
+    principals
+        .iter()
+        .filter(|principal| permissions::is_approver(principal))
+        .count()
+}
+
+fn counted_approvals(proposal: &AdminProposal) -> usize {
+    proposal
+        .approvals
+        .iter()
+        .filter(|principal| permissions::is_approver(principal))
+        .count()
+}
+
+fn validate_policy(policy: &MultisigPolicy) -> Result<(), String> {
+    if policy.threshold == 0 {
+        return Err("Threshold must be at least 1".to_string());
+    }
+    let approvers = approver_count();
+    if policy.threshold as usize > approvers {
+        return Err(format!(
+            "Threshold {} exceeds the {} principals able to approve",
+            policy.threshold, approvers
+        ));
+    }
+    if policy.proposal_ttl_ns == 0 {
+        return Err("Proposal lifetime must be positive".to_string());
+    }
+    Ok(())
+}
+
+fn configure_policy(actor: Principal, policy: MultisigPolicy) -> Result<(), String> {
+    validate_policy(&policy)?;
+
+    let before = get_policy();
+    set_policy(policy.clone());
+    audit::record(
+        actor,
+        "set_multisig_policy",
+        EntityType::Config,
+        "multisig_policy",
+        Some(&before),
+        Some(&policy),
+    );
+    Ok(())
+}
+
+pub fn get(id: u64) -> Option<AdminProposal> {
+    PROPOSALS
+        .with(|proposals| proposals.borrow().get(&id))
+        .map(redacted)
+}
+
+pub fn list(only_open: bool) -> Vec<AdminProposal> {
+    PROPOSALS.with(|proposals| {
+        proposals
+            .borrow()
+            .iter()
+            .filter(|(_, proposal)| !only_open || proposal.status == ProposalStatus::Open)
+            .map(|(_, proposal)| redacted(proposal))
+            .collect()
+    })
+}
+
+// Proposals are readable by every approver; keep relay credentials out of them
+fn redacted(proposal: AdminProposal) -> AdminProposal {
+    match proposal.action {
+        AdminAction::ConfigureNotifier(config) => AdminProposal {
+            action: AdminAction::ConfigureNotifier(notifier::redacted(config)),
+            ..proposal
+        },
+        _ => proposal,
+    }
+}
+
+/// Records a new proposal with the proposer's approval, executing it right
+/// away if that already meets the threshold.
+pub fn propose(proposer: Principal, action: AdminAction) -> Result<AdminProposal, String> {
+    permissions::check(&proposer, required_permission(&action))?;
+    if let AdminAction::SetMultisigPolicy(policy) = &action {
+        validate_policy(policy)?;
+    }
+
+    let policy = get_policy();
+    let now = ic_cdk::api::time();
+    let id = PROPOSALS.with(|proposals| {
+        proposals
+            .borrow()
+            .last_key_value()
+            .map_or(0, |(id, _)| id + 1)
+    });
+
+    let proposal = AdminProposal {
+        id,
+        action,
+        proposer,
+        approvals: vec![proposer],
+        threshold: policy.threshold,
+        status: ProposalStatus::Open,
+        created_at: now,
+        expires_at: now + policy.proposal_ttl_ns,
+        executed_at: None,
+    };
+    store(proposer, "propose_admin_action", None, &proposal);
+
+    Ok(redacted(maybe_execute(proposer, proposal)))
+}
+
+pub fn approve(approver: Principal, id: u64) -> Result<AdminProposal, String> {
+    let before = PROPOSALS
+        .with(|proposals| proposals.borrow().get(&id))
+        .ok_or("Proposal not found")?;
+
+    if before.status != ProposalStatus::Open {
+        return Err("Proposal is no longer open".to_string());
+    }
+    if ic_cdk::api::time() >= before.expires_at {
+        expire(before);
+        return Err("Proposal has expired".to_string());
+    }
+    if before.approvals.contains(&approver) {
+        return Err("Proposal already approved by caller".to_string());
+    }
+    if !permissions::is_approver(&approver) {
+        return Err("Only admins granted the role can approve proposals".to_string());
+    }
+
+    let mut proposal = before.clone();
+    proposal.approvals.push(approver);
+    store(approver, "approve_admin_action", Some(&before), &proposal);
+
+    Ok(redacted(maybe_execute(approver, proposal)))
+}
+
+/// Withdraws an open proposal; only its proposer can do this.
+pub fn withdraw(actor: Principal, id: u64) -> Result<AdminProposal, String> {
+    let before = PROPOSALS
+        .with(|proposals| proposals.borrow().get(&id))
+        .ok_or("Proposal not found")?;
+
+    if before.proposer != actor {
+        return Err("Only the proposer can withdraw a proposal".to_string());
+    }
+    if before.status != ProposalStatus::Open {
+        return Err("Proposal is no longer open".to_string());
+    }
+
+    let proposal = AdminProposal {
+        status: ProposalStatus::Withdrawn,
+        ..before.clone()
+    };
+    store(actor, "withdraw_admin_proposal", Some(&before), &proposal);
+
+    Ok(redacted(proposal))
+}
+
+/// Marks open proposals past their deadline as expired.
+pub fn expire_proposals() {
+    let now = ic_cdk::api::time();
+    let expired = PROPOSALS.with(|proposals| {
+        proposals
+            .borrow()
+            .iter()
+            .filter(|(_, proposal)| proposal.status == ProposalStatus::Open)
+            .filter(|(_, proposal)| now >= proposal.expires_at)
+            .map(|(_, proposal)| proposal)
+            .collect::<Vec<_>>()
+    });
+
+    for proposal in expired {
+        expire(proposal);
+    }
+}
+
+fn expire(before: AdminProposal) {
+    let proposal = AdminProposal {
+        status: ProposalStatus::Expired,
+        ..before.clone()
+    };
+    store(
+        ic_cdk::id(),
+        "expire_admin_proposal",
+        Some(&before),
+        &proposal,
+    );
+}
+
+fn maybe_execute(actor: Principal, proposal: AdminProposal) -> AdminProposal {
+    // Approvals of accounts that no longer count are kept but ignored
+    if (counted_approvals(&proposal) as u32) < proposal.threshold {
+        return proposal;
+    }
+
+    let status = match execute(actor, &proposal.action) {
+        Ok(()) => ProposalStatus::Executed,
+        Err(error) => ProposalStatus::Failed(error),
+    };
+    let executed = AdminProposal {
+        status,
+        executed_at: Some(ic_cdk::api::time()),
+        ..proposal.clone()
+    };
+    store(actor, "execute_admin_action", Some(&proposal), &executed);
+
+    executed
+}
+
+fn execute(actor: Principal, action: &AdminAction) -> Result<(), String> {
+    match action.clone() {
+        AdminAction::UpdateUserRole { principal, role } => {
+            permissions::set_user_role(actor, principal, role).map(drop)
+        }
+        AdminAction::DefineRole(request) => permissions::define_role(actor, request).map(drop),
+        AdminAction::DeleteRole { name } => permissions::delete_role(actor, &name).map(drop),
+        AdminAction::GrantRole { principal, name } => {
+            permissions::grant_role(actor, principal, &name).map(drop)
+        }
+        AdminAction::RevokeRole { principal, name } => {
+            permissions::revoke_role(actor, principal, &name).map(drop)
+        }
+        AdminAction::ConfigureIcrc3Archive(request) => {
+            icrc3::configure_archive(actor, request).map(drop)
+        }
+        AdminAction::ConfigureRecordArchive(request) => {
+            archive::configure(actor, request).map(drop)
+        }
+        AdminAction::ConfigureKycPolicy(policy) => kyc::configure_policy(actor, policy).map(drop),
+        AdminAction::ConfigureNotifier(config) => notifier::configure(actor, config).map(drop),
+        AdminAction::ConfigureRateLimits(config) => rate_limit::configure(actor, config).map(drop),
+        AdminAction::UpdateSettings(new_settings) => {
+            settings::update(actor, new_settings).map(drop)
+        }
+        AdminAction::ConfigureTreasury(config) => treasury::configure(actor, config).map(drop),
+        AdminAction::ConfigureCyclesMonitor(config) => cycles::configure(actor, config).map(drop),
+        AdminAction::SetRateLimitOverride {
+            principal,
+            rate_limit_override,
+        } => rate_limit::set_override(actor, principal, rate_limit_override),
+        AdminAction::CancelOffer { offer_id, .. } => crate::force_cancel_offer(actor, &offer_id),
+        AdminAction::CancelRequest { request_id, .. } => {
+            crate::force_cancel_request(actor, &request_id)
+        }
+        AdminAction::RefundTransaction { transaction_id, .. } => {
+            crate::refund_transaction(actor, &transaction_id)
+        }
+        AdminAction::SetMultisigPolicy(policy) => configure_policy(actor, policy),
+    }
+}
+
+fn store(actor: Principal, action: &str, before: Option<&AdminProposal>, proposal: &AdminProposal) {
+    PROPOSALS.with(|proposals| {
+        proposals.borrow_mut().insert(proposal.id, proposal.clone());
+    });
+    audit::record(
+        actor,
+        action,
+        EntityType::Proposal,
+        &proposal.id.to_string(),
+        before,
+        Some(proposal),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountStatus, KycStatus, RoleGrants, UserProfile, UserRole};
+    use crate::ROLE_GRANTS;
+
+    // An active admin, granted the role when `granted` is set
+    fn admin(id: u8, granted: bool) -> Principal {
+        let principal = Principal::from_slice(&[id]);
+        USERS.with(|users| {
+            users.borrow_mut().insert(
+                principal,
+                UserProfile {
+                    principal,
+                    role: UserRole::Admin,
+                    display_name: format!("Admin {}", id),
+                    email: format!("admin{}@example.com", id),
+                    email_verified: Some(true),
+                    kyc_status: Some(KycStatus::Unverified),
+                    status: Some(AccountStatus::Active),
+                    created_at: 0,
+                    updated_at: 0,
+                },
+            )
+        });
+        if granted {
+            ROLE_GRANTS.with(|grants| {
+                grants.borrow_mut().insert(
+                    principal,
+                    RoleGrants {
+                        roles: vec![],
+                        admin_granted_by: Some(principal),
+                    },
+                )
+            });
+        }
+        principal
+    }
+
+    fn policy(threshold: u32) -> MultisigPolicy {
+        MultisigPolicy {
+            threshold,
+            ..MultisigPolicy::default()
+        }
+    }
+
+    #[test]
+    fn threshold_is_bounded_by_the_approvers() {
+        admin(1, true);
+        admin(2, true);
+        admin(3, false);
+
+        assert!(validate_policy(&policy(0)).is_err());
+        assert!(validate_policy(&policy(2)).is_ok());
+        assert!(validate_policy(&policy(3)).is_err());
+        assert!(validate_policy(&MultisigPolicy {
+            proposal_ttl_ns: 0,
+            ..policy(1)
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn only_current_approvers_are_counted() {
+        let proposal = AdminProposal {
+            id: 0,
+            action: AdminAction::CancelOffer {
+                offer_id: "offer_1".to_string(),
+                reason: "Duplicate".to_string(),
+            },
+            proposer: admin(1, true),
+            approvals: vec![admin(1, true), admin(2, false), admin(3, true)],
+            threshold: 2,
+            status: ProposalStatus::Open,
+            created_at: 0,
+            expires_at: 1,
+            executed_at: None,
+        };
+        assert_eq!(counted_approvals(&proposal), 2);
+
+        // Approvals from an admin who has since lost the grant no longer count
+        ROLE_GRANTS.with(|grants| grants.borrow_mut().remove(&Principal::from_slice(&[3])));
+        assert_eq!(counted_approvals(&proposal), 1);
+    }
+
+    #[test]
+    fn direct_calls_need_a_single_signer_policy() {
+        assert!(require_single_signer().is_ok());
+        set_policy(policy(2));
+        assert!(require_single_signer().is_err());
+    }
+}
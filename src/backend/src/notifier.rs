@@ -0,0 +1,168 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+
+use candid::Principal;
+
+use crate::types::{EntityType, NotifierConfig, NotifierMode};
+use crate::NOTIFIER_CONFIG;
+use crate::{audit, validation};
+
+// Upper bound for a small POST on a 13-node subnet; unused cycles are refunded.
+const HTTP_OUTCALL_CYCLES: u128 = 50_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 2_048;
+// In bytes, so that the stored config stays within its bound
+const MAX_RELAY_URL_LEN: usize = 256;
+const MAX_AUTH_HEADER_LEN: usize = 256;
+
+/// Name of the query that strips relay responses down to what all replicas
+/// agree on.
+pub const TRANSFORM_METHOD: &str = "transform_relay_response";
+
+pub fn get_config() -> NotifierConfig {
+    NOTIFIER_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+pub fn set_config(config: NotifierConfig) {
+    NOTIFIER_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .expect("failed to write notifier config");
+    });
+}
+
+/// Validates and stores a new config.
+pub fn configure(actor: Principal, config: NotifierConfig) -> Result<NotifierConfig, String> {
+    if let NotifierMode::HttpsRelay { url, auth_header } = &config.mode {
+        if !url.starts_with("https://") {
+            return Err("Mail relay URL must use HTTPS".to_string());
+        }
+        if url.len() > MAX_RELAY_URL_LEN {
+            return Err(format!(
+                "Mail relay URL must be at most {} bytes",
+                MAX_RELAY_URL_LEN
+            ));
+        }
+        if auth_header
+            .as_ref()
+            .is_some_and(|header| header.len() > MAX_AUTH_HEADER_LEN)
+        {
+            return Err(format!(
+                "Authorization header must be at most {} bytes",
+                MAX_AUTH_HEADER_LEN
+            ));
+        }
+    }
+    let sender = validation::validate_email(&config.sender)
+        .map_err(|error| format!("Invalid sender: {}", error))?;
+    let config = NotifierConfig { sender, ..config };
+
+    let before = get_config();
+    set_config(config.clone());
+    audit::record(
+        actor,
+        "configure_notifier",
+        EntityType::Config,
+        "notifier",
+        Some(&before),
+        Some(&config),
+    );
+
+    Ok(config)
+}
+
+/// The config with the relay credentials masked, for returning to callers.
+pub fn redacted(config: NotifierConfig) -> NotifierConfig {
+    let mode = match config.mode {
+        NotifierMode::HttpsRelay { url, auth_header } => NotifierMode::HttpsRelay {
+            url,
+            auth_header: auth_header.map(|_| "<redacted>".to_string()),
+        },
+        mode => mode,
+    };
+    NotifierConfig { mode, ..config }
+}
+
+/// Sends an email through the configured notifier.
+///
+/// `idempotency_key` is passed to the relay: every replica in the subnet
+/// makes the outcall, so the relay must deliver each key only once.
+pub async fn send_email(
+    to: &str,
+    subject: &str,
+    body: &str,
+    idempotency_key: &str,
+) -> Result<(), String> {
+    let config = get_config();
+
+    match config.mode {
+        // The body is left out: it can carry a verification code, and the
+        // canister log is readable by more people than the recipient
+        NotifierMode::Log => {
+            ic_cdk::println!("Email from {} to {}: {}", config.sender, to, subject);
+            Ok(())
+        }
+        NotifierMode::HttpsRelay { url, auth_header } => {
+            let payload = serde_json::json!({
+                "from": config.sender,
+                "to": to,
+                "subject": subject,
+                "text": body,
+            });
+
+            let mut headers = vec![
+                HttpHeader {
+                    name: "Content-Type".to_string(),
+                    value: "application/json".to_string(),
+                },
+                HttpHeader {
+                    name: "Idempotency-Key".to_string(),
+                    value: idempotency_key.to_string(),
+                },
+            ];
+            if let Some(auth_header) = auth_header {
+                headers.push(HttpHeader {
+                    name: "Authorization".to_string(),
+                    value: auth_header,
+                });
+            }
+
+            let request = CanisterHttpRequestArgument {
+                url,
+                max_response_bytes: Some(MAX_RESPONSE_BYTES),
+                method: HttpMethod::POST,
+                headers,
+                body: Some(payload.to_string().into_bytes()),
+                transform: Some(TransformContext::from_name(
+                    TRANSFORM_METHOD.to_string(),
+                    Vec::new(),
+                )),
+            };
+
+            match http_request(request, HTTP_OUTCALL_CYCLES).await {
+                Ok((response,)) if is_success(&response) => Ok(()),
+                Ok((response,)) => Err(format!("Mail relay responded with {}", response.status)),
+                Err((code, message)) => Err(format!(
+                    "Failed to reach mail relay: {:?} {}",
+                    code, message
+                )),
+            }
+        }
+    }
+}
+
+fn is_success(response: &HttpResponse) -> bool {
+    let status = u64::try_from(&response.status.0).unwrap_or(0);
+    (200..300).contains(&status)
+}
+
+/// Keeps only the status so that responses from different replicas match;
+/// headers and bodies carry request ids and timestamps.
+pub fn transform(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
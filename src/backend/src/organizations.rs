@@ -0,0 +1,295 @@
+use candid::Principal;
+
+use crate::types::{
+    CreateOrganizationRequest, EntityType, InvestmentOffer, OrgMember, OrgRole, Organization,
+    OrganizationRevenue, Transaction,
+};
+use crate::{audit, fees, validation};
+use crate::{ORGANIZATIONS, ORG_MEMBERS, TRANSACTIONS, USERS};
+
+const MAX_NAME_LEN: usize = 128;
+const MAX_DESCRIPTION_LEN: usize = 1024;
+
+// Members are keyed by `<organization_id>|<principal>` so that an
+// organization's members form one contiguous range.
+fn member_key(organization_id: &str, principal: &Principal) -> String {
+    format!("{}{}", member_prefix(organization_id), principal.to_text())
+}
+
+fn member_prefix(organization_id: &str) -> String {
+    format!("{}|", organization_id)
+}
+
+pub fn get(organization_id: &str) -> Option<Organization> {
+    ORGANIZATIONS.with(|organizations| organizations.borrow().get(&organization_id.to_string()))
+}
+
+pub fn role(organization_id: &str, principal: &Principal) -> Option<OrgRole> {
+    ORG_MEMBERS
+        .with(|members| {
+            members
+                .borrow()
+                .get(&member_key(organization_id, principal))
+        })
+        .map(|member| member.role)
+}
+
+/// Whether `principal` holds `role` or a higher one in the organization.
+pub fn has_role(organization_id: &str, principal: &Principal, role: OrgRole) -> bool {
+    self::role(organization_id, principal).is_some_and(|held| held >= role)
+}
+
+pub fn members(organization_id: &str) -> Vec<OrgMember> {
+    let prefix = member_prefix(organization_id);
+    ORG_MEMBERS.with(|members| {
+        members
+            .borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, member)| member)
+            .collect()
+    })
+}
+
+pub fn memberships(principal: &Principal) -> Vec<OrgMember> {
+    ORG_MEMBERS.with(|members| {
+        members
+            .borrow()
+            .iter()
+            .filter(|(_, member)| member.principal == *principal)
+            .map(|(_, member)| member)
+            .collect()
+    })
+}
+
+/// Whether `principal` may act on the offer as its seller: the farmer who
+/// created it, or a manager of the organization it belongs to.
+pub fn can_manage_offer(offer: &InvestmentOffer, principal: &Principal) -> bool {
+    offer.farmer == *principal
+        || offer
+            .organization
+            .as_ref()
+            .is_some_and(|org| has_role(org, principal, OrgRole::Manager))
+}
+
+/// Like `can_manage_offer`, but any member of the organization qualifies.
+pub fn can_view_offer(offer: &InvestmentOffer, principal: &Principal) -> bool {
+    offer.farmer == *principal
+        || offer
+            .organization
+            .as_ref()
+            .is_some_and(|org| has_role(org, principal, OrgRole::Viewer))
+}
+
+/// Creates an organization with `creator` as its first owner.
+pub fn create(
+    creator: Principal,
+    request: CreateOrganizationRequest,
+) -> Result<Organization, String> {
+    let now = ic_cdk::api::time();
+    let organization = Organization {
+        id: crate::generate_id("org"),
+        name: validation::validate_text("Name", &request.name, MAX_NAME_LEN)?,
+        description: request.description.trim().to_string(),
+        created_by: creator,
+        created_at: now,
+        updated_at: now,
+    };
+    if organization.description.chars().count() > MAX_DESCRIPTION_LEN {
+        return Err(format!(
+            "Description must be at most {} characters",
+            MAX_DESCRIPTION_LEN
+        ));
+    }
+
+    ORGANIZATIONS.with(|organizations| {
+        organizations
+            .borrow_mut()
+            .insert(organization.id.clone(), organization.clone());
+    });
+    audit::record(
+        creator,
+        "create_organization",
+        EntityType::Organization,
+        &organization.id,
+        None,
+        Some(&organization),
+    );
+
+    put_member(
+        creator,
+        OrgMember {
+            organization_id: organization.id.clone(),
+            principal: creator,
+            role: OrgRole::Owner,
+            added_by: creator,
+            added_at: now,
+        },
+    );
+
+    Ok(organization)
+}
+
+/// Adds a member or changes the role of an existing one. Only owners may
+/// do this, and the last owner cannot be demoted.
+pub fn set_member(
+    actor: Principal,
+    organization_id: &str,
+    principal: Principal,
+    role: OrgRole,
+) -> Result<OrgMember, String> {
+    require_owner(organization_id, &actor)?;
+
+    if USERS.with(|users| !users.borrow().contains_key(&principal)) {
+        return Err("User not found".to_string());
+    }
+    if role != OrgRole::Owner && is_last_owner(organization_id, &principal) {
+        return Err("An organization needs at least one owner".to_string());
+    }
+
+    let member = OrgMember {
+        organization_id: organization_id.to_string(),
+        principal,
+        role,
+        added_by: actor,
+        added_at: ic_cdk::api::time(),
+    };
+    put_member(actor, member.clone());
+
+    Ok(member)
+}
+
+/// Removes a member. Owners may remove anyone; members may remove themselves.
+pub fn remove_member(
+    actor: Principal,
+    organization_id: &str,
+    principal: Principal,
+) -> Result<OrgMember, String> {
+    if actor != principal {
+        require_owner(organization_id, &actor)?;
+    }
+
+    let key = member_key(organization_id, &principal);
+    let member = ORG_MEMBERS
+        .with(|members| members.borrow().get(&key))
+        .ok_or("Not a member of this organization")?;
+
+    if is_last_owner(organization_id, &principal) {
+        return Err("An organization needs at least one owner".to_string());
+    }
+
+    ORG_MEMBERS.with(|members| members.borrow_mut().remove(&key));
+    audit::record(
+        actor,
+        "remove_org_member",
+        EntityType::Organization,
+        organization_id,
+        Some(&member),
+        None,
+    );
+
+    Ok(member)
+}
+
+pub fn transactions(organization_id: &str) -> Vec<Transaction> {
+    TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .filter(|(_, txn)| txn.organization.as_deref() == Some(organization_id))
+            .map(|(_, txn)| txn)
+            .collect()
+    })
+}
+
+pub fn revenue(organization_id: &str) -> OrganizationRevenue {
+    let transactions = transactions(organization_id);
+    OrganizationRevenue {
+        organization_id: organization_id.to_string(),
+        transaction_count: transactions.len() as u64,
+        total_quantity: transactions.iter().map(|txn| txn.quantity).sum(),
+        total_amount: transactions.iter().map(|txn| txn.total_amount).sum(),
+        total_fees: transactions
+            .iter()
+            .map(|txn| fees::total(txn.fee_lines()))
+            .sum(),
+    }
+}
+
+fn require_owner(organization_id: &str, principal: &Principal) -> Result<(), String> {
+    if get(organization_id).is_none() {
+        return Err("Organization not found".to_string());
+    }
+    if !has_role(organization_id, principal, OrgRole::Owner) {
+        return Err("Organization owner role required".to_string());
+    }
+    Ok(())
+}
+
+fn is_last_owner(organization_id: &str, principal: &Principal) -> bool {
+    let owners = members(organization_id)
+        .into_iter()
+        .filter(|member| member.role == OrgRole::Owner)
+        .collect::<Vec<_>>();
+    owners.len() == 1 && owners[0].principal == *principal
+}
+
+fn put_member(actor: Principal, member: OrgMember) {
+    let key = member_key(&member.organization_id, &member.principal);
+    let before = ORG_MEMBERS.with(|members| members.borrow_mut().insert(key, member.clone()));
+    audit::record(
+        actor,
+        "set_org_member",
+        EntityType::Organization,
+        &member.organization_id,
+        before.as_ref(),
+        Some(&member),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(organization_id: &str, id: u8, role: OrgRole) -> Principal {
+        let principal = Principal::from_slice(&[id]);
+        ORG_MEMBERS.with(|members| {
+            members.borrow_mut().insert(
+                member_key(organization_id, &principal),
+                OrgMember {
+                    organization_id: organization_id.to_string(),
+                    principal,
+                    role,
+                    added_by: principal,
+                    added_at: 0,
+                },
+            )
+        });
+        principal
+    }
+
+    #[test]
+    fn roles_include_the_ones_below() {
+        let manager = add("org_1", 1, OrgRole::Manager);
+        assert!(has_role("org_1", &manager, OrgRole::Viewer));
+        assert!(has_role("org_1", &manager, OrgRole::Manager));
+        assert!(!has_role("org_1", &manager, OrgRole::Owner));
+        assert!(!has_role("org_2", &manager, OrgRole::Viewer));
+    }
+
+    #[test]
+    fn members_stay_within_their_organization() {
+        // "org_1" is a prefix of "org_10" without the separator
+        add("org_1", 1, OrgRole::Owner);
+        add("org_1", 2, OrgRole::Viewer);
+        add("org_10", 3, OrgRole::Owner);
+        add("org_0", 4, OrgRole::Owner);
+
+        let members = members("org_1");
+        assert_eq!(members.len(), 2);
+        assert!(members
+            .iter()
+            .all(|member| member.organization_id == "org_1"));
+        assert_eq!(memberships(&Principal::from_slice(&[3])).len(), 1);
+    }
+}
@@ -0,0 +1,180 @@
+use candid::Principal;
+
+use crate::types::{EntityType, PausableFeature, PauseState, Paused, Permission};
+use crate::PAUSE_STATE;
+use crate::{audit, permissions, validation};
+
+const MAX_REASON_LEN: usize = 256;
+
+pub fn get() -> PauseState {
+    PAUSE_STATE.with(|state| state.borrow().get().clone())
+}
+
+fn set(state: PauseState) {
+    PAUSE_STATE.with(|cell| {
+        cell.borrow_mut()
+            .set(state)
+            .expect("failed to write pause state");
+    });
+}
+
+/// Controllers can pause without an account, so that an emergency stop
+/// does not depend on the state of the user tables.
+pub fn authorize() -> Result<Principal, String> {
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        return Ok(caller);
+    }
+    permissions::authorize(Permission::PausePlatform)
+}
+
+/// Fails while `feature` is paused, on its own or by maintenance mode.
+pub fn check(feature: PausableFeature) -> Result<(), Paused> {
+    let state = get();
+    if state.platform || state.features.contains(&feature) {
+        return Err(Paused {
+            feature: Some(feature),
+            reason: state.reason,
+        });
+    }
+    Ok(())
+}
+
+/// Fails during maintenance mode. Every update endpoint that changes state
+/// runs this first; pausing and resuming do not, nor do cycle top-ups.
+pub fn check_platform() -> Result<(), Paused> {
+    let state = get();
+    if state.platform {
+        return Err(Paused {
+            feature: None,
+            reason: state.reason,
+        });
+    }
+    Ok(())
+}
+
+pub fn in_maintenance() -> bool {
+    get().platform
+}
+
+/// Pauses or resumes `feature`, or the whole platform when it is `None`.
+pub fn set_paused(
+    actor: Principal,
+    feature: Option<PausableFeature>,
+    paused: bool,
+    reason: Option<String>,
+) -> Result<PauseState, String> {
+    let reason = match reason {
+        Some(reason) => Some(validation::validate_text(
+            "Reason",
+            &reason,
+            MAX_REASON_LEN,
+        )?),
+        None => None,
+    };
+
+    let before = get();
+    let mut state = toggle(before.clone(), feature, paused, reason);
+    state.updated_by = Some(actor);
+    state.updated_at = ic_cdk::api::time();
+
+    set(state.clone());
+    audit::record(
+        actor,
+        if paused { "pause" } else { "unpause" },
+        EntityType::Config,
+        "pause_state",
+        Some(&before),
+        Some(&state),
+    );
+
+    Ok(state)
+}
+
+// The reason is kept for as long as anything stays paused
+fn toggle(
+    mut state: PauseState,
+    feature: Option<PausableFeature>,
+    paused: bool,
+    reason: Option<String>,
+) -> PauseState {
+    match feature {
+        None => state.platform = paused,
+        Some(feature) => {
+            state.features.retain(|existing| *existing != feature);
+            if paused {
+                state.features.push(feature);
+            }
+        }
+    }
+    if paused {
+        state.reason = reason;
+    } else if !state.platform && state.features.is_empty() {
+        state.reason = None;
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(text: &str) -> Option<String> {
+        Some(text.to_string())
+    }
+
+    #[test]
+    fn pausing_twice_lists_a_feature_once() {
+        let state = toggle(
+            PauseState::default(),
+            Some(PausableFeature::Offers),
+            true,
+            None,
+        );
+        let state = toggle(state, Some(PausableFeature::Offers), true, reason("Audit"));
+        assert_eq!(state.features, vec![PausableFeature::Offers]);
+        assert_eq!(state.reason, reason("Audit"));
+    }
+
+    #[test]
+    fn reason_is_kept_while_anything_is_paused() {
+        let state = toggle(PauseState::default(), None, true, reason("Upgrade"));
+        let state = toggle(
+            state,
+            Some(PausableFeature::Requests),
+            true,
+            reason("Review"),
+        );
+        let state = toggle(state, Some(PausableFeature::Requests), false, None);
+        assert_eq!(state.reason, reason("Review"));
+
+        let state = toggle(state, None, false, None);
+        assert!(!state.platform);
+        assert_eq!(state.reason, None);
+    }
+
+    #[test]
+    fn maintenance_pauses_every_feature() {
+        set(PauseState {
+            features: vec![PausableFeature::Settlements],
+            reason: reason("Reconciling"),
+            ..PauseState::default()
+        });
+        assert!(check_platform().is_ok());
+        assert!(check(PausableFeature::Offers).is_ok());
+        assert_eq!(
+            check(PausableFeature::Settlements).unwrap_err().message(),
+            "Settlements are paused: Reconciling"
+        );
+
+        set(PauseState {
+            platform: true,
+            ..PauseState::default()
+        });
+        assert!(check(PausableFeature::Offers).is_err());
+        assert_eq!(
+            check_platform().unwrap_err().message(),
+            "The platform is in maintenance mode"
+        );
+    }
+}
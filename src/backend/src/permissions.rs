@@ -0,0 +1,479 @@
+use candid::Principal;
+use std::collections::BTreeSet;
+
+use crate::types::{
+    AccountStatus, DefineRoleRequest, EffectivePermissions, EntityType, Permission, RoleDefinition,
+    RoleGrants, UserProfile, UserRole,
+};
+use crate::{audit, stats, validation};
+use crate::{CUSTOM_ROLES, ROLE_GRANTS, USERS};
+
+const MAX_ROLE_NAME_LEN: usize = 64;
+const MAX_ROLE_DESCRIPTION_LEN: usize = 256;
+const MAX_GRANTED_ROLES: usize = 16;
+
+const ALL_PERMISSIONS: [Permission; 18] = [
+    Permission::CreateOffer,
+    Permission::CreateInvestmentRequest,
+    Permission::ReviewKyc,
+    Permission::ViewUsers,
+    Permission::ManageRoles,
+    Permission::ExportUserData,
+    Permission::ViewAuditLog,
+    Permission::ManageArchive,
+    Permission::ManageNotifier,
+    Permission::ManageKycPolicy,
+    Permission::ManageTrades,
+    Permission::ManageRateLimits,
+    Permission::ManageSettings,
+    Permission::PausePlatform,
+    Permission::ViewRevenue,
+    Permission::ManageTreasury,
+    Permission::ManageCycles,
+    Permission::ApproveAdminActions,
+];
+
+const BUILT_IN_ROLES: [UserRole; 5] = [
+    UserRole::Admin,
+    UserRole::Farmer,
+    UserRole::Investor,
+    UserRole::Verifier,
+    UserRole::Guest,
+];
+
+/// Permissions that come with each built-in role.
+fn built_in_permissions(role: &UserRole) -> &'static [Permission] {
+    match role {
+        UserRole::Admin => &ALL_PERMISSIONS,
+        UserRole::Farmer => &[Permission::CreateOffer],
+        UserRole::Investor => &[Permission::CreateInvestmentRequest],
+        UserRole::Verifier => &[Permission::ReviewKyc],
+        UserRole::Guest => &[],
+    }
+}
+
+fn built_in_name(role: &UserRole) -> String {
+    format!("{:?}", role)
+}
+
+/// The guard every endpoint goes through: resolves the caller to an active,
+/// registered account and checks that it holds `permission`.
+pub fn authorize(permission: Permission) -> Result<Principal, String> {
+    let caller = authenticate()?;
+    check(&caller, permission)?;
+    Ok(caller)
+}
+
+/// Resolves the caller to an active, registered account without requiring
+/// a particular permission.
+pub fn authenticate() -> Result<Principal, String> {
+    let (caller, user) = account()?;
+    if !matches!(user.account_status(), AccountStatus::Active) {
+        return Err("Account is not active".to_string());
+    }
+    Ok(caller)
+}
+
+/// Resolves the caller to a registered account in any status, for the
+/// calls that manage the account itself: a deactivated account can still
+/// be reactivated, exported or deleted.
+pub fn account() -> Result<(Principal, UserProfile), String> {
+    let caller = identify()?;
+    match USERS.with(|users| users.borrow().get(&caller)) {
+        Some(user) => Ok((caller, user)),
+        None => Err("User not found".to_string()),
+    }
+}
+
+/// Resolves the caller to the account principal it is linked to, or to
+/// itself, for the calls made before an account exists.
+pub fn identify() -> Result<Principal, String> {
+    if ic_cdk::caller() == Principal::anonymous() {
+        return Err("Authentication required".to_string());
+    }
+    Ok(crate::get_caller())
+}
+
+/// Active accounts holding `permission`, e.g. to notify them.
+pub fn holders(permission: Permission) -> Vec<Principal> {
+    let active = USERS.with(|users| {
+        users
+            .borrow()
+            .iter()
+            .filter(|(_, user)| matches!(user.account_status(), AccountStatus::Active))
+            .map(|(principal, _)| principal)
+            .collect::<Vec<_>>()
+    });
+    active
+        .into_iter()
+        .filter(|principal| effective(principal).contains(&permission))
+        .collect()
+}
+
+pub fn check(principal: &Principal, permission: Permission) -> Result<(), String> {
+    if effective(principal).contains(&permission) {
+        Ok(())
+    } else {
+        Err(format!("Permission denied: {:?} required", permission))
+    }
+}
+
+pub fn effective(principal: &Principal) -> BTreeSet<Permission> {
+    let role = match USERS.with(|users| users.borrow().get(principal)) {
+        Some(user) => user.role,
+        None => return BTreeSet::new(),
+    };
+
+    let mut permissions = built_in_permissions(&role)
+        .iter()
+        .copied()
+        .collect::<BTreeSet<_>>();
+    for name in granted(principal).roles {
+        if let Some(definition) = CUSTOM_ROLES.with(|roles| roles.borrow().get(&name)) {
+            permissions.extend(definition.permissions);
+        }
+    }
+    permissions
+}
+
+pub fn effective_permissions(principal: &Principal) -> Option<EffectivePermissions> {
+    let user = USERS.with(|users| users.borrow().get(principal))?;
+    Some(EffectivePermissions {
+        principal: *principal,
+        role: user.role,
+        custom_roles: granted(principal).roles,
+        permissions: effective(principal).into_iter().collect(),
+    })
+}
+
+/// Records who granted `principal` the built-in `Admin` role, or clears
+/// the record when the role is taken away.
+pub fn set_admin_grant(
+    actor: Principal,
+    principal: Principal,
+    granted_by: Option<Principal>,
+) -> Result<(), String> {
+    let before = granted(&principal);
+    if before.admin_granted_by == granted_by {
+        return Ok(());
+    }
+    let after = RoleGrants {
+        admin_granted_by: granted_by,
+        ..before.clone()
+    };
+    store_grants(actor, "set_admin_grant", principal, &before, after).map(drop)
+}
+
+/// Whether `principal` counts towards multi-signature approvals: an active
+/// admin who was granted the role, or a holder of a custom role carrying
+/// `ApproveAdminActions`. Self-registered admins from before registration
+/// was restricted do not count.
+pub fn is_approver(principal: &Principal) -> bool {
+    let user = match USERS.with(|users| users.borrow().get(principal)) {
+        Some(user) if matches!(user.account_status(), AccountStatus::Active) => user,
+        _ => return false,
+    };
+    let grants = granted(principal);
+    if matches!(user.role, UserRole::Admin) && grants.admin_granted_by.is_some() {
+        return true;
+    }
+    grants.roles.iter().any(|name| {
+        CUSTOM_ROLES
+            .with(|roles| roles.borrow().get(name))
+            .is_some_and(|role| role.permissions.contains(&Permission::ApproveAdminActions))
+    })
+}
+
+fn granted(principal: &Principal) -> RoleGrants {
+    ROLE_GRANTS
+        .with(|grants| grants.borrow().get(principal))
+        .unwrap_or_default()
+}
+
+/// Changes the built-in role of `principal`.
+pub fn set_user_role(
+    actor: Principal,
+    principal: Principal,
+    role: UserRole,
+) -> Result<UserProfile, String> {
+    let before = USERS
+        .with(|users| users.borrow().get(&principal))
+        .ok_or("User not found")?;
+
+    let is_admin = matches!(role, UserRole::Admin);
+    let mut user = before.clone();
+    user.role = role;
+    user.updated_at = ic_cdk::api::time();
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(principal, user.clone());
+    });
+    set_admin_grant(actor, principal, is_admin.then_some(actor))?;
+    stats::on_user(Some(&before), &user);
+    audit::record(
+        actor,
+        "update_user_role",
+        EntityType::User,
+        &principal.to_text(),
+        Some(&before),
+        Some(&user),
+    );
+
+    Ok(user)
+}
+
+pub fn list_roles() -> Vec<RoleDefinition> {
+    let mut roles = BUILT_IN_ROLES
+        .iter()
+        .map(|role| RoleDefinition {
+            name: built_in_name(role),
+            description: "Built-in role".to_string(),
+            permissions: built_in_permissions(role).to_vec(),
+            built_in: true,
+        })
+        .collect::<Vec<_>>();
+    roles.extend(CUSTOM_ROLES.with(|custom| {
+        custom
+            .borrow()
+            .iter()
+            .map(|(_, role)| role)
+            .collect::<Vec<_>>()
+    }));
+    roles
+}
+
+/// Creates or replaces a custom role.
+pub fn define_role(actor: Principal, request: DefineRoleRequest) -> Result<RoleDefinition, String> {
+    let name = validation::validate_text("Role name", &request.name, MAX_ROLE_NAME_LEN)?;
+    if BUILT_IN_ROLES
+        .iter()
+        .any(|role| built_in_name(role).eq_ignore_ascii_case(&name))
+    {
+        return Err("Built-in roles cannot be redefined".to_string());
+    }
+
+    let mut permissions = request.permissions;
+    permissions.sort();
+    permissions.dedup();
+
+    let role = RoleDefinition {
+        name: name.clone(),
+        description: request.description.trim().to_string(),
+        permissions,
+        built_in: false,
+    };
+    if role.description.chars().count() > MAX_ROLE_DESCRIPTION_LEN {
+        return Err(format!(
+            "Description must be at most {} characters",
+            MAX_ROLE_DESCRIPTION_LEN
+        ));
+    }
+
+    let before = CUSTOM_ROLES.with(|roles| roles.borrow_mut().insert(name.clone(), role.clone()));
+    audit::record(
+        actor,
+        "define_role",
+        EntityType::Config,
+        &format!("role:{}", name),
+        before.as_ref(),
+        Some(&role),
+    );
+
+    Ok(role)
+}
+
+/// Deletes a custom role and takes it away from everyone it was granted to.
+pub fn delete_role(actor: Principal, name: &str) -> Result<RoleDefinition, String> {
+    let role = CUSTOM_ROLES
+        .with(|roles| roles.borrow_mut().remove(&name.to_string()))
+        .ok_or("Role not found")?;
+    audit::record(
+        actor,
+        "delete_role",
+        EntityType::Config,
+        &format!("role:{}", name),
+        Some(&role),
+        None,
+    );
+
+    let holders = ROLE_GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .iter()
+            .filter(|(_, granted)| granted.roles.iter().any(|role| role == name))
+            .map(|(principal, _)| principal)
+            .collect::<Vec<_>>()
+    });
+    for principal in holders {
+        revoke_role(actor, principal, name)?;
+    }
+
+    Ok(role)
+}
+
+pub fn grant_role(
+    actor: Principal,
+    principal: Principal,
+    name: &str,
+) -> Result<RoleGrants, String> {
+    if USERS.with(|users| !users.borrow().contains_key(&principal)) {
+        return Err("User not found".to_string());
+    }
+    if CUSTOM_ROLES.with(|roles| !roles.borrow().contains_key(&name.to_string())) {
+        return Err("Role not found".to_string());
+    }
+
+    let before = granted(&principal);
+    if before.roles.iter().any(|role| role == name) {
+        return Ok(before);
+    }
+    if before.roles.len() >= MAX_GRANTED_ROLES {
+        return Err(format!(
+            "At most {} custom roles per user",
+            MAX_GRANTED_ROLES
+        ));
+    }
+
+    let mut after = before.clone();
+    after.roles.push(name.to_string());
+    store_grants(actor, "grant_role", principal, &before, after)
+}
+
+pub fn revoke_role(
+    actor: Principal,
+    principal: Principal,
+    name: &str,
+) -> Result<RoleGrants, String> {
+    let before = granted(&principal);
+    if !before.roles.iter().any(|role| role == name) {
+        return Err("Role not granted".to_string());
+    }
+
+    let mut after = before.clone();
+    after.roles.retain(|role| role != name);
+    store_grants(actor, "revoke_role", principal, &before, after)
+}
+
+fn store_grants(
+    actor: Principal,
+    action: &str,
+    principal: Principal,
+    before: &RoleGrants,
+    after: RoleGrants,
+) -> Result<RoleGrants, String> {
+    ROLE_GRANTS.with(|grants| {
+        let mut grants = grants.borrow_mut();
+        if after.roles.is_empty() && after.admin_granted_by.is_none() {
+            grants.remove(&principal);
+        } else {
+            grants.insert(principal, after.clone());
+        }
+    });
+    audit::record(
+        actor,
+        action,
+        EntityType::User,
+        &principal.to_text(),
+        Some(before),
+        Some(&after),
+    );
+
+    Ok(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::KycStatus;
+
+    fn register(id: u8, role: UserRole, status: AccountStatus) -> Principal {
+        let principal = Principal::from_slice(&[id]);
+        USERS.with(|users| {
+            users.borrow_mut().insert(
+                principal,
+                UserProfile {
+                    principal,
+                    role,
+                    display_name: format!("User {}", id),
+                    email: format!("user{}@example.com", id),
+                    email_verified: Some(true),
+                    kyc_status: Some(KycStatus::Unverified),
+                    status: Some(status),
+                    created_at: 0,
+                    updated_at: 0,
+                },
+            )
+        });
+        principal
+    }
+
+    fn define(name: &str, permissions: Vec<Permission>) {
+        CUSTOM_ROLES.with(|roles| {
+            roles.borrow_mut().insert(
+                name.to_string(),
+                RoleDefinition {
+                    name: name.to_string(),
+                    description: String::new(),
+                    permissions,
+                    built_in: false,
+                },
+            )
+        });
+    }
+
+    fn grant(principal: Principal, roles: &[&str], admin_granted_by: Option<Principal>) {
+        ROLE_GRANTS.with(|grants| {
+            grants.borrow_mut().insert(
+                principal,
+                RoleGrants {
+                    roles: roles.iter().map(|role| role.to_string()).collect(),
+                    admin_granted_by,
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn custom_roles_add_to_the_built_in_ones() {
+        let farmer = register(1, UserRole::Farmer, AccountStatus::Active);
+        define("auditor", vec![Permission::ViewAuditLog]);
+        grant(farmer, &["auditor", "missing"], None);
+
+        assert_eq!(
+            effective(&farmer),
+            BTreeSet::from([Permission::CreateOffer, Permission::ViewAuditLog])
+        );
+        assert!(check(&farmer, Permission::ViewUsers).is_err());
+        assert!(effective(&Principal::from_slice(&[9])).is_empty());
+    }
+
+    #[test]
+    fn holders_are_active_accounts_with_the_permission() {
+        let admin = register(1, UserRole::Admin, AccountStatus::Active);
+        register(2, UserRole::Admin, AccountStatus::Deactivated);
+        let operator = register(3, UserRole::Guest, AccountStatus::Active);
+        register(4, UserRole::Farmer, AccountStatus::Active);
+        define("operator", vec![Permission::ManageCycles]);
+        grant(operator, &["operator"], None);
+
+        assert_eq!(holders(Permission::ManageCycles), vec![admin, operator]);
+    }
+
+    #[test]
+    fn only_granted_admins_and_approver_roles_approve() {
+        let granted = register(1, UserRole::Admin, AccountStatus::Active);
+        let self_registered = register(2, UserRole::Admin, AccountStatus::Active);
+        let inactive = register(3, UserRole::Admin, AccountStatus::Deactivated);
+        let approver = register(4, UserRole::Verifier, AccountStatus::Active);
+        grant(granted, &[], Some(approver));
+        grant(inactive, &[], Some(approver));
+        define("approver", vec![Permission::ApproveAdminActions]);
+        grant(approver, &["approver"], None);
+
+        assert!(is_approver(&granted));
+        assert!(!is_approver(&self_registered));
+        assert!(!is_approver(&inactive));
+        assert!(is_approver(&approver));
+        assert!(!is_approver(&Principal::from_slice(&[9])));
+    }
+}
@@ -0,0 +1,159 @@
+use candid::Principal;
+
+use crate::types::{
+    Portfolio, PortfolioPosition, ProductExposure, StatusBreakdown, Transaction, TransactionStatus,
+};
+use crate::{archive, prices};
+use crate::{OFFERS, TRANSACTIONS};
+
+fn new_position(offer_id: &str) -> PortfolioPosition {
+    let offer = OFFERS.with(|offers| offers.borrow().get(&offer_id.to_string()));
+    PortfolioPosition {
+        offer_id: offer_id.to_string(),
+        product_name: offer.as_ref().map(|offer| offer.product_name.clone()),
+        product_type: offer.as_ref().map(|offer| offer.product_type.clone()),
+        quality_grade: offer.map(|offer| offer.quality_grade),
+        transaction_count: 0,
+        committed_capital: 0.0,
+        refunded_amount: 0.0,
+        quantity: 0,
+        delivered_quantity: 0,
+        outstanding_quantity: 0,
+        average_price_per_kg: 0.0,
+        market_price_per_kg: None,
+        market_value: None,
+        unrealized_return: None,
+    }
+}
+
+/// The investor's transactions, including archived ones, aggregated into
+/// positions and valued at the latest market prices.
+pub async fn build(investor: Principal) -> Result<Portfolio, String> {
+    let mut transactions = TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .filter(|(_, txn)| txn.investor == investor)
+            .map(|(_, txn)| txn)
+            .collect::<Vec<_>>()
+    });
+    transactions.extend(
+        archive::fetch_transactions(investor)
+            .await?
+            .into_iter()
+            .filter(|txn| txn.investor == investor),
+    );
+
+    let mut positions: Vec<PortfolioPosition> = Vec::new();
+    let mut by_status: Vec<StatusBreakdown> = Vec::new();
+    for txn in &transactions {
+        add_to_status(&mut by_status, txn);
+
+        let position = match positions
+            .iter()
+            .position(|position| position.offer_id == txn.offer_id)
+        {
+            Some(index) => &mut positions[index],
+            None => {
+                positions.push(new_position(&txn.offer_id));
+                positions.last_mut().unwrap()
+            }
+        };
+
+        if txn.status == TransactionStatus::Refunded {
+            position.refunded_amount += txn.total_amount;
+            continue;
+        }
+        position.transaction_count += 1;
+        position.committed_capital += txn.total_amount;
+        position.quantity += txn.quantity;
+        if txn.status == TransactionStatus::Completed {
+            position.delivered_quantity += txn.quantity;
+        } else {
+            position.outstanding_quantity += txn.quantity;
+        }
+    }
+
+    let market_prices = prices::latest();
+    for position in &mut positions {
+        if position.quantity > 0 {
+            position.average_price_per_kg = position.committed_capital / position.quantity as f64;
+        }
+        let price = match (&position.product_type, &position.quality_grade) {
+            (Some(product_type), Some(quality_grade)) => market_prices.iter().find(|price| {
+                price.product_type == *product_type && price.quality_grade == *quality_grade
+            }),
+            _ => None,
+        };
+        if let Some(price) = price {
+            let market_value = position.quantity as f64 * price.price_per_kg;
+            position.market_price_per_kg = Some(price.price_per_kg);
+            position.market_value = Some(market_value);
+            position.unrealized_return = Some(market_value - position.committed_capital);
+        }
+    }
+
+    let mut by_product_type: Vec<ProductExposure> = Vec::new();
+    for position in &positions {
+        let product_type = match &position.product_type {
+            Some(product_type) => product_type,
+            None => continue,
+        };
+        let market_value = position.market_value.unwrap_or(position.committed_capital);
+        match by_product_type
+            .iter_mut()
+            .find(|exposure| exposure.product_type == *product_type)
+        {
+            Some(exposure) => {
+                exposure.committed_capital += position.committed_capital;
+                exposure.quantity += position.quantity;
+                exposure.market_value += market_value;
+            }
+            None => by_product_type.push(ProductExposure {
+                product_type: product_type.clone(),
+                committed_capital: position.committed_capital,
+                quantity: position.quantity,
+                market_value,
+            }),
+        }
+    }
+
+    let committed_capital = positions
+        .iter()
+        .map(|position| position.committed_capital)
+        .sum::<f64>();
+    let market_value = positions
+        .iter()
+        .map(|position| position.market_value.unwrap_or(position.committed_capital))
+        .sum::<f64>();
+    let unrealized_return = market_value - committed_capital;
+
+    Ok(Portfolio {
+        investor,
+        generated_at: ic_cdk::api::time(),
+        positions,
+        by_product_type,
+        by_status,
+        committed_capital,
+        market_value,
+        unrealized_return,
+        return_ratio: (committed_capital > 0.0).then_some(unrealized_return / committed_capital),
+    })
+}
+
+fn add_to_status(by_status: &mut Vec<StatusBreakdown>, txn: &Transaction) {
+    match by_status
+        .iter_mut()
+        .find(|breakdown| breakdown.status == txn.status)
+    {
+        Some(breakdown) => {
+            breakdown.transaction_count += 1;
+            breakdown.amount += txn.total_amount;
+        }
+        None => by_status.push(StatusBreakdown {
+            status: txn.status.clone(),
+            transaction_count: 1,
+            amount: txn.total_amount,
+        }),
+    }
+}
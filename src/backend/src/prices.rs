@@ -0,0 +1,222 @@
+use crate::types::{
+    InvestmentOffer, PriceCandle, PriceHistoryQuery, PricePoint, ProductType, ReferencePrice,
+    Transaction, TransactionStatus,
+};
+use crate::{OFFERS, PRICE_HISTORY, TRANSACTIONS};
+
+const DAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Trades older than this do not count towards the reference price.
+const REFERENCE_WINDOW_NS: u64 = 30 * DAY_NS;
+const MAX_HISTORY_BUCKETS: u64 = 366;
+// Offer locations are free text; a region needs no more than this
+const MAX_REGION_LEN: usize = 128;
+
+fn region_of(offer: &InvestmentOffer) -> String {
+    offer.location.trim().chars().take(MAX_REGION_LEN).collect()
+}
+
+fn matches_region(point: &PricePoint, region: &Option<String>) -> bool {
+    match region {
+        Some(region) => point.region.eq_ignore_ascii_case(region.trim()),
+        None => true,
+    }
+}
+
+/// Adds an accepted trade to the price history.
+pub fn record(offer: &InvestmentOffer, transaction: &Transaction) {
+    PRICE_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let index = history.last_key_value().map_or(0, |(index, _)| index + 1);
+        history.insert(
+            index,
+            PricePoint {
+                index,
+                timestamp: transaction.created_at,
+                transaction_id: transaction.id.clone(),
+                product_type: offer.product_type.clone(),
+                quality_grade: offer.quality_grade.clone(),
+                region: region_of(offer),
+                quantity: transaction.quantity,
+                price_per_kg: transaction.price_per_kg,
+            },
+        );
+    });
+}
+
+/// Drops a refunded trade from the history; it no longer reflects a price
+/// anyone paid.
+pub fn on_refunded(transaction_id: &str) {
+    PRICE_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let index = history
+            .iter()
+            .find(|(_, point)| point.transaction_id == transaction_id)
+            .map(|(index, _)| index);
+        if let Some(index) = index {
+            history.remove(&index);
+        }
+    });
+}
+
+/// Fills an empty history from the trades still held by the backend, so
+/// that canisters upgraded from before the history existed start with data.
+pub fn seed() {
+    if !PRICE_HISTORY.with(|history| history.borrow().is_empty()) {
+        return;
+    }
+
+    let mut transactions = TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .filter(|(_, txn)| txn.status != TransactionStatus::Refunded)
+            .map(|(_, txn)| txn)
+            .collect::<Vec<_>>()
+    });
+    transactions.sort_by_key(|txn| txn.created_at);
+
+    for txn in transactions {
+        if let Some(offer) = OFFERS.with(|offers| offers.borrow().get(&txn.offer_id)) {
+            record(&offer, &txn);
+        }
+    }
+}
+
+fn add_to_candle(candle: &mut PriceCandle, point: &PricePoint) {
+    // Points are stored in trade order, so the latest one closes the bucket
+    candle.high = candle.high.max(point.price_per_kg);
+    candle.low = candle.low.min(point.price_per_kg);
+    candle.close = point.price_per_kg;
+    candle.volume += point.quantity;
+    candle.value += point.quantity as f64 * point.price_per_kg;
+    candle.trade_count += 1;
+}
+
+/// Trades in `[from, to)` matching the query, grouped into day or week
+/// candles. Buckets without trades are left out.
+pub fn history(query: PriceHistoryQuery) -> Result<Vec<PriceCandle>, String> {
+    let duration = query.bucket.duration_ns();
+    if query.to <= query.from {
+        return Err("Period end must be after its start".to_string());
+    }
+    let first = query.from - query.from % duration;
+    if (query.to - first) / duration >= MAX_HISTORY_BUCKETS {
+        return Err(format!(
+            "At most {} buckets can be requested at once",
+            MAX_HISTORY_BUCKETS
+        ));
+    }
+
+    let mut candles: Vec<PriceCandle> = Vec::new();
+    PRICE_HISTORY.with(|history| {
+        for (_, point) in history.borrow().iter() {
+            if point.timestamp < query.from
+                || point.timestamp >= query.to
+                || point.product_type != query.product_type
+                || !matches_region(&point, &query.region)
+            {
+                continue;
+            }
+            if let Some(quality_grade) = &query.quality_grade {
+                if point.quality_grade != *quality_grade {
+                    continue;
+                }
+            }
+
+            let start = point.timestamp - point.timestamp % duration;
+            match candles.iter_mut().find(|candle| candle.start == start) {
+                Some(candle) => add_to_candle(candle, &point),
+                None => candles.push(PriceCandle {
+                    start,
+                    end: start + duration,
+                    open: point.price_per_kg,
+                    high: point.price_per_kg,
+                    low: point.price_per_kg,
+                    close: point.price_per_kg,
+                    volume: point.quantity,
+                    value: point.quantity as f64 * point.price_per_kg,
+                    vwap: 0.0,
+                    trade_count: 1,
+                }),
+            }
+        }
+    });
+
+    for candle in &mut candles {
+        if candle.volume > 0 {
+            candle.vwap = candle.value / candle.volume as f64;
+        }
+    }
+    candles.sort_by_key(|candle| candle.start);
+    Ok(candles)
+}
+
+/// Volume-weighted price per product type and grade over the last 30 days.
+pub fn reference_prices(
+    product_type: Option<ProductType>,
+    region: Option<String>,
+) -> Vec<ReferencePrice> {
+    let since = ic_cdk::api::time().saturating_sub(REFERENCE_WINDOW_NS);
+
+    let mut prices: Vec<ReferencePrice> = Vec::new();
+    PRICE_HISTORY.with(|history| {
+        for (_, point) in history.borrow().iter() {
+            if point.timestamp < since || !matches_region(&point, &region) {
+                continue;
+            }
+            if let Some(product_type) = &product_type {
+                if point.product_type != *product_type {
+                    continue;
+                }
+            }
+
+            let value = point.quantity as f64 * point.price_per_kg;
+            match prices.iter_mut().find(|price| {
+                price.product_type == point.product_type
+                    && price.quality_grade == point.quality_grade
+            }) {
+                Some(price) => {
+                    // Running value until the division below
+                    price.vwap += value;
+                    price.volume += point.quantity;
+                    price.trade_count += 1;
+                    price.last_price_per_kg = point.price_per_kg;
+                    price.last_traded_at = point.timestamp;
+                }
+                None => prices.push(ReferencePrice {
+                    product_type: point.product_type,
+                    quality_grade: point.quality_grade,
+                    vwap: value,
+                    volume: point.quantity,
+                    trade_count: 1,
+                    last_price_per_kg: point.price_per_kg,
+                    last_traded_at: point.timestamp,
+                }),
+            }
+        }
+    });
+
+    for price in &mut prices {
+        if price.volume > 0 {
+            price.vwap /= price.volume as f64;
+        }
+    }
+    prices
+}
+
+/// The most recent trade for every product type and grade, however old.
+pub fn latest() -> Vec<PricePoint> {
+    let mut latest: Vec<PricePoint> = Vec::new();
+    PRICE_HISTORY.with(|history| {
+        for (_, point) in history.borrow().iter() {
+            match latest.iter_mut().find(|price| {
+                price.product_type == point.product_type
+                    && price.quality_grade == point.quality_grade
+            }) {
+                Some(price) => *price = point,
+                None => latest.push(point),
+            }
+        }
+    });
+    latest
+}
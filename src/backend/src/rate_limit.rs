@@ -0,0 +1,303 @@
+use candid::Principal;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use crate::audit;
+use crate::types::{
+    EntityType, RateLimit, RateLimitConfig, RateLimitOverride, RateLimited, RateLimitedAction,
+};
+use crate::{RATE_LIMIT_CONFIG, RATE_LIMIT_OVERRIDES};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+thread_local! {
+    // Sliding-window log of recent actions, oldest first. Kept on the heap:
+    // losing it on upgrade only resets the windows.
+    static RECENT: RefCell<HashMap<(Principal, RateLimitedAction), VecDeque<u64>>> =
+        RefCell::new(HashMap::new());
+}
+
+pub fn get_config() -> RateLimitConfig {
+    RATE_LIMIT_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn set_config(config: RateLimitConfig) {
+    RATE_LIMIT_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .expect("failed to write rate limit config");
+    });
+}
+
+pub fn configure(actor: Principal, config: RateLimitConfig) -> Result<RateLimitConfig, String> {
+    validate_limits(&config.limits)?;
+
+    let before = get_config();
+    set_config(config.clone());
+    audit::record(
+        actor,
+        "configure_rate_limits",
+        EntityType::Config,
+        "rate_limits",
+        Some(&before),
+        Some(&config),
+    );
+
+    Ok(config)
+}
+
+fn validate_limits(limits: &[RateLimit]) -> Result<(), String> {
+    for (position, limit) in limits.iter().enumerate() {
+        if limit.max == 0 || limit.window_ns == 0 {
+            return Err(format!(
+                "Limit for {:?} needs a positive maximum and window",
+                limit.action
+            ));
+        }
+        if limits[..position]
+            .iter()
+            .any(|other| other.action == limit.action)
+        {
+            return Err(format!("Duplicate limit for {:?}", limit.action));
+        }
+    }
+    Ok(())
+}
+
+pub fn overrides() -> Vec<(Principal, RateLimitOverride)> {
+    RATE_LIMIT_OVERRIDES.with(|overrides| overrides.borrow().iter().collect())
+}
+
+/// Sets or, with `None`, removes the override for `principal`.
+pub fn set_override(
+    actor: Principal,
+    principal: Principal,
+    rate_limit_override: Option<RateLimitOverride>,
+) -> Result<(), String> {
+    if let Some(rate_limit_override) = &rate_limit_override {
+        validate_limits(&rate_limit_override.limits)?;
+    }
+
+    let before = RATE_LIMIT_OVERRIDES.with(|overrides| {
+        let mut overrides = overrides.borrow_mut();
+        match &rate_limit_override {
+            Some(rate_limit_override) => overrides.insert(principal, rate_limit_override.clone()),
+            None => overrides.remove(&principal),
+        }
+    });
+    if before.is_none() && rate_limit_override.is_none() {
+        return Err("No override set for principal".to_string());
+    }
+
+    audit::record(
+        actor,
+        "set_rate_limit_override",
+        EntityType::Config,
+        &principal.to_text(),
+        before.as_ref(),
+        rate_limit_override.as_ref(),
+    );
+    Ok(())
+}
+
+// The limit that applies to `principal`, if any
+fn limit_for(principal: &Principal, action: RateLimitedAction) -> Option<RateLimit> {
+    let find = |limits: &[RateLimit]| limits.iter().find(|limit| limit.action == action).cloned();
+
+    match RATE_LIMIT_OVERRIDES.with(|overrides| overrides.borrow().get(principal)) {
+        Some(rate_limit_override) if rate_limit_override.exempt => None,
+        Some(rate_limit_override) => {
+            find(&rate_limit_override.limits).or_else(|| find(&get_config().limits))
+        }
+        None => find(&get_config().limits),
+    }
+}
+
+/// Counts one of each of `actions` against `principal`'s limits. Nothing is
+/// counted unless all of them are within their limit.
+pub fn consume(principal: Principal, actions: &[RateLimitedAction]) -> Result<(), RateLimited> {
+    consume_at(principal, actions, ic_cdk::api::time())
+}
+
+fn consume_at(
+    principal: Principal,
+    actions: &[RateLimitedAction],
+    now: u64,
+) -> Result<(), RateLimited> {
+    let limits = actions
+        .iter()
+        .filter_map(|action| limit_for(&principal, *action))
+        .collect::<Vec<_>>();
+
+    RECENT.with(|recent| {
+        let mut recent = recent.borrow_mut();
+
+        for limit in &limits {
+            let window_start = now.saturating_sub(limit.window_ns);
+            let times = match recent.get_mut(&(principal, limit.action)) {
+                Some(times) => times,
+                None => continue,
+            };
+            while times.front().is_some_and(|time| *time <= window_start) {
+                times.pop_front();
+            }
+            if times.len() >= limit.max as usize {
+                // Entries leave the window oldest first; once this one has, the action fits
+                let free_at = times[times.len() - limit.max as usize] + limit.window_ns;
+                return Err(RateLimited {
+                    action: limit.action,
+                    retry_after_secs: (free_at - now).div_ceil(NANOS_PER_SEC),
+                });
+            }
+        }
+
+        for limit in &limits {
+            recent
+                .entry((principal, limit.action))
+                .or_default()
+                .push_back(now);
+        }
+        Ok(())
+    })
+}
+
+/// Drops entries that have left every window they could count towards.
+pub fn prune() {
+    let now = ic_cdk::api::time();
+    // Overrides may use longer windows than the defaults
+    let longest_window = get_config()
+        .limits
+        .iter()
+        .chain(overrides().iter().flat_map(|(_, o)| o.limits.iter()))
+        .map(|limit| limit.window_ns)
+        .max()
+        .unwrap_or(0);
+    let cutoff = now.saturating_sub(longest_window);
+
+    RECENT.with(|recent| {
+        recent.borrow_mut().retain(|_, times| {
+            while times.front().is_some_and(|time| *time <= cutoff) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 10 * NANOS_PER_SEC;
+    // A realistic current time, so that every window lies after 0
+    const T: u64 = 1_700_000_000 * NANOS_PER_SEC;
+
+    fn limit(action: RateLimitedAction, max: u32) -> RateLimit {
+        RateLimit {
+            action,
+            max,
+            window_ns: WINDOW,
+        }
+    }
+
+    fn configure_limits(limits: Vec<RateLimit>) {
+        set_config(RateLimitConfig { limits });
+    }
+
+    fn principal() -> Principal {
+        Principal::from_slice(&[1])
+    }
+
+    #[test]
+    fn window_frees_up_when_the_oldest_entry_leaves() {
+        configure_limits(vec![limit(RateLimitedAction::CreateOffer, 2)]);
+        let offer = [RateLimitedAction::CreateOffer];
+
+        assert!(consume_at(principal(), &offer, T).is_ok());
+        assert!(consume_at(principal(), &offer, T + NANOS_PER_SEC).is_ok());
+
+        let limited = consume_at(principal(), &offer, T + NANOS_PER_SEC + 1).unwrap_err();
+        assert_eq!(limited.action, RateLimitedAction::CreateOffer);
+        // 9 seconds and a nanosecond, rounded up
+        assert_eq!(limited.retry_after_secs, 9);
+        assert!(consume_at(principal(), &offer, T + WINDOW - 1).is_err());
+
+        // The first entry drops out exactly one window later
+        assert!(consume_at(principal(), &offer, T + WINDOW).is_ok());
+        assert!(consume_at(principal(), &offer, T + WINDOW + NANOS_PER_SEC - 1).is_err());
+        assert!(consume_at(principal(), &offer, T + WINDOW + NANOS_PER_SEC).is_ok());
+    }
+
+    #[test]
+    fn nothing_is_counted_unless_every_action_fits() {
+        configure_limits(vec![
+            limit(RateLimitedAction::CreateRequest, 5),
+            limit(RateLimitedAction::SendMessage, 1),
+        ]);
+        let both = [
+            RateLimitedAction::CreateRequest,
+            RateLimitedAction::SendMessage,
+        ];
+
+        assert!(consume_at(principal(), &both, T).is_ok());
+        for _ in 0..3 {
+            let limited = consume_at(principal(), &both, T + 1).unwrap_err();
+            assert_eq!(limited.action, RateLimitedAction::SendMessage);
+        }
+        // Only the first call counted towards requests
+        for _ in 0..4 {
+            assert!(consume_at(principal(), &[RateLimitedAction::CreateRequest], T + 1).is_ok());
+        }
+        assert!(consume_at(principal(), &[RateLimitedAction::CreateRequest], T + 1).is_err());
+    }
+
+    #[test]
+    fn overrides_replace_or_lift_the_defaults() {
+        configure_limits(vec![
+            limit(RateLimitedAction::CreateOffer, 1),
+            limit(RateLimitedAction::SendMessage, 1),
+        ]);
+        let trusted = Principal::from_slice(&[2]);
+        let exempt = Principal::from_slice(&[3]);
+        RATE_LIMIT_OVERRIDES.with(|overrides| {
+            let mut overrides = overrides.borrow_mut();
+            overrides.insert(
+                trusted,
+                RateLimitOverride {
+                    exempt: false,
+                    limits: vec![limit(RateLimitedAction::CreateOffer, 3)],
+                },
+            );
+            overrides.insert(
+                exempt,
+                RateLimitOverride {
+                    exempt: true,
+                    limits: vec![],
+                },
+            );
+        });
+
+        let max = |principal, action| limit_for(&principal, action).map(|limit| limit.max);
+        assert_eq!(max(principal(), RateLimitedAction::CreateOffer), Some(1));
+        assert_eq!(max(trusted, RateLimitedAction::CreateOffer), Some(3));
+        assert_eq!(max(trusted, RateLimitedAction::SendMessage), Some(1));
+        assert_eq!(max(trusted, RateLimitedAction::CreateRequest), None);
+        assert_eq!(max(exempt, RateLimitedAction::SendMessage), None);
+    }
+
+    #[test]
+    fn rejects_empty_and_duplicate_limits() {
+        assert!(validate_limits(&RateLimitConfig::default().limits).is_ok());
+        assert!(validate_limits(&[limit(RateLimitedAction::CreateOffer, 0)]).is_err());
+        assert!(validate_limits(&[RateLimit {
+            window_ns: 0,
+            ..limit(RateLimitedAction::CreateOffer, 1)
+        }])
+        .is_err());
+        assert!(validate_limits(&[
+            limit(RateLimitedAction::CreateOffer, 1),
+            limit(RateLimitedAction::CreateOffer, 2),
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use candid::Principal;
+
+use crate::types::{CreateOfferRequest, EntityType, PlatformSettings, ProductType, PublicSettings};
+use crate::PLATFORM_SETTINGS;
+use crate::{audit, fees, validation};
+
+const HOUR_NS: u64 = 60 * 60 * 1_000_000_000;
+const MIN_REQUEST_TTL_NS: u64 = HOUR_NS;
+const MAX_REQUEST_TTL_NS: u64 = 90 * 24 * HOUR_NS;
+const MAX_PRODUCT_TYPES: usize = 32;
+const MAX_PRODUCT_TYPE_LEN: usize = 64;
+
+pub fn get() -> PlatformSettings {
+    PLATFORM_SETTINGS.with(|settings| settings.borrow().get().clone())
+}
+
+fn set(settings: PlatformSettings) {
+    PLATFORM_SETTINGS.with(|cell| {
+        cell.borrow_mut()
+            .set(settings)
+            .expect("failed to write platform settings");
+    });
+}
+
+/// Applies the settings passed at install time, if any.
+pub fn init(settings: Option<PlatformSettings>) {
+    if let Some(settings) = settings {
+        if let Err(error) = validate(&settings) {
+            ic_cdk::trap(&format!("Invalid init settings: {}", error));
+        }
+        set(settings);
+    }
+}
+
+pub fn public() -> PublicSettings {
+    let settings = get();
+    PublicSettings {
+        request_ttl_ns: settings.request_ttl_ns,
+        allowed_product_types: settings.allowed_product_types,
+        max_offer_quantity: settings.max_offer_quantity,
+        max_price_per_kg: settings.max_price_per_kg,
+        fee_schedule: settings.fee_schedule,
+    }
+}
+
+pub fn update(actor: Principal, settings: PlatformSettings) -> Result<PlatformSettings, String> {
+    validate(&settings)?;
+
+    let before = get();
+    set(settings.clone());
+    audit::record(
+        actor,
+        "update_platform_settings",
+        EntityType::Config,
+        "platform_settings",
+        Some(&before),
+        Some(&settings),
+    );
+
+    Ok(settings)
+}
+
+fn validate(settings: &PlatformSettings) -> Result<(), String> {
+    if !(MIN_REQUEST_TTL_NS..=MAX_REQUEST_TTL_NS).contains(&settings.request_ttl_ns) {
+        return Err("Request lifetime must be between 1 hour and 90 days".to_string());
+    }
+    if settings.allowed_product_types.is_empty() {
+        return Err("At least one product type must be allowed".to_string());
+    }
+    if settings.allowed_product_types.len() > MAX_PRODUCT_TYPES {
+        return Err(format!(
+            "At most {} product types can be listed",
+            MAX_PRODUCT_TYPES
+        ));
+    }
+    for product_type in &settings.allowed_product_types {
+        if let ProductType::Other(name) = product_type {
+            if name.chars().count() > MAX_PRODUCT_TYPE_LEN {
+                return Err(format!(
+                    "Product type names must be at most {} characters",
+                    MAX_PRODUCT_TYPE_LEN
+                ));
+            }
+        }
+    }
+    if settings.max_offer_quantity == 0 {
+        return Err("Maximum offer quantity must be positive".to_string());
+    }
+    if !settings.max_price_per_kg.is_finite() || settings.max_price_per_kg <= 0.0 {
+        return Err("Maximum price must be a positive number".to_string());
+    }
+    if settings.max_pending_requests_per_investor == 0 {
+        return Err("Maximum pending requests must be positive".to_string());
+    }
+    fees::validate_schedule(&settings.fee_schedule)
+}
+
+fn is_allowed(settings: &PlatformSettings, product_type: &ProductType) -> bool {
+    settings
+        .allowed_product_types
+        .iter()
+        .any(|allowed| match (allowed, product_type) {
+            // An empty name allows every custom product type
+            (ProductType::Other(allowed), ProductType::Other(name)) => {
+                allowed.is_empty() || allowed.eq_ignore_ascii_case(name.trim())
+            }
+            _ => allowed == product_type,
+        })
+}
+
+/// Checks a new offer against the configured product types and limits.
+pub fn check_offer(request: &CreateOfferRequest) -> Result<(), String> {
+    let settings = get();
+
+    if let ProductType::Other(name) = &request.product_type {
+        validation::validate_text("Product type", name, MAX_PRODUCT_TYPE_LEN)?;
+    }
+    if !is_allowed(&settings, &request.product_type) {
+        return Err("Product type is not accepted on the platform".to_string());
+    }
+    if request.total_quantity == 0 || request.total_quantity > settings.max_offer_quantity {
+        return Err(format!(
+            "Quantity must be between 1 and {} kg",
+            settings.max_offer_quantity
+        ));
+    }
+    if !request.price_per_kg.is_finite()
+        || request.price_per_kg <= 0.0
+        || request.price_per_kg > settings.max_price_per_kg
+    {
+        return Err(format!(
+            "Price per kg must be positive and at most {}",
+            settings.max_price_per_kg
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{FeeSchedule, ProductFeeRate, VolumeTier};
+
+    fn other(name: &str) -> ProductType {
+        ProductType::Other(name.to_string())
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        let defaults = PlatformSettings::default();
+        assert!(validate(&defaults).is_ok());
+
+        let invalid = [
+            PlatformSettings {
+                request_ttl_ns: MIN_REQUEST_TTL_NS - 1,
+                ..defaults.clone()
+            },
+            PlatformSettings {
+                request_ttl_ns: MAX_REQUEST_TTL_NS + 1,
+                ..defaults.clone()
+            },
+            PlatformSettings {
+                allowed_product_types: vec![],
+                ..defaults.clone()
+            },
+            PlatformSettings {
+                allowed_product_types: vec![ProductType::Grains; MAX_PRODUCT_TYPES + 1],
+                ..defaults.clone()
+            },
+            PlatformSettings {
+                allowed_product_types: vec![other(&"x".repeat(MAX_PRODUCT_TYPE_LEN + 1))],
+                ..defaults.clone()
+            },
+            PlatformSettings {
+                max_offer_quantity: 0,
+                ..defaults.clone()
+            },
+            PlatformSettings {
+                max_price_per_kg: f64::NAN,
+                ..defaults.clone()
+            },
+            PlatformSettings {
+                max_pending_requests_per_investor: 0,
+                ..defaults.clone()
+            },
+        ];
+        for settings in invalid {
+            assert!(validate(&settings).is_err(), "{:?}", settings);
+        }
+    }
+
+    #[test]
+    fn matches_custom_product_types_by_name() {
+        let settings = PlatformSettings {
+            allowed_product_types: vec![ProductType::Grains, other("Coffee")],
+            ..PlatformSettings::default()
+        };
+        assert!(is_allowed(&settings, &ProductType::Grains));
+        assert!(!is_allowed(&settings, &ProductType::Fruits));
+        assert!(is_allowed(&settings, &other(" coffee ")));
+        assert!(!is_allowed(&settings, &other("Cocoa")));
+
+        // The defaults allow every custom product type
+        assert!(is_allowed(&PlatformSettings::default(), &other("Cocoa")));
+    }
+
+    #[test]
+    fn stores_the_largest_settings() {
+        let name = || other(&"𝕏".repeat(MAX_PRODUCT_TYPE_LEN));
+        let settings = PlatformSettings {
+            allowed_product_types: vec![name(); MAX_PRODUCT_TYPES],
+            fee_schedule: FeeSchedule {
+                percentage_bps: 10_000,
+                flat_fee: f64::MAX,
+                volume_tiers: (0..16)
+                    .map(|tier| VolumeTier {
+                        min_amount: tier as f64,
+                        percentage_bps: 10_000,
+                    })
+                    .collect(),
+                product_rates: (0..16)
+                    .map(|rate| ProductFeeRate {
+                        product_type: other(&format!("{:𝕏<64}", rate)),
+                        percentage_bps: 10_000,
+                    })
+                    .collect(),
+            },
+            ..PlatformSettings::default()
+        };
+        assert!(validate(&settings).is_ok());
+
+        set(settings.clone());
+        assert_eq!(get().allowed_product_types, settings.allowed_product_types);
+        assert_eq!(get().fee_schedule.product_rates.len(), 16);
+    }
+}
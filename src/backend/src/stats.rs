@@ -0,0 +1,501 @@
+use crate::types::{
+    InvestmentOffer, InvestmentRequest, OfferStatus, PlatformCounters, ProductType, RequestStatus,
+    StatsBucket, TimeBucket, TradeVolume, Transaction, TransactionStatus, UserProfile,
+};
+use crate::{OFFERS, PLATFORM_COUNTERS, REQUESTS, STATS_SERIES, TRANSACTIONS, USERS};
+
+const MAX_SERIES_BUCKETS: u64 = 366;
+
+pub fn counters() -> PlatformCounters {
+    PLATFORM_COUNTERS.with(|counters| counters.borrow().get().clone())
+}
+
+fn update_counters(update: impl FnOnce(&mut PlatformCounters)) {
+    let mut counters = counters();
+    update(&mut counters);
+    PLATFORM_COUNTERS.with(|cell| {
+        cell.borrow_mut()
+            .set(counters)
+            .expect("failed to write platform counters");
+    });
+}
+
+fn update_day(timestamp: u64, update: impl FnOnce(&mut StatsBucket)) {
+    let duration = TimeBucket::Day.duration_ns();
+    let start = timestamp - timestamp % duration;
+    STATS_SERIES.with(|series| {
+        let mut series = series.borrow_mut();
+        let mut bucket = series
+            .get(&start)
+            .unwrap_or_else(|| empty_bucket(start, duration));
+        update(&mut bucket);
+        series.insert(start, bucket);
+    });
+}
+
+fn empty_bucket(start: u64, duration: u64) -> StatsBucket {
+    StatsBucket {
+        start,
+        end: start + duration,
+        new_users: Vec::new(),
+        new_offers: Vec::new(),
+        new_requests: 0,
+        resolved_requests: Vec::new(),
+        accepted_volume: Vec::new(),
+    }
+}
+
+fn add<T: PartialEq>(counts: &mut Vec<(T, u64)>, key: T, amount: u64) {
+    match counts.iter_mut().find(|(existing, _)| *existing == key) {
+        Some((_, count)) => *count += amount,
+        None => counts.push((key, amount)),
+    }
+}
+
+fn subtract<T: PartialEq>(counts: &mut [(T, u64)], key: &T) {
+    if let Some((_, count)) = counts.iter_mut().find(|(existing, _)| existing == key) {
+        *count = count.saturating_sub(1);
+    }
+}
+
+fn add_volume(
+    volumes: &mut Vec<(ProductType, TradeVolume)>,
+    product_type: ProductType,
+    txn: &Transaction,
+) {
+    let volume = match volumes
+        .iter()
+        .position(|(existing, _)| *existing == product_type)
+    {
+        Some(position) => &mut volumes[position].1,
+        None => {
+            volumes.push((product_type, TradeVolume::default()));
+            &mut volumes.last_mut().unwrap().1
+        }
+    };
+    volume.trade_count += 1;
+    volume.quantity += txn.quantity;
+    volume.value += txn.total_amount;
+}
+
+fn subtract_volume(
+    volumes: &mut [(ProductType, TradeVolume)],
+    product_type: &ProductType,
+    txn: &Transaction,
+) {
+    if let Some((_, volume)) = volumes
+        .iter_mut()
+        .find(|(existing, _)| existing == product_type)
+    {
+        volume.trade_count = volume.trade_count.saturating_sub(1);
+        volume.quantity = volume.quantity.saturating_sub(txn.quantity);
+        volume.value -= txn.total_amount;
+    }
+}
+
+// Custom product types are free text, so they share one entry to keep
+// counters and buckets within their storable bounds
+fn product_key(product_type: &ProductType) -> ProductType {
+    match product_type {
+        ProductType::Other(_) => ProductType::Other(String::new()),
+        product_type => product_type.clone(),
+    }
+}
+
+fn product_key_for(transaction: &Transaction) -> Option<ProductType> {
+    OFFERS
+        .with(|offers| offers.borrow().get(&transaction.offer_id))
+        .map(|offer| product_key(&offer.product_type))
+}
+
+fn is_resolved(status: &RequestStatus) -> bool {
+    !matches!(status, RequestStatus::Pending)
+}
+
+/// Counts a new account or a change of role.
+pub fn on_user(before: Option<&UserProfile>, after: &UserProfile) {
+    match before {
+        None => {
+            update_counters(|counters| add(&mut counters.users_by_role, after.role.clone(), 1));
+            update_day(after.created_at, |bucket| {
+                add(&mut bucket.new_users, after.role.clone(), 1)
+            });
+        }
+        Some(before) if before.role != after.role => update_counters(|counters| {
+            subtract(&mut counters.users_by_role, &before.role);
+            add(&mut counters.users_by_role, after.role.clone(), 1);
+        }),
+        Some(_) => {}
+    }
+}
+
+/// Counts a new offer or a change of status.
+pub fn on_offer(before: Option<&InvestmentOffer>, after: &InvestmentOffer) {
+    match before {
+        None => {
+            let product_type = product_key(&after.product_type);
+            update_counters(|counters| {
+                add(&mut counters.offers_by_status, after.status.clone(), 1);
+                add(
+                    &mut counters.offers_by_product_type,
+                    product_type.clone(),
+                    1,
+                );
+            });
+            update_day(after.created_at, |bucket| {
+                add(&mut bucket.new_offers, product_type, 1)
+            });
+        }
+        Some(before) if before.status != after.status => update_counters(|counters| {
+            subtract(&mut counters.offers_by_status, &before.status);
+            add(&mut counters.offers_by_status, after.status.clone(), 1);
+        }),
+        Some(_) => {}
+    }
+}
+
+/// Counts a new request or a change of status.
+pub fn on_request(before: Option<&InvestmentRequest>, after: &InvestmentRequest) {
+    match before {
+        None => {
+            update_counters(|counters| {
+                add(&mut counters.requests_by_status, after.status.clone(), 1)
+            });
+            update_day(after.created_at, |bucket| bucket.new_requests += 1);
+        }
+        Some(before) if before.status != after.status => {
+            update_counters(|counters| {
+                subtract(&mut counters.requests_by_status, &before.status);
+                add(&mut counters.requests_by_status, after.status.clone(), 1);
+            });
+            if is_resolved(&after.status) {
+                update_day(after.updated_at, |bucket| {
+                    add(&mut bucket.resolved_requests, after.status.clone(), 1)
+                });
+            }
+        }
+        Some(_) => {}
+    }
+}
+
+/// Counts a newly accepted trade or a change of status. Refunds are
+/// deducted from the traded volume but not from the day it was accepted.
+pub fn on_transaction(before: Option<&Transaction>, after: &Transaction) {
+    let product_type = product_key_for(after);
+    match before {
+        None => {
+            update_counters(|counters| {
+                add(
+                    &mut counters.transactions_by_status,
+                    after.status.clone(),
+                    1,
+                );
+                if let Some(product_type) = &product_type {
+                    add_volume(&mut counters.traded_volume, product_type.clone(), after);
+                }
+            });
+            if let Some(product_type) = product_type {
+                update_day(after.created_at, |bucket| {
+                    add_volume(&mut bucket.accepted_volume, product_type, after)
+                });
+            }
+        }
+        Some(before) if before.status != after.status => update_counters(|counters| {
+            subtract(&mut counters.transactions_by_status, &before.status);
+            add(
+                &mut counters.transactions_by_status,
+                after.status.clone(),
+                1,
+            );
+            if after.status == TransactionStatus::Refunded {
+                if let Some(product_type) = &product_type {
+                    subtract_volume(&mut counters.traded_volume, product_type, after);
+                }
+            }
+        }),
+        Some(_) => {}
+    }
+}
+
+/// Builds the counters and daily series from the records held by the
+/// backend, once. Records archived before then are not included.
+pub fn seed() {
+    if counters().counting_since != 0 {
+        return;
+    }
+
+    let users = USERS.with(|users| {
+        users
+            .borrow()
+            .iter()
+            .map(|(_, user)| user)
+            .collect::<Vec<_>>()
+    });
+    for user in &users {
+        on_user(None, user);
+    }
+    let offers = OFFERS.with(|offers| {
+        offers
+            .borrow()
+            .iter()
+            .map(|(_, offer)| offer)
+            .collect::<Vec<_>>()
+    });
+    for offer in &offers {
+        on_offer(None, offer);
+    }
+    let requests = REQUESTS.with(|requests| {
+        requests
+            .borrow()
+            .iter()
+            .map(|(_, req)| req)
+            .collect::<Vec<_>>()
+    });
+    for req in &requests {
+        if is_resolved(&req.status) {
+            // Counted as created pending, then resolved when last updated
+            let pending = InvestmentRequest {
+                status: RequestStatus::Pending,
+                ..req.clone()
+            };
+            on_request(None, &pending);
+            on_request(Some(&pending), req);
+        } else {
+            on_request(None, req);
+        }
+    }
+    let transactions = TRANSACTIONS.with(|transactions| {
+        transactions
+            .borrow()
+            .iter()
+            .map(|(_, txn)| txn)
+            .collect::<Vec<_>>()
+    });
+    for txn in &transactions {
+        if txn.status == TransactionStatus::Refunded {
+            let accepted = Transaction {
+                status: TransactionStatus::Confirmed,
+                ..txn.clone()
+            };
+            on_transaction(None, &accepted);
+            on_transaction(Some(&accepted), txn);
+        } else {
+            on_transaction(None, txn);
+        }
+    }
+
+    update_counters(|counters| counters.counting_since = ic_cdk::api::time());
+}
+
+pub fn active_offers() -> u64 {
+    counters()
+        .offers_by_status
+        .iter()
+        .find(|(status, _)| *status == OfferStatus::Active)
+        .map_or(0, |(_, count)| *count)
+}
+
+fn merge(into: &mut StatsBucket, day: StatsBucket) {
+    for (role, count) in day.new_users {
+        add(&mut into.new_users, role, count);
+    }
+    for (product_type, count) in day.new_offers {
+        add(&mut into.new_offers, product_type, count);
+    }
+    into.new_requests += day.new_requests;
+    for (status, count) in day.resolved_requests {
+        add(&mut into.resolved_requests, status, count);
+    }
+    for (product_type, volume) in day.accepted_volume {
+        match into
+            .accepted_volume
+            .iter_mut()
+            .find(|(existing, _)| *existing == product_type)
+        {
+            Some((_, total)) => {
+                total.trade_count += volume.trade_count;
+                total.quantity += volume.quantity;
+                total.value += volume.value;
+            }
+            None => into.accepted_volume.push((product_type, volume)),
+        }
+    }
+}
+
+/// Activity in the buckets overlapping `[from, to)`. Buckets without
+/// activity are left out.
+pub fn series(from: u64, to: u64, bucket: TimeBucket) -> Result<Vec<StatsBucket>, String> {
+    let duration = bucket.duration_ns();
+    if to <= from {
+        return Err("Period end must be after its start".to_string());
+    }
+    let first = from - from % duration;
+    if (to - first).div_ceil(duration) > MAX_SERIES_BUCKETS {
+        return Err(format!(
+            "At most {} buckets can be requested at once",
+            MAX_SERIES_BUCKETS
+        ));
+    }
+
+    let mut buckets: Vec<StatsBucket> = Vec::new();
+    STATS_SERIES.with(|series| {
+        for (day_start, day) in series.borrow().range(first..to) {
+            let start = day_start - day_start % duration;
+            match buckets.iter_mut().find(|bucket| bucket.start == start) {
+                Some(bucket) => merge(bucket, day),
+                None => {
+                    let mut bucket = empty_bucket(start, duration);
+                    merge(&mut bucket, day);
+                    buckets.push(bucket);
+                }
+            }
+        }
+    });
+    Ok(buckets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+
+    use crate::types::QualityGrade;
+
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    fn offer(id: &str, product_type: ProductType, created_at: u64) -> InvestmentOffer {
+        InvestmentOffer {
+            id: id.to_string(),
+            farmer: Principal::from_slice(&[1]),
+            product_name: "Maize".to_string(),
+            product_type,
+            total_quantity: 100,
+            available_quantity: 100,
+            price_per_kg: 2.0,
+            description: String::new(),
+            harvest_date: "2024-01-01".to_string(),
+            location: "Nakuru".to_string(),
+            quality_grade: QualityGrade::Standard,
+            minimum_investment: 1,
+            organization: None,
+            status: OfferStatus::Active,
+            created_at,
+            updated_at: created_at,
+        }
+    }
+
+    fn transaction(offer_id: &str, created_at: u64) -> Transaction {
+        Transaction {
+            id: "txn_1".to_string(),
+            offer_id: offer_id.to_string(),
+            request_id: "req_1".to_string(),
+            farmer: Principal::from_slice(&[1]),
+            investor: Principal::from_slice(&[2]),
+            quantity: 10,
+            price_per_kg: 2.0,
+            total_amount: 20.0,
+            status: TransactionStatus::Confirmed,
+            created_at,
+            updated_at: created_at,
+            tokenized_at: None,
+            organization: None,
+            fees: None,
+            settled_at: None,
+        }
+    }
+
+    fn count<T: PartialEq>(counts: &[(T, u64)], key: &T) -> u64 {
+        counts
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    #[test]
+    fn custom_product_types_share_one_entry() {
+        let other = |name: &str| ProductType::Other(name.to_string());
+        on_offer(None, &offer("offer_1", other("Coffee"), 0));
+        on_offer(None, &offer("offer_2", other("Cocoa"), DAY));
+        on_offer(None, &offer("offer_3", ProductType::Nuts, DAY));
+
+        let counters = counters();
+        assert_eq!(counters.offers_by_product_type.len(), 2);
+        assert_eq!(count(&counters.offers_by_product_type, &other("")), 2);
+        assert_eq!(active_offers(), 3);
+    }
+
+    #[test]
+    fn status_changes_move_counts() {
+        let before = offer("offer_1", ProductType::Grains, 0);
+        on_offer(None, &before);
+        let after = InvestmentOffer {
+            status: OfferStatus::Completed,
+            ..before.clone()
+        };
+        on_offer(Some(&before), &after);
+        // Unchanged statuses are not counted again
+        on_offer(Some(&after), &after);
+
+        let counters = counters();
+        assert_eq!(count(&counters.offers_by_status, &OfferStatus::Active), 0);
+        assert_eq!(
+            count(&counters.offers_by_status, &OfferStatus::Completed),
+            1
+        );
+    }
+
+    #[test]
+    fn refunds_leave_the_day_of_acceptance_alone() {
+        OFFERS.with(|offers| {
+            offers.borrow_mut().insert(
+                "offer_1".to_string(),
+                offer("offer_1", ProductType::Fruits, 0),
+            )
+        });
+        let accepted = transaction("offer_1", DAY + 1);
+        on_transaction(None, &accepted);
+        on_transaction(
+            Some(&accepted),
+            &Transaction {
+                status: TransactionStatus::Refunded,
+                ..accepted.clone()
+            },
+        );
+
+        let traded = &counters().traded_volume[0].1;
+        assert_eq!(
+            (traded.trade_count, traded.quantity, traded.value),
+            (0, 0, 0.0)
+        );
+
+        let day = STATS_SERIES
+            .with(|series| series.borrow().get(&DAY))
+            .unwrap();
+        let accepted = &day.accepted_volume[0].1;
+        assert_eq!((accepted.trade_count, accepted.value), (1, 20.0));
+    }
+
+    #[test]
+    fn series_merges_days_into_weeks() {
+        on_offer(None, &offer("offer_1", ProductType::Herbs, DAY));
+        on_offer(None, &offer("offer_2", ProductType::Herbs, 3 * DAY + 5));
+        on_offer(None, &offer("offer_3", ProductType::Nuts, 8 * DAY));
+
+        let weeks = series(DAY, 14 * DAY, TimeBucket::Week).unwrap();
+        assert_eq!(weeks.len(), 2);
+        assert_eq!((weeks[0].start, weeks[0].end), (0, 7 * DAY));
+        assert_eq!(count(&weeks[0].new_offers, &ProductType::Herbs), 2);
+        assert_eq!(count(&weeks[1].new_offers, &ProductType::Nuts), 1);
+
+        let days = series(2 * DAY, 3 * DAY + 1, TimeBucket::Day).unwrap();
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].start, 3 * DAY);
+    }
+
+    #[test]
+    fn rejects_empty_and_oversized_periods() {
+        assert!(series(DAY, DAY, TimeBucket::Day).is_err());
+        assert!(series(0, MAX_SERIES_BUCKETS * DAY, TimeBucket::Day).is_ok());
+        assert!(series(0, MAX_SERIES_BUCKETS * DAY + 1, TimeBucket::Day).is_err());
+    }
+}
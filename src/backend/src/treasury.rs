@@ -0,0 +1,563 @@
+use candid::{Nat, Principal};
+
+use crate::types::{
+    AccountReconciliation, EntityType, Icrc1Account, ReconciliationReport, TimeBucket, Transaction,
+    TransactionStatus, TreasuryAccount, TreasuryBalance, TreasuryConfig, TreasuryEntry,
+    TreasuryEntryKind, TreasuryPeriodSummary,
+};
+use crate::{audit, fees, inbox, validation};
+use crate::{OFFERS, TRANSACTIONS, TREASURY_CONFIG, TREASURY_LEDGER};
+
+const ACCOUNTS: [TreasuryAccount; 3] = [
+    TreasuryAccount::Escrow,
+    TreasuryAccount::Fees,
+    TreasuryAccount::Payouts,
+];
+
+const MAX_CURRENCY_LEN: usize = 16;
+const MAX_DECIMALS: u8 = 18;
+const MAX_REFERENCE_LEN: usize = 128;
+const MAX_SUMMARY_BUCKETS: u64 = 366;
+
+pub fn get_config() -> TreasuryConfig {
+    TREASURY_CONFIG.with(|config| config.borrow().get().clone())
+}
+
+fn set_config(config: TreasuryConfig) {
+    TREASURY_CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .expect("failed to write treasury config");
+    });
+}
+
+/// Trades accepted from now on are booked in the new currency; earlier
+/// ones keep theirs, so balances are reported per currency.
+pub fn configure(actor: Principal, config: TreasuryConfig) -> Result<TreasuryConfig, String> {
+    let currency = validation::validate_text("Currency", &config.currency, MAX_CURRENCY_LEN)?;
+    if config.decimals > MAX_DECIMALS {
+        return Err(format!("Decimals must be at most {}", MAX_DECIMALS));
+    }
+    let config = TreasuryConfig { currency, ..config };
+
+    let before = get_config();
+    set_config(config.clone());
+    audit::record(
+        actor,
+        "configure_treasury",
+        EntityType::Config,
+        "treasury",
+        Some(&before),
+        Some(&config),
+    );
+
+    Ok(config)
+}
+
+/// The canister subaccount holding `account` on the ledger.
+pub fn subaccount(account: TreasuryAccount) -> Vec<u8> {
+    let mut subaccount = vec![0; 32];
+    subaccount[31] = match account {
+        TreasuryAccount::Escrow => 1,
+        TreasuryAccount::Fees => 2,
+        TreasuryAccount::Payouts => 3,
+    };
+    subaccount
+}
+
+/// Currency of the transaction's escrow deposit. Everything booked for a
+/// trade stays in that currency, so that its escrow drains even after the
+/// configured currency changes.
+fn currency_of(transaction: &Transaction) -> String {
+    TREASURY_LEDGER
+        .with(|ledger| {
+            ledger
+                .borrow()
+                .iter()
+                .find(|(_, entry)| entry.transaction_id == transaction.id)
+                .map(|(_, entry)| entry.currency)
+        })
+        .unwrap_or_else(|| get_config().currency)
+}
+
+fn book(
+    actor: Principal,
+    kind: TreasuryEntryKind,
+    from: Option<TreasuryAccount>,
+    to: Option<TreasuryAccount>,
+    transaction: &Transaction,
+    amount: f64,
+    reference: Option<String>,
+) -> Option<TreasuryEntry> {
+    if amount <= 0.0 {
+        return None;
+    }
+
+    let product_type = OFFERS
+        .with(|offers| offers.borrow().get(&transaction.offer_id))
+        .map(|offer| offer.product_type);
+    let currency = currency_of(transaction);
+    let entry = TREASURY_LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        let entry = TreasuryEntry {
+            index: ledger.last_key_value().map_or(0, |(index, _)| index + 1),
+            timestamp: ic_cdk::api::time(),
+            kind,
+            currency,
+            from,
+            to,
+            transaction_id: transaction.id.clone(),
+            product_type,
+            amount,
+            reference,
+        };
+        ledger.insert(entry.index, entry.clone());
+        entry
+    });
+    audit::record(
+        actor,
+        "record_treasury_entry",
+        EntityType::Transaction,
+        &transaction.id,
+        None,
+        Some(&entry),
+    );
+
+    Some(entry)
+}
+
+fn net_proceeds(transaction: &Transaction) -> f64 {
+    transaction.total_amount - fees::total(transaction.fee_lines())
+}
+
+/// Moves the value of a newly accepted trade into escrow.
+pub fn on_accepted(actor: Principal, transaction: &Transaction) {
+    book(
+        actor,
+        TreasuryEntryKind::EscrowDeposit,
+        None,
+        Some(TreasuryAccount::Escrow),
+        transaction,
+        transaction.total_amount,
+        None,
+    );
+}
+
+/// Releases a settling trade from escrow: fees to the treasury, the rest
+/// to the farmer's pending payout.
+pub fn on_settled(actor: Principal, transaction: &Transaction) {
+    book(
+        actor,
+        TreasuryEntryKind::FeeIncome,
+        Some(TreasuryAccount::Escrow),
+        Some(TreasuryAccount::Fees),
+        transaction,
+        fees::total(transaction.fee_lines()),
+        None,
+    );
+    book(
+        actor,
+        TreasuryEntryKind::PayoutScheduled,
+        Some(TreasuryAccount::Escrow),
+        Some(TreasuryAccount::Payouts),
+        transaction,
+        net_proceeds(transaction),
+        None,
+    );
+}
+
+/// Returns a refunded trade's funds to the investor from wherever they
+/// are held. Settled trades must not have been paid out yet.
+pub fn on_refunded(actor: Principal, transaction: &Transaction, was_settled: bool) {
+    if !was_settled {
+        book(
+            actor,
+            TreasuryEntryKind::Refund,
+            Some(TreasuryAccount::Escrow),
+            None,
+            transaction,
+            transaction.total_amount,
+            None,
+        );
+        return;
+    }
+
+    book(
+        actor,
+        TreasuryEntryKind::FeeRefund,
+        Some(TreasuryAccount::Fees),
+        None,
+        transaction,
+        fees::total(transaction.fee_lines()),
+        None,
+    );
+    book(
+        actor,
+        TreasuryEntryKind::Refund,
+        Some(TreasuryAccount::Payouts),
+        None,
+        transaction,
+        net_proceeds(transaction),
+        None,
+    );
+}
+
+pub fn is_paid_out(transaction_id: &str) -> bool {
+    TREASURY_LEDGER.with(|ledger| {
+        ledger.borrow().iter().any(|(_, entry)| {
+            entry.kind == TreasuryEntryKind::Payout && entry.transaction_id == transaction_id
+        })
+    })
+}
+
+/// Records that a settled trade's proceeds were paid out to the farmer,
+/// with an optional reference such as the ledger block index.
+pub fn record_payout(
+    actor: Principal,
+    transaction_id: &str,
+    reference: Option<String>,
+) -> Result<TreasuryEntry, String> {
+    let reference = match reference {
+        Some(reference) => Some(validation::validate_text(
+            "Reference",
+            &reference,
+            MAX_REFERENCE_LEN,
+        )?),
+        None => None,
+    };
+
+    let transaction = TRANSACTIONS
+        .with(|transactions| transactions.borrow().get(&transaction_id.to_string()))
+        .ok_or("Transaction not found")?;
+    if !matches!(transaction.status, TransactionStatus::Completed) {
+        return Err("Only settled transactions can be paid out".to_string());
+    }
+    if is_paid_out(transaction_id) {
+        return Err("Transaction has already been paid out".to_string());
+    }
+
+    let entry = book(
+        actor,
+        TreasuryEntryKind::Payout,
+        Some(TreasuryAccount::Payouts),
+        None,
+        &transaction,
+        net_proceeds(&transaction),
+        reference,
+    )
+    .ok_or("Nothing to pay out")?;
+    inbox::on_paid_out(&transaction);
+
+    Ok(entry)
+}
+
+fn adjust(
+    balances: &mut Vec<TreasuryBalance>,
+    currency: &str,
+    account: TreasuryAccount,
+    amount: f64,
+) {
+    match balances
+        .iter_mut()
+        .find(|balance| balance.currency == currency && balance.account == account)
+    {
+        Some(balance) => balance.balance += amount,
+        None => balances.push(TreasuryBalance {
+            currency: currency.to_string(),
+            account,
+            balance: amount,
+        }),
+    }
+}
+
+/// Current balance of every internal account, per currency.
+pub fn balances() -> Vec<TreasuryBalance> {
+    let mut balances = Vec::new();
+    TREASURY_LEDGER.with(|ledger| {
+        for (_, entry) in ledger.borrow().iter() {
+            if let Some(from) = entry.from {
+                adjust(&mut balances, &entry.currency, from, -entry.amount);
+            }
+            if let Some(to) = entry.to {
+                adjust(&mut balances, &entry.currency, to, entry.amount);
+            }
+        }
+    });
+    balances
+}
+
+/// Ledger activity in `[from, to)` grouped into day or week buckets.
+/// Buckets without activity are left out.
+pub fn summaries(
+    from: u64,
+    to: u64,
+    bucket: TimeBucket,
+) -> Result<Vec<TreasuryPeriodSummary>, String> {
+    let duration = bucket.duration_ns();
+    let first = from - from % duration;
+    if to <= from {
+        return Err("Period end must be after its start".to_string());
+    }
+    if (to - first).div_ceil(duration) > MAX_SUMMARY_BUCKETS {
+        return Err(format!(
+            "At most {} buckets can be requested at once",
+            MAX_SUMMARY_BUCKETS
+        ));
+    }
+
+    let mut summaries: Vec<TreasuryPeriodSummary> = Vec::new();
+    TREASURY_LEDGER.with(|ledger| {
+        for (_, entry) in ledger.borrow().iter() {
+            if entry.timestamp < from || entry.timestamp >= to {
+                continue;
+            }
+            let start = entry.timestamp - entry.timestamp % duration;
+            let position = match summaries
+                .iter()
+                .position(|summary| summary.start == start && summary.currency == entry.currency)
+            {
+                Some(position) => position,
+                None => {
+                    summaries.push(TreasuryPeriodSummary {
+                        start,
+                        end: start + duration,
+                        currency: entry.currency.clone(),
+                        escrow_deposits: 0.0,
+                        fee_income: 0.0,
+                        payouts_scheduled: 0.0,
+                        payouts: 0.0,
+                        fee_refunds: 0.0,
+                        refunds: 0.0,
+                    });
+                    summaries.len() - 1
+                }
+            };
+
+            let summary = &mut summaries[position];
+            match entry.kind {
+                TreasuryEntryKind::EscrowDeposit => summary.escrow_deposits += entry.amount,
+                TreasuryEntryKind::FeeIncome => summary.fee_income += entry.amount,
+                TreasuryEntryKind::PayoutScheduled => summary.payouts_scheduled += entry.amount,
+                TreasuryEntryKind::Payout => summary.payouts += entry.amount,
+                TreasuryEntryKind::FeeRefund => summary.fee_refunds += entry.amount,
+                TreasuryEntryKind::Refund => summary.refunds += entry.amount,
+            }
+        }
+    });
+
+    summaries.sort_by(|a, b| a.start.cmp(&b.start).then(a.currency.cmp(&b.currency)));
+    Ok(summaries)
+}
+
+fn to_units(value: &Nat, decimals: u8) -> f64 {
+    let raw = u128::try_from(&value.0).map_or(f64::MAX, |raw| raw as f64);
+    raw / 10f64.powi(decimals as i32)
+}
+
+/// Compares the internal balances in the configured currency with what the
+/// ledger reports for the canister's subaccounts.
+pub async fn reconcile() -> Result<ReconciliationReport, String> {
+    let config = get_config();
+    let ledger = config.ledger.ok_or("No treasury ledger configured")?;
+    let internal = balances();
+    // Differences below one ledger unit are rounding
+    let tolerance = 1.0 / 10f64.powi(config.decimals as i32);
+
+    let mut accounts = Vec::new();
+    for account in ACCOUNTS {
+        let subaccount = subaccount(account);
+        let owner = Icrc1Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(subaccount.clone()),
+        };
+        let result: ic_cdk::api::call::CallResult<(Nat,)> =
+            ic_cdk::call(ledger, "icrc1_balance_of", (owner,)).await;
+        let ledger_balance = match result {
+            Ok((balance,)) => to_units(&balance, config.decimals),
+            Err((code, message)) => {
+                return Err(format!("Failed to reach ledger: {:?} {}", code, message))
+            }
+        };
+
+        let internal_balance = internal
+            .iter()
+            .find(|balance| balance.currency == config.currency && balance.account == account)
+            .map_or(0.0, |balance| balance.balance);
+        accounts.push(AccountReconciliation {
+            account,
+            subaccount,
+            internal_balance,
+            ledger_balance,
+            difference: ledger_balance - internal_balance,
+        });
+    }
+
+    Ok(ReconciliationReport {
+        currency: config.currency,
+        ledger,
+        checked_at: ic_cdk::api::time(),
+        balanced: accounts
+            .iter()
+            .all(|account| account.difference.abs() < tolerance),
+        accounts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    // Appends an entry the way `book` does, at `timestamp`
+    fn entry(
+        timestamp: u64,
+        kind: TreasuryEntryKind,
+        currency: &str,
+        from: Option<TreasuryAccount>,
+        to: Option<TreasuryAccount>,
+        amount: f64,
+    ) {
+        TREASURY_LEDGER.with(|ledger| {
+            let mut ledger = ledger.borrow_mut();
+            let index = ledger.last_key_value().map_or(0, |(index, _)| index + 1);
+            ledger.insert(
+                index,
+                TreasuryEntry {
+                    index,
+                    timestamp,
+                    kind,
+                    currency: currency.to_string(),
+                    from,
+                    to,
+                    transaction_id: "txn_1".to_string(),
+                    product_type: None,
+                    amount,
+                    reference: None,
+                },
+            );
+        });
+    }
+
+    // A trade of 100 with 3 in fees, accepted, settled and paid out
+    fn settled_trade(timestamp: u64, currency: &str) {
+        use TreasuryAccount::*;
+        use TreasuryEntryKind::*;
+        entry(
+            timestamp,
+            EscrowDeposit,
+            currency,
+            None,
+            Some(Escrow),
+            100.0,
+        );
+        entry(
+            timestamp,
+            FeeIncome,
+            currency,
+            Some(Escrow),
+            Some(Fees),
+            3.0,
+        );
+        entry(
+            timestamp,
+            PayoutScheduled,
+            currency,
+            Some(Escrow),
+            Some(Payouts),
+            97.0,
+        );
+        entry(timestamp + DAY, Payout, currency, Some(Payouts), None, 97.0);
+    }
+
+    fn balance(balances: &[TreasuryBalance], currency: &str, account: TreasuryAccount) -> f64 {
+        balances
+            .iter()
+            .find(|balance| balance.currency == currency && balance.account == account)
+            .map_or(f64::NAN, |balance| balance.balance)
+    }
+
+    #[test]
+    fn balances_follow_the_trade_lifecycle_per_currency() {
+        settled_trade(0, "ckUSDC");
+        entry(
+            0,
+            TreasuryEntryKind::EscrowDeposit,
+            "ckBTC",
+            None,
+            Some(TreasuryAccount::Escrow),
+            0.5,
+        );
+
+        let balances = balances();
+        assert_eq!(balance(&balances, "ckUSDC", TreasuryAccount::Escrow), 0.0);
+        assert_eq!(balance(&balances, "ckUSDC", TreasuryAccount::Fees), 3.0);
+        assert_eq!(balance(&balances, "ckUSDC", TreasuryAccount::Payouts), 0.0);
+        assert_eq!(balance(&balances, "ckBTC", TreasuryAccount::Escrow), 0.5);
+        assert_eq!(balances.len(), 4);
+    }
+
+    #[test]
+    fn refunds_drain_the_accounts_holding_the_funds() {
+        use TreasuryAccount::*;
+        use TreasuryEntryKind::*;
+        entry(0, EscrowDeposit, "ckUSDC", None, Some(Escrow), 100.0);
+        entry(0, FeeIncome, "ckUSDC", Some(Escrow), Some(Fees), 3.0);
+        entry(
+            0,
+            PayoutScheduled,
+            "ckUSDC",
+            Some(Escrow),
+            Some(Payouts),
+            97.0,
+        );
+        entry(0, FeeRefund, "ckUSDC", Some(Fees), None, 3.0);
+        entry(0, Refund, "ckUSDC", Some(Payouts), None, 97.0);
+
+        assert!(balances().iter().all(|balance| balance.balance == 0.0));
+    }
+
+    #[test]
+    fn summaries_group_entries_by_bucket_and_currency() {
+        settled_trade(DAY + 5, "ckUSDC");
+        settled_trade(DAY + 6, "ckBTC");
+        settled_trade(10 * DAY, "ckUSDC");
+
+        let days = summaries(DAY, 3 * DAY, TimeBucket::Day).unwrap();
+        let buckets = days
+            .iter()
+            .map(|summary| (summary.start, summary.currency.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            buckets,
+            [
+                (DAY, "ckBTC"),
+                (DAY, "ckUSDC"),
+                (2 * DAY, "ckBTC"),
+                (2 * DAY, "ckUSDC")
+            ]
+        );
+        let first = &days[1];
+        assert_eq!(first.end, 2 * DAY);
+        assert_eq!(first.escrow_deposits, 100.0);
+        assert_eq!(first.fee_income, 3.0);
+        assert_eq!(first.payouts_scheduled, 97.0);
+        assert_eq!(first.payouts, 0.0);
+        assert_eq!(days[3].payouts, 97.0);
+
+        // The end is exclusive and buckets start at the period's bucket
+        let week = summaries(DAY, 7 * DAY, TimeBucket::Week).unwrap();
+        assert_eq!(week.len(), 2);
+        assert_eq!(week[0].start, 0);
+    }
+
+    #[test]
+    fn rejects_empty_and_oversized_periods() {
+        assert!(summaries(DAY, DAY, TimeBucket::Day).is_err());
+        assert!(summaries(0, MAX_SUMMARY_BUCKETS * DAY, TimeBucket::Day).is_ok());
+        assert!(summaries(0, MAX_SUMMARY_BUCKETS * DAY + 1, TimeBucket::Day).is_err());
+        // Counted from the start of the first bucket
+        let noon = DAY / 2;
+        assert!(summaries(noon, MAX_SUMMARY_BUCKETS * DAY + noon, TimeBucket::Day).is_err());
+    }
+}
@@ -1,16 +1,25 @@
-use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use candid::{CandidType, Decode, Deserialize, Encode, Int, Nat, Principal};
 use ic_stable_structures::storable::{Bound, Storable}; // <-- Remove BoundedStorable
 use serde::Serialize;
 
 macro_rules! impl_storable {
-    ($t:ty, $max_size:expr) => {
-        impl Storable for $t {
-            const BOUND: Bound = Bound::Bounded {
+    ($t:ty) => {
+        impl_storable!($t, Bound::Unbounded);
+    };
+    ($t:ty, $max_size:literal) => {
+        impl_storable!(
+            $t,
+            Bound::Bounded {
                 max_size: $max_size,
                 is_fixed_size: false,
-            };
+            }
+        );
+    };
+    ($t:ty, $bound:expr) => {
+        impl Storable for $t {
+            const BOUND: Bound = $bound;
 
-            fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+            fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
                 std::borrow::Cow::Owned(candid::Encode!(self).unwrap())
             }
 
@@ -21,12 +30,89 @@ macro_rules! impl_storable {
     };
 }
 // User Management
-#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
 pub enum UserRole {
     Admin,
     Farmer,
     Investor,
     Guest,
+    /// Reviews identity verification submissions.
+    Verifier,
+}
+
+// Permissions
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, CandidType, Serialize, Deserialize,
+)]
+pub enum Permission {
+    CreateOffer,
+    CreateInvestmentRequest,
+    ReviewKyc,
+    ViewUsers,
+    /// Change users' roles and define custom roles.
+    ManageRoles,
+    ExportUserData,
+    ViewAuditLog,
+    ManageArchive,
+    ManageNotifier,
+    ManageKycPolicy,
+    /// Force-cancel offers and requests and refund transactions.
+    ManageTrades,
+    ManageRateLimits,
+    ManageSettings,
+    /// Pause and resume features; not subject to multi-signature approval.
+    PausePlatform,
+    ViewRevenue,
+    /// Record payouts and configure the treasury ledger.
+    ManageTreasury,
+    /// View cycles consumption and configure low-cycles alerts.
+    ManageCycles,
+    /// Propose and approve multi-signature admin actions.
+    ApproveAdminActions,
+}
+
+/// A named set of permissions. Built-in roles correspond to `UserRole`
+/// and cannot be changed; custom roles are defined by admins and granted
+/// on top of a user's built-in role.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct RoleDefinition {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<Permission>,
+    pub built_in: bool,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct DefineRoleRequest {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<Permission>,
+}
+
+/// Custom roles granted to a user.
+#[derive(Debug, Clone, Default, CandidType, Serialize, Deserialize)]
+pub struct RoleGrants {
+    pub roles: Vec<String>,
+    /// Who granted the built-in `Admin` role, when the user holds it by
+    /// grant. Admins without it do not count as multi-signature approvers.
+    pub admin_granted_by: Option<Principal>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct EffectivePermissions {
+    pub principal: Principal,
+    pub role: UserRole,
+    pub custom_roles: Vec<String>,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum AccountStatus {
+    Active,
+    /// Self-deactivated; history is kept but no new offers or requests.
+    Deactivated,
+    /// Personal data erased on the user's request; financial records remain.
+    Erased,
 }
 
 #[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
@@ -35,10 +121,663 @@ pub struct UserProfile {
     pub role: UserRole,
     pub display_name: String,
     pub email: String,
+    /// Set once the user has entered a code sent to `email`; reset whenever
+    /// the email changes. `None` on profiles stored before verification
+    /// existed, which are unverified.
+    pub email_verified: Option<bool>,
+    /// Mirrors the KYC record. `None` on profiles stored before KYC
+    /// existed, which are unverified.
+    pub kyc_status: Option<KycStatus>,
+    /// `None` on profiles stored before account statuses existed, which are
+    /// active.
+    pub status: Option<AccountStatus>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl UserProfile {
+    pub fn account_status(&self) -> AccountStatus {
+        self.status.clone().unwrap_or(AccountStatus::Active)
+    }
+
+    pub fn has_verified_email(&self) -> bool {
+        self.email_verified.unwrap_or(false)
+    }
+}
+
+// Email Verification
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct EmailVerification {
+    /// The address the code was sent to.
+    pub email: String,
+    pub code_hash: Vec<u8>,
+    pub salt: Vec<u8>,
+    pub attempts: u32,
     pub created_at: u64,
+    pub expires_at: u64,
+}
+
+/// Wrong codes entered for one email address across all codes sent to it.
+#[derive(Debug, Clone, Default, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct EmailFailures {
+    pub failures: u32,
+    /// No codes are sent or checked for the address before this time.
+    pub locked_until: Option<u64>,
+    pub last_failure_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum NotifierMode {
+    /// Write the sender, recipient and subject of outgoing messages to the
+    /// canister log, without the body; for local development.
+    Log,
+    /// POST outgoing messages as JSON to a mail relay via HTTPS outcall.
+    HttpsRelay {
+        url: String,
+        /// Sent as the `Authorization` header, if set.
+        auth_header: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct NotifierConfig {
+    pub mode: NotifierMode,
+    pub sender: String,
+}
+
+impl Default for NotifierConfig {
+    fn default() -> Self {
+        Self {
+            mode: NotifierMode::Log,
+            sender: "no-reply@harvestx.app".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct EmailVerificationStatus {
+    pub email: String,
+    pub expires_at: u64,
+    pub attempts_remaining: u32,
+}
+
+// Account Linking
+/// An additional principal (device, Internet Identity anchor or wallet)
+/// that acts on behalf of `account`.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct LinkedPrincipal {
+    pub principal: Principal,
+    pub account: Principal,
+    pub label: String,
+    pub linked_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct LinkChallenge {
+    pub account: Principal,
+    pub label: String,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct LinkChallengeResponse {
+    /// Handed to the new device, which passes it to `link_principal`.
+    pub challenge: String,
+    pub expires_at: u64,
+}
+
+// Organizations
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub created_by: Principal,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// Roles are ordered: each one includes the permissions of those below it.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, CandidType, Serialize, Deserialize,
+)]
+pub enum OrgRole {
+    /// Can see the organization's offers, requests and transactions.
+    Viewer,
+    /// Can also create offers and respond to requests.
+    Manager,
+    /// Can also manage members and edit the organization.
+    Owner,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct OrgMember {
+    pub organization_id: String,
+    pub principal: Principal,
+    pub role: OrgRole,
+    pub added_by: Principal,
+    pub added_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct OrganizationRevenue {
+    pub organization_id: String,
+    pub transaction_count: u64,
+    pub total_quantity: u64,
+    pub total_amount: f64,
+    pub total_fees: f64,
+}
+
+// Multi-signature Admin Actions
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum AdminAction {
+    UpdateUserRole {
+        principal: Principal,
+        role: UserRole,
+    },
+    DefineRole(DefineRoleRequest),
+    DeleteRole {
+        name: String,
+    },
+    GrantRole {
+        principal: Principal,
+        name: String,
+    },
+    RevokeRole {
+        principal: Principal,
+        name: String,
+    },
+    ConfigureIcrc3Archive(ConfigureArchiveRequest),
+    ConfigureRecordArchive(ConfigureRecordArchiveRequest),
+    ConfigureKycPolicy(KycPolicy),
+    ConfigureNotifier(NotifierConfig),
+    ConfigureRateLimits(RateLimitConfig),
+    UpdateSettings(PlatformSettings),
+    ConfigureTreasury(TreasuryConfig),
+    ConfigureCyclesMonitor(CyclesMonitorConfig),
+    SetRateLimitOverride {
+        principal: Principal,
+        rate_limit_override: Option<RateLimitOverride>,
+    },
+    CancelOffer {
+        offer_id: String,
+        reason: String,
+    },
+    CancelRequest {
+        request_id: String,
+        reason: String,
+    },
+    RefundTransaction {
+        transaction_id: String,
+        reason: String,
+    },
+    SetMultisigPolicy(MultisigPolicy),
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
+pub enum ProposalStatus {
+    Open,
+    Executed,
+    /// Reached the threshold but the action itself failed.
+    Failed(String),
+    Expired,
+    Withdrawn,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct AdminProposal {
+    pub id: u64,
+    pub action: AdminAction,
+    pub proposer: Principal,
+    /// Includes the proposer.
+    pub approvals: Vec<Principal>,
+    /// Approvals needed, fixed when the proposal is created.
+    pub threshold: u32,
+    pub status: ProposalStatus,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub executed_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct MultisigPolicy {
+    /// Approvals (M) needed out of all principals holding
+    /// `ApproveAdminActions` (N). With 1, sensitive endpoints can also be
+    /// called directly.
+    pub threshold: u32,
+    pub proposal_ttl_ns: u64,
+}
+
+impl Default for MultisigPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 1,
+            proposal_ttl_ns: 3 * 24 * 60 * 60 * 1_000_000_000,
+        }
+    }
+}
+
+// Platform Settings
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct PlatformSettings {
+    /// How long an investment request stays open.
+    pub request_ttl_ns: u64,
+    /// Product types offers may be created for. `Other("")` allows every
+    /// custom product type, `Other(name)` only that one.
+    pub allowed_product_types: Vec<ProductType>,
+    pub max_offer_quantity: u64,
+    pub max_price_per_kg: f64,
+    pub max_pending_requests_per_investor: u32,
+    pub fee_schedule: FeeSchedule,
+}
+
+impl Default for PlatformSettings {
+    fn default() -> Self {
+        Self {
+            request_ttl_ns: 7 * 24 * 60 * 60 * 1_000_000_000,
+            allowed_product_types: vec![
+                ProductType::Grains,
+                ProductType::Fruits,
+                ProductType::Vegetables,
+                ProductType::Nuts,
+                ProductType::Herbs,
+                ProductType::Legumes,
+                ProductType::Other(String::new()),
+            ],
+            max_offer_quantity: 1_000_000_000,
+            max_price_per_kg: 1_000_000.0,
+            max_pending_requests_per_investor: 50,
+            fee_schedule: FeeSchedule::default(),
+        }
+    }
+}
+
+/// The settings the frontend needs, readable by anyone.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct PublicSettings {
+    pub request_ttl_ns: u64,
+    pub allowed_product_types: Vec<ProductType>,
+    pub max_offer_quantity: u64,
+    pub max_price_per_kg: f64,
+    pub fee_schedule: FeeSchedule,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct InitArgs {
+    /// Defaults to `PlatformSettings::default()`.
+    pub settings: Option<PlatformSettings>,
+}
+
+// Platform Fees
+/// Fees charged on every accepted trade. The percentage comes from the
+/// first matching product rate, else the highest volume tier the farmer's
+/// trailing volume reaches, else `percentage_bps`; `flat_fee` is added on
+/// top.
+#[derive(Debug, Clone, Default, CandidType, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub percentage_bps: u32,
+    pub flat_fee: f64,
+    pub volume_tiers: Vec<VolumeTier>,
+    pub product_rates: Vec<ProductFeeRate>,
+}
+
+/// Applies to farmers who traded at least `min_amount` over the last 30
+/// days, counting the trade being priced.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct VolumeTier {
+    pub min_amount: f64,
+    pub percentage_bps: u32,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ProductFeeRate {
+    pub product_type: ProductType,
+    pub percentage_bps: u32,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum FeeKind {
+    Percentage { bps: u32 },
+    Flat,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct FeeLine {
+    pub kind: FeeKind,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ProductRevenue {
+    pub product_type: ProductType,
+    pub fees: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct FeeRevenueReport {
+    pub from: u64,
+    pub to: u64,
+    pub currency: String,
+    pub fees_collected: f64,
+    pub fees_refunded: f64,
+    pub net_fees: f64,
+    pub settled_transactions: u64,
+    pub by_product_type: Vec<ProductRevenue>,
+    /// Fees on confirmed transactions that have not settled yet.
+    pub outstanding_fees: f64,
+}
+
+// Treasury
+/// Internal accounts, each backed by a subaccount of the canister on the
+/// configured ICRC-1 ledger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum TreasuryAccount {
+    /// Investor funds of accepted trades that have not settled.
+    Escrow,
+    /// Platform fee income.
+    Fees,
+    /// Farmer proceeds of settled trades awaiting payout.
+    Payouts,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
+pub enum TreasuryEntryKind {
+    /// An accepted trade's value moved into escrow.
+    EscrowDeposit,
+    /// Fees collected when a transaction settled.
+    FeeIncome,
+    /// Farmer proceeds released from escrow at settlement.
+    PayoutScheduled,
+    /// Proceeds paid out to the farmer.
+    Payout,
+    /// Fees returned when a settled transaction was refunded.
+    FeeRefund,
+    /// Funds returned to the investor.
+    Refund,
+}
+
+/// A movement of `amount` between internal accounts; `None` is outside
+/// the treasury.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct TreasuryEntry {
+    pub index: u64,
+    pub timestamp: u64,
+    pub kind: TreasuryEntryKind,
+    pub currency: String,
+    pub from: Option<TreasuryAccount>,
+    pub to: Option<TreasuryAccount>,
+    pub transaction_id: String,
+    pub product_type: Option<ProductType>,
+    pub amount: f64,
+    /// External reference, e.g. the ledger block of a payout.
+    pub reference: Option<String>,
+}
+
+/// Trade amounts are denominated in `currency`, held on `ledger`.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct TreasuryConfig {
+    pub currency: String,
+    pub ledger: Option<Principal>,
+    pub decimals: u8,
+}
+
+impl Default for TreasuryConfig {
+    fn default() -> Self {
+        Self {
+            currency: "ckUSDC".to_string(),
+            ledger: None,
+            decimals: 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct TreasuryBalance {
+    pub currency: String,
+    pub account: TreasuryAccount,
+    pub balance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum TimeBucket {
+    Day,
+    Week,
+}
+
+impl TimeBucket {
+    pub fn duration_ns(&self) -> u64 {
+        const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+        match self {
+            TimeBucket::Day => DAY,
+            TimeBucket::Week => 7 * DAY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct TreasuryPeriodSummary {
+    pub start: u64,
+    pub end: u64,
+    pub currency: String,
+    pub escrow_deposits: f64,
+    pub fee_income: f64,
+    pub payouts_scheduled: f64,
+    pub payouts: f64,
+    pub fee_refunds: f64,
+    pub refunds: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Icrc1Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct AccountReconciliation {
+    pub account: TreasuryAccount,
+    pub subaccount: Vec<u8>,
+    pub internal_balance: f64,
+    pub ledger_balance: f64,
+    /// `ledger_balance - internal_balance`
+    pub difference: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    pub currency: String,
+    pub ledger: Principal,
+    pub checked_at: u64,
+    pub accounts: Vec<AccountReconciliation>,
+    pub balanced: bool,
+}
+
+// Emergency Pause
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum PausableFeature {
+    Offers,
+    Requests,
+    Acceptances,
+    Settlements,
+}
+
+#[derive(Debug, Clone, Default, CandidType, Serialize, Deserialize)]
+pub struct PauseState {
+    /// Maintenance mode: pauses every feature.
+    pub platform: bool,
+    pub features: Vec<PausableFeature>,
+    /// Shown to callers while anything is paused.
+    pub reason: Option<String>,
+    pub updated_by: Option<Principal>,
     pub updated_at: u64,
 }
 
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Paused {
+    /// `None` in maintenance mode.
+    pub feature: Option<PausableFeature>,
+    pub reason: Option<String>,
+}
+
+impl Paused {
+    pub fn message(&self) -> String {
+        let what = match &self.feature {
+            Some(feature) => format!("{:?} are paused", feature),
+            None => "The platform is in maintenance mode".to_string(),
+        };
+        match &self.reason {
+            Some(reason) => format!("{}: {}", what, reason),
+            None => what,
+        }
+    }
+}
+
+// Rate Limiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, CandidType, Serialize, Deserialize)]
+pub enum RateLimitedAction {
+    CreateOffer,
+    CreateRequest,
+    /// Free-text request messages and outgoing emails.
+    SendMessage,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub action: RateLimitedAction,
+    /// At most `max` actions within any `window_ns` interval.
+    pub max: u32,
+    pub window_ns: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub limits: Vec<RateLimit>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        const MINUTE: u64 = 60 * 1_000_000_000;
+        Self {
+            limits: vec![
+                RateLimit {
+                    action: RateLimitedAction::CreateOffer,
+                    max: 20,
+                    window_ns: 24 * 60 * MINUTE,
+                },
+                RateLimit {
+                    action: RateLimitedAction::CreateRequest,
+                    max: 30,
+                    window_ns: 60 * MINUTE,
+                },
+                RateLimit {
+                    action: RateLimitedAction::SendMessage,
+                    max: 10,
+                    window_ns: MINUTE,
+                },
+            ],
+        }
+    }
+}
+
+/// Per-principal exception for trusted accounts.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct RateLimitOverride {
+    /// Skip rate limiting entirely.
+    pub exempt: bool,
+    /// Replace the default limit for the listed actions.
+    pub limits: Vec<RateLimit>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct RateLimited {
+    pub action: RateLimitedAction,
+    pub retry_after_secs: u64,
+}
+
+// Identity Verification (KYC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum KycStatus {
+    Unverified,
+    /// Submitted and waiting for a verifier.
+    Pending,
+    Verified,
+    Rejected,
+    /// Was verified, but the verification is older than the policy allows.
+    Expired,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct KycDetails {
+    pub legal_name: String,
+    /// ISO 3166-1 alpha-2 country code.
+    pub country: String,
+    pub address: String,
+    pub date_of_birth: Option<String>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct KycDocument {
+    /// E.g. "passport", "national_id", "land_title".
+    pub kind: String,
+    /// SHA-256 of the document; the document itself stays off-chain.
+    pub sha256: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct KycRecord {
+    pub principal: Principal,
+    pub status: KycStatus,
+    pub details: KycDetails,
+    pub documents: Vec<KycDocument>,
+    pub submitted_at: u64,
+    pub reviewed_by: Option<Principal>,
+    pub reviewed_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub rejection_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct SubmitKycRequest {
+    pub details: KycDetails,
+    pub documents: Vec<KycDocument>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ReviewKycRequest {
+    pub principal: Principal,
+    pub approve: bool,
+    /// Required when rejecting.
+    pub reason: Option<String>,
+}
+
+/// When verification is required. A threshold is compared with the value
+/// of the offer or request (quantity times price per kg); `None` disables
+/// the requirement.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct KycPolicy {
+    pub offer_value_threshold: Option<f64>,
+    pub request_value_threshold: Option<f64>,
+    /// How long a verification stays valid.
+    pub validity_ns: u64,
+}
+
+impl Default for KycPolicy {
+    fn default() -> Self {
+        Self {
+            offer_value_threshold: Some(0.0),
+            request_value_threshold: Some(0.0),
+            validity_ns: 365 * 24 * 60 * 60 * 1_000_000_000,
+        }
+    }
+}
+
 // Investment Offers
 #[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
 pub struct InvestmentOffer {
@@ -54,12 +793,15 @@ pub struct InvestmentOffer {
     pub location: String,
     pub quality_grade: QualityGrade,
     pub minimum_investment: u64,
+    /// Set when the offer is sold on behalf of an organization; `farmer`
+    /// is then the member who created it.
+    pub organization: Option<String>,
     pub status: OfferStatus,
     pub created_at: u64,
     pub updated_at: u64,
 }
 
-#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
 pub enum ProductType {
     Grains,
     Fruits,
@@ -70,7 +812,7 @@ pub enum ProductType {
     Other(String),
 }
 
-#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
 pub enum QualityGrade {
     Premium,
     Grade1,
@@ -80,7 +822,7 @@ pub enum QualityGrade {
     Certified(String),
 }
 
-#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
 pub enum OfferStatus {
     Active,
     Completed,
@@ -104,7 +846,7 @@ pub struct InvestmentRequest {
     pub expires_at: u64,
 }
 
-#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
 pub enum RequestStatus {
     Pending,
     Accepted,
@@ -128,13 +870,183 @@ pub struct Transaction {
     pub created_at: u64,
     pub updated_at: u64,
     pub tokenized_at: Option<u64>,
+    /// Organization the revenue is attributed to, copied from the offer.
+    pub organization: Option<String>,
+    /// Platform fees, deducted from the farmer's proceeds at settlement.
+    /// `None` on transactions stored before fees existed, which carry none.
+    pub fees: Option<Vec<FeeLine>>,
+    pub settled_at: Option<u64>,
 }
 
-#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+impl Transaction {
+    pub fn fee_lines(&self) -> &[FeeLine] {
+        self.fees.as_deref().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
 pub enum TransactionStatus {
     Confirmed,
     Tokenized,
     Completed,
+    /// Reversed by an admin action.
+    Refunded,
+}
+
+// Investor Portfolio
+/// An investor's trades on one offer. Refunded trades only count towards
+/// `refunded_amount`.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct PortfolioPosition {
+    pub offer_id: String,
+    /// `None` when the offer no longer exists.
+    pub product_name: Option<String>,
+    pub product_type: Option<ProductType>,
+    pub quality_grade: Option<QualityGrade>,
+    pub transaction_count: u64,
+    pub committed_capital: f64,
+    pub refunded_amount: f64,
+    pub quantity: u64,
+    /// Quantity of settled trades.
+    pub delivered_quantity: u64,
+    pub outstanding_quantity: u64,
+    pub average_price_per_kg: f64,
+    /// Price of the latest trade in the same product type and grade.
+    pub market_price_per_kg: Option<f64>,
+    pub market_value: Option<f64>,
+    pub unrealized_return: Option<f64>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ProductExposure {
+    pub product_type: ProductType,
+    pub committed_capital: f64,
+    pub quantity: u64,
+    pub market_value: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct StatusBreakdown {
+    pub status: TransactionStatus,
+    pub transaction_count: u64,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Portfolio {
+    pub investor: Principal,
+    pub generated_at: u64,
+    pub positions: Vec<PortfolioPosition>,
+    pub by_product_type: Vec<ProductExposure>,
+    pub by_status: Vec<StatusBreakdown>,
+    pub committed_capital: f64,
+    /// Positions without a market price are valued at cost.
+    pub market_value: f64,
+    pub unrealized_return: f64,
+    /// `unrealized_return / committed_capital`, if anything is committed.
+    pub return_ratio: Option<f64>,
+}
+
+// Farmer Analytics
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct OfferAnalytics {
+    pub offer_id: String,
+    pub product_name: String,
+    pub status: OfferStatus,
+    pub asking_price_per_kg: f64,
+    pub total_quantity: u64,
+    pub sold_quantity: u64,
+    /// `sold_quantity / total_quantity`
+    pub fill_rate: f64,
+    /// Value of trades on the offer, refunds excluded.
+    pub funding_raised: f64,
+    pub average_accepted_price_per_kg: Option<f64>,
+    /// `average_accepted_price_per_kg / asking_price_per_kg`
+    pub price_realization: Option<f64>,
+    pub requests_received: u64,
+    pub requests_accepted: u64,
+    pub requests_rejected: u64,
+    pub requests_pending: u64,
+    /// Accepted share of the requests the farmer responded to.
+    pub acceptance_rate: Option<f64>,
+    /// From listing to the trade that sold the last kilogram.
+    pub time_to_fill_ns: Option<u64>,
+    /// Traded but not yet settled.
+    pub pending_delivery_quantity: u64,
+    pub pending_delivery_value: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct FarmerAnalytics {
+    pub farmer: Principal,
+    pub generated_at: u64,
+    pub offers: Vec<OfferAnalytics>,
+    pub funding_raised: f64,
+    pub fill_rate: Option<f64>,
+    pub price_realization: Option<f64>,
+    pub acceptance_rate: Option<f64>,
+    pub average_time_to_fill_ns: Option<u64>,
+    pub pending_delivery_quantity: u64,
+    pub pending_delivery_value: f64,
+    /// Requests awaiting the farmer's response.
+    pub pending_requests: u64,
+    pub pending_request_value: f64,
+}
+
+// Market Prices
+/// An accepted trade as recorded in the price history.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct PricePoint {
+    pub index: u64,
+    pub timestamp: u64,
+    pub transaction_id: String,
+    pub product_type: ProductType,
+    pub quality_grade: QualityGrade,
+    /// The offer's location at the time of the trade.
+    pub region: String,
+    pub quantity: u64,
+    pub price_per_kg: f64,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct PriceHistoryQuery {
+    pub product_type: ProductType,
+    /// All grades when unset.
+    pub quality_grade: Option<QualityGrade>,
+    /// All regions when unset; matched case-insensitively.
+    pub region: Option<String>,
+    pub from: u64,
+    pub to: u64,
+    pub bucket: TimeBucket,
+}
+
+/// Open, high, low and close prices per kg over one bucket.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct PriceCandle {
+    pub start: u64,
+    pub end: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// Kilograms traded.
+    pub volume: u64,
+    pub value: f64,
+    /// Volume-weighted average price per kg.
+    pub vwap: f64,
+    pub trade_count: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ReferencePrice {
+    pub product_type: ProductType,
+    pub quality_grade: QualityGrade,
+    /// Volume-weighted average price per kg over the reference window.
+    pub vwap: f64,
+    pub volume: u64,
+    pub trade_count: u64,
+    pub last_price_per_kg: f64,
+    pub last_traded_at: u64,
 }
 
 // Request Types
@@ -145,6 +1057,12 @@ pub struct RegisterUserRequest {
     pub email: String,
 }
 
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct UpdateProfileRequest {
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+}
+
 #[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
 pub struct CreateOfferRequest {
     pub product_name: String,
@@ -156,6 +1074,8 @@ pub struct CreateOfferRequest {
     pub location: String,
     pub quality_grade: QualityGrade,
     pub minimum_investment: u64,
+    /// Sell on behalf of this organization; requires the manager role.
+    pub organization_id: Option<String>,
 }
 
 #[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
@@ -178,6 +1098,16 @@ pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    /// Identifies errors clients are expected to handle specially.
+    pub error_kind: Option<ErrorKind>,
+    /// Set when the call was rate limited: seconds until it may succeed.
+    pub retry_after_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum ErrorKind {
+    RateLimited,
+    Paused,
 }
 
 impl<T> ApiResponse<T> {
@@ -186,18 +1116,166 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            error_kind: None,
+            retry_after_secs: None,
         }
     }
 
     pub fn error(error: String) -> Self {
+        crate::metrics::note_failure();
         Self {
             success: false,
             data: None,
             error: Some(error),
+            error_kind: None,
+            retry_after_secs: None,
+        }
+    }
+
+    pub fn rate_limited(limited: RateLimited) -> Self {
+        crate::metrics::note_failure();
+        Self {
+            success: false,
+            data: None,
+            error: Some(format!(
+                "Rate limit exceeded for {:?}, retry after {} seconds",
+                limited.action, limited.retry_after_secs
+            )),
+            error_kind: Some(ErrorKind::RateLimited),
+            retry_after_secs: Some(limited.retry_after_secs),
+        }
+    }
+
+    pub fn paused(paused: Paused) -> Self {
+        crate::metrics::note_failure();
+        Self {
+            success: false,
+            data: None,
+            error: Some(paused.message()),
+            error_kind: Some(ErrorKind::Paused),
+            retry_after_secs: None,
         }
     }
 }
 
+// Cycles
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct CyclesMonitorConfig {
+    /// Admins are alerted when the balance falls below this.
+    pub alert_threshold: u128,
+    /// Balance at which the canister is considered frozen, such as the
+    /// reserve implied by its freezing threshold.
+    pub freeze_reserve: u128,
+}
+
+impl Default for CyclesMonitorConfig {
+    fn default() -> Self {
+        Self {
+            alert_threshold: 2_000_000_000_000,
+            freeze_reserve: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct CyclesSample {
+    pub timestamp: u64,
+    pub balance: u128,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct CyclesTopUp {
+    pub index: u64,
+    pub contributor: Principal,
+    pub amount: u128,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct CyclesContributor {
+    pub contributor: Principal,
+    pub total: u128,
+    pub top_ups: u64,
+    pub last_top_up_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct CyclesStatus {
+    pub balance: u128,
+    pub config: CyclesMonitorConfig,
+    pub below_threshold: bool,
+    /// Average consumption over the sampled period, top-ups excluded.
+    pub burn_rate_per_day: Option<u128>,
+    /// When the balance is projected to reach `freeze_reserve` at the
+    /// current burn rate.
+    pub projected_freeze_at: Option<u64>,
+    pub sampled_since: Option<u64>,
+    pub checked_at: u64,
+}
+
+/// The reply expected from a `wallet_receive` call.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct WalletReceiveResult {
+    pub accepted: u64,
+}
+
+// Notifications
+#[derive(Debug, Clone, PartialEq, CandidType, Serialize, Deserialize)]
+pub enum NotificationKind {
+    /// An investor sent a request for one of the farmer's offers.
+    RequestReceived,
+    RequestAccepted,
+    RequestRejected,
+    RequestExpired,
+    /// Cancelled by an admin.
+    RequestCancelled,
+    TransactionSettled,
+    TransactionRefunded,
+    PayoutRecorded,
+    /// Sent to admins when the backend runs low on cycles.
+    LowCycles,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Notification {
+    /// Increasing per recipient.
+    pub id: u64,
+    pub recipient: Principal,
+    pub kind: NotificationKind,
+    pub message: String,
+    pub offer_id: Option<String>,
+    pub request_id: Option<String>,
+    pub transaction_id: Option<String>,
+    pub read: bool,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct NotificationPage {
+    /// Newest first.
+    pub notifications: Vec<Notification>,
+    /// Pass as `before` to fetch the next page.
+    pub next_before: Option<u64>,
+    pub unread_count: u64,
+}
+
+// HTTP Gateway
+/// A request forwarded by the HTTP gateway to `http_request`.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct HttpGatewayResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
 // Platform Statistics
 #[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
 pub struct PlatformStats {
@@ -207,6 +1285,345 @@ pub struct PlatformStats {
     pub total_transactions: u64,
     pub active_offers: u64,
 }
+
+/// Accepted trades in one product type.
+#[derive(Debug, Clone, Default, CandidType, Serialize, Deserialize)]
+pub struct TradeVolume {
+    pub trade_count: u64,
+    pub quantity: u64,
+    pub value: f64,
+}
+
+/// Running totals, updated as records change rather than recounted.
+/// Custom product types are counted together under `Other("")`.
+#[derive(Debug, Clone, Default, CandidType, Serialize, Deserialize)]
+pub struct PlatformCounters {
+    pub users_by_role: Vec<(UserRole, u64)>,
+    pub offers_by_status: Vec<(OfferStatus, u64)>,
+    pub offers_by_product_type: Vec<(ProductType, u64)>,
+    pub requests_by_status: Vec<(RequestStatus, u64)>,
+    pub transactions_by_status: Vec<(TransactionStatus, u64)>,
+    /// Accepted trades per product type, refunds deducted.
+    pub traded_volume: Vec<(ProductType, TradeVolume)>,
+    /// When the counters were first built from the stored records.
+    pub counting_since: u64,
+}
+
+/// Platform activity over one day or week.
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct StatsBucket {
+    pub start: u64,
+    pub end: u64,
+    pub new_users: Vec<(UserRole, u64)>,
+    pub new_offers: Vec<(ProductType, u64)>,
+    pub new_requests: u64,
+    /// Requests that were accepted, rejected, cancelled or expired.
+    pub resolved_requests: Vec<(RequestStatus, u64)>,
+    /// Trades accepted in the bucket, including ones refunded later.
+    pub accepted_volume: Vec<(ProductType, TradeVolume)>,
+}
+
+// Certified Responses
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct CertifiedResponse<T> {
+    pub data: T,
+    /// System certificate over the canister's certified data.
+    pub certificate: Vec<u8>,
+    /// CBOR-encoded hash tree proving `data` under the certified root hash.
+    pub witness: Vec<u8>,
+}
+
+// Audit Log
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum EntityType {
+    User,
+    Offer,
+    Request,
+    Transaction,
+    Config,
+    Kyc,
+    Organization,
+    Proposal,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub index: u64,
+    pub timestamp: u64,
+    pub actor: Principal,
+    pub action: String,
+    pub entity_type: EntityType,
+    pub entity_id: String,
+    pub before_digest: Option<Vec<u8>>,
+    pub after_digest: Option<Vec<u8>>,
+    pub prev_hash: Vec<u8>,
+    pub hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: u64,
+    pub next_start: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct AuditChainReport {
+    pub valid: bool,
+    pub entries_checked: u64,
+    pub first_invalid_index: Option<u64>,
+    pub error: Option<String>,
+    /// Hash of the last entry checked, which the next segment must link to.
+    pub last_hash: Option<Vec<u8>>,
+    pub next_start: Option<u64>,
+}
+
+impl AuditChainReport {
+    pub fn broken(index: u64, error: &str) -> Self {
+        Self {
+            valid: false,
+            entries_checked: 0,
+            first_invalid_index: Some(index),
+            error: Some(error.to_string()),
+            last_hash: None,
+            next_start: None,
+        }
+    }
+}
+
+// ICRC-3 Transaction Log
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum Icrc3Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Icrc3Value>),
+    Map(Vec<(String, Icrc3Value)>),
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Icrc3State {
+    /// Number of blocks ever appended, archived or not.
+    pub log_length: u64,
+    /// Index of the oldest block still held by this canister.
+    pub first_local_index: u64,
+    pub last_block_hash: Option<Vec<u8>>,
+    pub archive_canister: Option<Principal>,
+    /// Archiving starts once more than this many blocks are held locally.
+    pub archive_threshold: u64,
+    /// Maximum number of blocks moved to the archive per call.
+    pub archive_batch_size: u64,
+    /// Whether blocks are being moved right now. Filled in when the state is
+    /// read and never stored; `None` in the stored copy.
+    pub archiving: Option<bool>,
+}
+
+impl Default for Icrc3State {
+    fn default() -> Self {
+        Self {
+            log_length: 0,
+            first_local_index: 0,
+            last_block_hash: None,
+            archive_canister: None,
+            archive_threshold: 10_000,
+            archive_batch_size: 1_000,
+            archiving: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Icrc3Value,
+}
+
+candid::define_function!(pub GetBlocksFn : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksFn,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct GetArchivesArgs {
+    pub from: Option<Principal>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Icrc3ArchiveInfo {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct Icrc3DataCertificate {
+    pub certificate: Vec<u8>,
+    pub hash_tree: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct SupportedBlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct SupportedStandard {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ConfigureArchiveRequest {
+    pub archive_canister: Principal,
+    pub archive_threshold: u64,
+    pub archive_batch_size: u64,
+}
+
+// Record Archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum RecordKind {
+    Transaction,
+    Request,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ArchivedRecord {
+    pub kind: RecordKind,
+    pub id: String,
+    pub parties: Vec<Principal>,
+    pub created_at: u64,
+    pub archived_at: u64,
+    /// Candid encoding of the `Transaction` or `InvestmentRequest`.
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct RecordArchiveState {
+    /// Finalized records untouched for longer than this are archived.
+    pub min_age_ns: u64,
+    /// Maximum number of records of each kind moved per run.
+    pub batch_size: u64,
+    pub archived_transactions: u64,
+    pub archived_requests: u64,
+    pub last_run_at: Option<u64>,
+    /// Whether a run is in progress. Filled in when the state is read and
+    /// never stored; `None` in the stored copy.
+    pub archiving: Option<bool>,
+}
+
+impl Default for RecordArchiveState {
+    fn default() -> Self {
+        Self {
+            min_age_ns: 90 * 24 * 60 * 60 * 1_000_000_000, // 90 days in nanoseconds
+            batch_size: 500,
+            archived_transactions: 0,
+            archived_requests: 0,
+            last_run_at: None,
+            archiving: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ConfigureRecordArchiveRequest {
+    pub min_age_ns: u64,
+    pub batch_size: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ArchiveRunSummary {
+    pub transactions_archived: u64,
+    pub requests_archived: u64,
+}
+
+// Personal Data Export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum ExportSection {
+    Profile,
+    Offers,
+    Requests,
+    Transactions,
+    Messages,
+    AuditEntries,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ExportCursor {
+    pub section: ExportSection,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Candid,
+    Json,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ExportRequest {
+    /// `None` starts from the beginning; otherwise the `next_cursor` of the
+    /// previous page.
+    pub cursor: Option<ExportCursor>,
+    pub page_size: Option<u64>,
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum MessageDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    pub request_id: String,
+    pub offer_id: String,
+    pub direction: MessageDirection,
+    pub message: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub struct DataExportPage {
+    pub version: u32,
+    pub subject: Principal,
+    pub generated_at: u64,
+    pub profile: Option<UserProfile>,
+    pub kyc: Option<KycRecord>,
+    pub linked_principals: Vec<LinkedPrincipal>,
+    pub offers: Vec<InvestmentOffer>,
+    pub requests: Vec<InvestmentRequest>,
+    pub transactions: Vec<Transaction>,
+    pub messages: Vec<ExportedMessage>,
+    pub audit_entries: Vec<AuditEntry>,
+    /// Cursor for the following page, `None` on the last page.
+    pub next_cursor: Option<ExportCursor>,
+}
+
+#[derive(Debug, Clone, CandidType, Serialize, Deserialize)]
+pub enum DataExport {
+    Candid(Box<DataExportPage>),
+    /// The same page serialized as JSON.
+    Json(String),
+}
 impl_storable!(UserProfile, 1024);
 impl_storable!(InvestmentOffer, 2048);
 impl_storable!(InvestmentRequest, 1024);
@@ -215,4 +1632,362 @@ impl_storable!(RegisterUserRequest, 512);
 impl_storable!(CreateOfferRequest, 1024);
 impl_storable!(CreateInvestmentRequest, 512);
 impl_storable!(RespondToRequestRequest, 256);
-impl_storable!(PlatformStats, 256);
\ No newline at end of file
+impl_storable!(PlatformStats, 256);
+impl_storable!(AuditEntry, 1024);
+impl_storable!(Icrc3Value, 4096);
+impl_storable!(Icrc3State, 256);
+impl_storable!(RecordArchiveState, 256);
+impl_storable!(EmailVerification, 512);
+impl_storable!(EmailFailures, 128);
+impl_storable!(NotifierConfig, 1024);
+// Free-text details and documents can exceed any practical bound in
+// multi-byte UTF-8
+impl_storable!(KycRecord);
+impl_storable!(KycPolicy, 128);
+// Labels are free text
+impl_storable!(LinkedPrincipal);
+impl_storable!(LinkChallenge);
+// Names and descriptions are free text
+impl_storable!(Organization);
+impl_storable!(OrgMember, 256);
+// Role names and descriptions are free text
+impl_storable!(RoleDefinition);
+impl_storable!(RoleGrants);
+// Carries whole settings and configs, and grows with every action added
+impl_storable!(AdminProposal);
+impl_storable!(MultisigPolicy, 128);
+impl_storable!(RateLimitConfig, 512);
+impl_storable!(RateLimitOverride, 512);
+// Custom product types, here and in the fee schedule, are free text
+impl_storable!(PlatformSettings);
+impl_storable!(PauseState, 2048);
+// References and custom product types are free text
+impl_storable!(TreasuryEntry);
+impl_storable!(TreasuryConfig, 256);
+// Regions and custom product types and grades are free text
+impl_storable!(PricePoint);
+impl_storable!(PlatformCounters, 2048);
+impl_storable!(StatsBucket, 2048);
+impl_storable!(CyclesMonitorConfig, 128);
+impl_storable!(CyclesSample, 64);
+impl_storable!(CyclesTopUp, 128);
+// Messages quote free-text product names
+impl_storable!(Notification);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Text limits count characters; four-byte characters give the most bytes
+    fn longest(chars: usize) -> String {
+        "𝕏".repeat(chars)
+    }
+
+    fn longest_principal() -> Principal {
+        Principal::from_slice(&[0xff; 29])
+    }
+
+    fn assert_fits<T: Storable>(value: &T) {
+        let len = value.to_bytes().len();
+        if let Bound::Bounded { max_size, .. } = T::BOUND {
+            assert!(
+                len <= max_size as usize,
+                "{} bytes exceed the bound of {}",
+                len,
+                max_size
+            );
+        }
+    }
+
+    #[test]
+    fn audit_entry_fits_its_bound() {
+        assert_fits(&AuditEntry {
+            index: u64::MAX,
+            timestamp: u64::MAX,
+            actor: longest_principal(),
+            action: "execute_admin_action".to_string(),
+            entity_type: EntityType::Config,
+            // Custom roles are logged by name, the longest entity id
+            entity_id: format!("role:{}", longest(64)),
+            before_digest: Some(vec![0xff; 32]),
+            after_digest: Some(vec![0xff; 32]),
+            prev_hash: vec![0xff; 32],
+            hash: vec![0xff; 32],
+        });
+    }
+
+    #[test]
+    fn icrc3_state_fits_its_bound() {
+        assert_fits(&Icrc3State {
+            log_length: u64::MAX,
+            first_local_index: u64::MAX,
+            last_block_hash: Some(vec![0xff; 32]),
+            archive_canister: Some(longest_principal()),
+            archive_threshold: u64::MAX,
+            archive_batch_size: u64::MAX,
+            archiving: Some(true),
+        });
+    }
+
+    #[test]
+    fn record_archive_state_fits_its_bound() {
+        assert_fits(&RecordArchiveState {
+            min_age_ns: u64::MAX,
+            batch_size: u64::MAX,
+            archived_transactions: u64::MAX,
+            archived_requests: u64::MAX,
+            last_run_at: Some(u64::MAX),
+            archiving: Some(true),
+        });
+    }
+
+    #[test]
+    fn user_profile_fits_its_bound() {
+        assert_fits(&UserProfile {
+            principal: longest_principal(),
+            role: UserRole::Verifier,
+            display_name: longest(64),
+            // Emails are ASCII after validation
+            email: "x".repeat(254),
+            email_verified: Some(true),
+            kyc_status: Some(KycStatus::Expired),
+            status: Some(AccountStatus::Deactivated),
+            created_at: u64::MAX,
+            updated_at: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn email_verification_types_fit_their_bounds() {
+        assert_fits(&EmailVerification {
+            email: "x".repeat(254),
+            code_hash: vec![0xff; 32],
+            salt: vec![0xff; 16],
+            attempts: u32::MAX,
+            created_at: u64::MAX,
+            expires_at: u64::MAX,
+        });
+        assert_fits(&EmailFailures {
+            failures: u32::MAX,
+            locked_until: Some(u64::MAX),
+            last_failure_at: u64::MAX,
+        });
+        // Relay URL and header are limited in bytes; the sender is an email
+        assert_fits(&NotifierConfig {
+            mode: NotifierMode::HttpsRelay {
+                url: "x".repeat(256),
+                auth_header: Some("x".repeat(256)),
+            },
+            sender: "x".repeat(254),
+        });
+    }
+
+    #[test]
+    fn kyc_policy_fits_its_bound() {
+        assert_fits(&KycPolicy {
+            offer_value_threshold: Some(f64::MAX),
+            request_value_threshold: Some(f64::MAX),
+            validity_ns: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn org_member_fits_its_bound() {
+        assert_fits(&OrgMember {
+            organization_id: format!("org_{}", u64::MAX),
+            principal: longest_principal(),
+            role: OrgRole::Owner,
+            added_by: longest_principal(),
+            added_at: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn multisig_policy_fits_its_bound() {
+        assert_fits(&MultisigPolicy {
+            threshold: u32::MAX,
+            proposal_ttl_ns: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn rate_limit_types_fit_their_bounds() {
+        // Validation allows one limit per action
+        let limits = [
+            RateLimitedAction::CreateOffer,
+            RateLimitedAction::CreateRequest,
+            RateLimitedAction::SendMessage,
+        ]
+        .map(|action| RateLimit {
+            action,
+            max: u32::MAX,
+            window_ns: u64::MAX,
+        })
+        .to_vec();
+        assert_fits(&RateLimitConfig {
+            limits: limits.clone(),
+        });
+        assert_fits(&RateLimitOverride {
+            exempt: true,
+            limits,
+        });
+    }
+
+    #[test]
+    fn pause_state_fits_its_bound() {
+        assert_fits(&PauseState {
+            platform: true,
+            features: vec![
+                PausableFeature::Offers,
+                PausableFeature::Requests,
+                PausableFeature::Acceptances,
+                PausableFeature::Settlements,
+            ],
+            reason: Some(longest(256)),
+            updated_by: Some(longest_principal()),
+            updated_at: u64::MAX,
+        });
+    }
+
+    #[test]
+    fn transaction_fits_its_bound() {
+        let id = |prefix: &str| Some(format!("{}_{}", prefix, u64::MAX));
+        assert_fits(&Transaction {
+            id: id("txn").unwrap(),
+            offer_id: id("offer").unwrap(),
+            request_id: id("req").unwrap(),
+            farmer: longest_principal(),
+            investor: longest_principal(),
+            quantity: u64::MAX,
+            price_per_kg: f64::MAX,
+            total_amount: f64::MAX,
+            status: TransactionStatus::Refunded,
+            created_at: u64::MAX,
+            updated_at: u64::MAX,
+            tokenized_at: Some(u64::MAX),
+            organization: id("org"),
+            // At most a percentage and a flat fee
+            fees: Some(vec![
+                FeeLine {
+                    kind: FeeKind::Percentage { bps: u32::MAX },
+                    amount: f64::MAX,
+                },
+                FeeLine {
+                    kind: FeeKind::Flat,
+                    amount: f64::MAX,
+                },
+            ]),
+            settled_at: Some(u64::MAX),
+        });
+    }
+
+    #[test]
+    fn treasury_types_fit_their_bounds() {
+        assert_fits(&TreasuryEntry {
+            index: u64::MAX,
+            timestamp: u64::MAX,
+            kind: TreasuryEntryKind::PayoutScheduled,
+            currency: longest(16),
+            from: Some(TreasuryAccount::Payouts),
+            to: Some(TreasuryAccount::Payouts),
+            transaction_id: format!("txn_{}", u64::MAX),
+            product_type: Some(ProductType::Other(longest(64))),
+            amount: f64::MAX,
+            reference: Some(longest(128)),
+        });
+        assert_fits(&TreasuryConfig {
+            currency: longest(16),
+            ledger: Some(longest_principal()),
+            decimals: u8::MAX,
+        });
+    }
+
+    #[test]
+    fn stats_types_fit_their_bounds() {
+        // Custom product types are all counted under `Other("")`
+        let product_types = [
+            ProductType::Grains,
+            ProductType::Fruits,
+            ProductType::Vegetables,
+            ProductType::Nuts,
+            ProductType::Herbs,
+            ProductType::Legumes,
+            ProductType::Other(String::new()),
+        ];
+        let roles = [
+            UserRole::Admin,
+            UserRole::Farmer,
+            UserRole::Investor,
+            UserRole::Guest,
+            UserRole::Verifier,
+        ];
+        let requests = [
+            RequestStatus::Pending,
+            RequestStatus::Accepted,
+            RequestStatus::Rejected,
+            RequestStatus::Expired,
+            RequestStatus::Cancelled,
+        ];
+        let volume = TradeVolume {
+            trade_count: u64::MAX,
+            quantity: u64::MAX,
+            value: f64::MAX,
+        };
+        fn counts<T: Clone>(keys: &[T]) -> Vec<(T, u64)> {
+            keys.iter().map(|key| (key.clone(), u64::MAX)).collect()
+        }
+        let volumes = || {
+            product_types
+                .iter()
+                .map(|product_type| (product_type.clone(), volume.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_fits(&PlatformCounters {
+            users_by_role: counts(&roles),
+            offers_by_status: counts(&[
+                OfferStatus::Active,
+                OfferStatus::Completed,
+                OfferStatus::Cancelled,
+                OfferStatus::Expired,
+            ]),
+            offers_by_product_type: counts(&product_types),
+            requests_by_status: counts(&requests),
+            transactions_by_status: counts(&[
+                TransactionStatus::Confirmed,
+                TransactionStatus::Tokenized,
+                TransactionStatus::Completed,
+                TransactionStatus::Refunded,
+            ]),
+            traded_volume: volumes(),
+            counting_since: u64::MAX,
+        });
+        assert_fits(&StatsBucket {
+            start: u64::MAX,
+            end: u64::MAX,
+            new_users: counts(&roles),
+            new_offers: counts(&product_types),
+            new_requests: u64::MAX,
+            resolved_requests: counts(&requests),
+            accepted_volume: volumes(),
+        });
+    }
+
+    #[test]
+    fn cycles_types_fit_their_bounds() {
+        assert_fits(&CyclesMonitorConfig {
+            alert_threshold: u128::MAX,
+            freeze_reserve: u128::MAX,
+        });
+        assert_fits(&CyclesSample {
+            timestamp: u64::MAX,
+            balance: u128::MAX,
+        });
+        assert_fits(&CyclesTopUp {
+            index: u64::MAX,
+            contributor: longest_principal(),
+            amount: u128::MAX,
+            timestamp: u64::MAX,
+        });
+    }
+}
\ No newline at end of file
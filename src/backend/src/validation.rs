@@ -0,0 +1,78 @@
+// Input validation for user-supplied text fields
+
+const MIN_DISPLAY_NAME_LEN: usize = 2;
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+const MAX_EMAIL_LEN: usize = 254;
+
+/// Trims the display name and checks its length and characters.
+pub fn validate_display_name(display_name: &str) -> Result<String, String> {
+    let display_name = display_name.trim();
+    let len = display_name.chars().count();
+
+    if !(MIN_DISPLAY_NAME_LEN..=MAX_DISPLAY_NAME_LEN).contains(&len) {
+        return Err(format!(
+            "Display name must be between {} and {} characters",
+            MIN_DISPLAY_NAME_LEN, MAX_DISPLAY_NAME_LEN
+        ));
+    }
+    if display_name.chars().any(char::is_control) {
+        return Err("Display name contains invalid characters".to_string());
+    }
+
+    Ok(display_name.to_string())
+}
+
+/// Trims and lowercases the email and checks that it looks like
+/// `local@domain.tld`.
+pub fn validate_email(email: &str) -> Result<String, String> {
+    let email = email.trim().to_lowercase();
+
+    if email.len() > MAX_EMAIL_LEN {
+        return Err("Email is too long".to_string());
+    }
+
+    let (local, domain) = match email.split_once('@') {
+        Some(parts) => parts,
+        None => return Err("Invalid email format".to_string()),
+    };
+
+    let valid_local = !local.is_empty()
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let valid_domain = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    if !valid_local || !valid_domain {
+        return Err("Invalid email format".to_string());
+    }
+
+    Ok(email)
+}
+
+/// Trims `value` and checks that it is non-empty, at most `max_len`
+/// characters and free of control characters. `field` names it in errors.
+pub fn validate_text(field: &str, value: &str, max_len: usize) -> Result<String, String> {
+    let value = value.trim();
+
+    if value.is_empty() {
+        return Err(format!("{} is required", field));
+    }
+    if value.chars().count() > max_len {
+        return Err(format!("{} must be at most {} characters", field, max_len));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(format!("{} contains invalid characters", field));
+    }
+
+    Ok(value.to_string())
+}